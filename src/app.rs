@@ -1,164 +1,276 @@
 use futures::stream::{self, StreamExt};
-use ron;
 
-use twitter_v2::{id::NumericId, Tweet, User};
+use twitter_v2::{Tweet, User};
 
+use archive::Archive;
+use progress::Event;
+use records::UserRecord;
+
+pub mod activitypub;
 pub mod api;
+pub mod archive;
+pub mod cache;
+pub mod check;
+pub mod convert;
+pub mod export;
+pub mod feed;
+pub mod format;
+pub mod graph;
 pub mod io;
+pub mod jobs;
+pub mod media;
+pub mod migrations;
+pub mod progress;
+pub mod records;
+pub mod search;
+pub mod site;
+pub mod stats;
+pub mod tombstones;
+pub mod watchlist;
+pub mod webhooks;
 
-pub async fn load_tweet_from_id(id: u64) -> Tweet {
-    match io::read::tweets_string_from_ron() {
-        Ok(tweets_string) => {
-            let tweets: Vec<Tweet> = ron::from_str(&tweets_string)
-                .expect("Failed to parse tweets from \"data/tweets.ron\"");
-            match tweets.iter().find(|tweet| tweet.id == id) {
-                Some(tweet) => {
-                    println!("Loading tweet {id} from \"data/tweets.ron\"");
-                    tweet.clone()
-                }
-                None => {
-                    println!("Tweet {id} not found in \"data/tweets.ron\"");
-                    println!("Loading tweet {id} from Twitter API");
-                    let tweet = api::get_tweet_by_id(id).await;
-                    let mut output: Vec<Tweet> = Vec::<Tweet>::new();
-                    output.push(tweet.clone());
-                    io::write::tweets_to_ron(&mut output);
-                    tweet
-                }
-            }
+pub async fn load_tweet_from_id(archive: &Archive, id: u64) -> Tweet {
+    match archive.tweet(id) {
+        Some(tweet) => {
+            println!("Loading tweet {id} from archive");
+            tweet
         }
-        Err(_error) => {
-            println!("File \"data/tweets.ron\" not found.");
+        None => {
+            println!("Tweet {id} not found in archive");
             println!("Loading tweet {id} from Twitter API");
             let tweet = api::get_tweet_by_id(id).await;
-            let mut tweets = Vec::<Tweet>::new();
-            tweets.push(tweet.clone());
-            io::write::tweets_to_ron(&mut tweets);
+            let added = archive.insert_tweets(std::slice::from_ref(&tweet));
+            stored(archive, None, &added).await;
             tweet
         }
     }
 }
 
-pub async fn load_user_from_twitter_handle(twitter_handle: &str) -> User {
-    match io::read::user_info_string_from_ron(twitter_handle) {
-        Ok(user) => {
-            println!(
-                "Loading user  @{twitter_handle} from \"data/user-info_{twitter_handle}.ron\""
-            );
-            ron::from_str(&user).expect(&format!(
-                "Failed to parse file \"data/user_{twitter_handle}.ron\""
-            ))
-        }
-        Err(_error) => {
+pub async fn load_user_from_twitter_handle(archive: &Archive, twitter_handle: &str) -> User {
+    match archive.user_from_twitter_handle(twitter_handle) {
+        Some(user) => {
+            println!("Loading user @{twitter_handle} from archive");
+            user
+        }
+        None => {
             println!("Loading User @{twitter_handle} from Twitter API");
             let user = api::get_user_by_twitter_handle(twitter_handle).await;
-            io::write::user_info_to_ron(&user, twitter_handle);
+            archive.insert_user_info(&user, twitter_handle);
             user
         }
     }
 }
 
-pub async fn load_user_from_id(id: u64) -> User {
-    match io::read::users_string_from_ron() {
-        Ok(users_ron_str) => {
-            println!("Loading user of id {id} from \"data/users.ron\"");
-            let users: Vec<User> = ron::from_str(&users_ron_str)
-                .expect(&format!("Failed to parse file \"data/users.ron\""));
-            if users.iter().any(|user| user.id.as_u64() == id) {
-                users
-                    .into_iter()
-                    .find(|user| user.id.as_u64() == id)
-                    .expect("Failed to get user from id")
-            } else {
-                println!("Loading User of id {id} from Twitter API");
-                let user = api::get_user_by_id(id).await;
-                io::write::user_to_users_ron(&user);
-                user
+//the user's profile from the Twitter API, replacing the archived one if it changed
+pub async fn refresh_user_from_twitter_handle(archive: &Archive, twitter_handle: &str) -> User {
+    println!("Refreshing user @{twitter_handle} from Twitter API");
+    let user = api::get_user_by_twitter_handle(twitter_handle).await;
+    match archive.user_from_twitter_handle(twitter_handle) {
+        Some(archived) => {
+            let (before, after) = (UserRecord::from(&archived), UserRecord::from(&user));
+            if before != after {
+                println!("@{twitter_handle} changed their profile");
+                archive.update_user_info(&user, twitter_handle);
+                webhooks::notify(
+                    archive,
+                    webhooks::Event::ProfileChanged {
+                        twitter_handle: twitter_handle.to_string(),
+                        before: Box::new(before),
+                        after: Box::new(after),
+                    },
+                );
             }
         }
-        Err(_error) => {
+        None => archive.insert_user_info(&user, twitter_handle),
+    }
+    user
+}
+
+pub async fn load_user_from_id(archive: &Archive, id: u64) -> User {
+    match archive.user(id) {
+        Some(user) => {
+            println!("Loading user of id {id} from archive");
+            user
+        }
+        None => {
             println!("Loading User of id {id} from Twitter API");
             let user = api::get_user_by_id(id).await;
-            io::write::user_to_users_ron(&user);
+            archive.insert_user(&user);
             user
         }
     }
 }
 
-pub async fn load_conversations_from_twitter_handle(twitter_handle: &str) -> Vec<Vec<Tweet>> {
-    match io::read::user_conversations_string_from_ron(twitter_handle) {
-        Ok(conversations_string) => {
-            println!("Loading @{twitter_handle}'s conversations from \"data/user-conversations_{twitter_handle}.ron\"");
-            ron::from_str(&conversations_string).expect(&format!(
-                "Failed to parse conversations from \"data/user-conversations_{twitter_handle}"
-            ))
-        }
-        Err(_error) => {
+pub async fn load_conversations_from_twitter_handle(
+    archive: &Archive,
+    twitter_handle: &str,
+) -> Vec<Vec<Tweet>> {
+    load_conversations_from_twitter_handle_with_progress(archive, twitter_handle, |_, _| {}).await
+}
+
+//the same, calling `progress` with how many of the user's tweets have had their conversation
+//loaded and how many there are in total
+pub async fn load_conversations_from_twitter_handle_with_progress(
+    archive: &Archive,
+    twitter_handle: &str,
+    progress: impl Fn(usize, usize),
+) -> Vec<Vec<Tweet>> {
+    match archive.user_conversations(twitter_handle) {
+        Some(conversations) => {
+            println!("Loading @{twitter_handle}'s conversations from archive");
+            progress(conversations.len(), conversations.len());
+            conversations
+        }
+        None => {
             println!("Loading @{twitter_handle}'s conversations from Twitter API");
-            let tweets = load_tweets_from_twitter_handle(twitter_handle).await;
-            let conversations_stream = stream::iter(tweets);
-            let conversations_then = conversations_stream
-                .then(|tweet| load_conversation_from_tweet_id(tweet.id.as_u64()));
+            let tweets = load_tweets_from_twitter_handle(archive, twitter_handle).await;
+            let total = tweets.len();
+            progress(0, total);
+            let conversations_stream = stream::iter(tweets.into_iter().enumerate());
+            let conversations_then = conversations_stream.then(|(loaded, tweet)| {
+                let progress = &progress;
+                async move {
+                    let tweet_id = tweet.id.as_u64();
+                    let conversation = load_conversation_from_tweet_id(archive, tweet_id).await;
+                    progress::emit(Event::ConversationResolved {
+                        tweet_id,
+                        tweets: conversation.len(),
+                        done: loaded + 1,
+                        total,
+                    });
+                    progress(loaded + 1, total);
+                    conversation
+                }
+            });
             let conversations = conversations_then.collect::<Vec<_>>().await;
-            io::write::user_conversations_to_ron(&conversations, twitter_handle);
+            archive.insert_user_conversations(&conversations, twitter_handle);
             conversations
         }
     }
 }
 
-pub async fn load_conversation_from_tweet_id(tweet_id: u64) -> Vec<Tweet> {
-    match io::read::conversations_string_from_ron() {
-        Ok(conversations_ron) => {
-            let conversations: Vec<Vec<Tweet>> = ron::from_str(&conversations_ron)
-                .expect("Failed to parse conversations from \"data/conversations.ron\"");
-            if conversations
-                .iter()
-                .any(|conversation| conversation[conversation.len() - 1].id.as_u64() == tweet_id)
-            {
-                println!("Loading conversation {tweet_id} from \"data/conversations.ron\"");
-                conversations
-                    .into_iter()
-                    .filter(|conversation| {
-                        conversation[conversation.len() - 1].id.as_u64() == tweet_id
-                    })
-                    .next()
-                    .expect("Failed to get conversation")
-            } else {
-                println!("Loading conversation {tweet_id} from Twitter API");
-                let mut conversation =
-                    api::get_twitter_conversation_from_tweet(load_tweet_from_id(tweet_id).await)
-                        .await;
-                io::write::conversation_to_ron(&mut conversation);
-                conversation
-            }
+pub async fn load_conversation_from_tweet_id(archive: &Archive, tweet_id: u64) -> Vec<Tweet> {
+    match archive.conversation(tweet_id) {
+        Some(conversation) => {
+            println!("Loading conversation {tweet_id} from archive");
+            conversation
         }
-        Err(_error) => {
+        None => {
             println!("Loading conversation {tweet_id} from Twitter API");
-            let mut conversation =
-                api::get_twitter_conversation_from_tweet(load_tweet_from_id(tweet_id).await).await;
-            io::write::conversation_to_ron(&mut conversation);
+            let conversation = api::get_twitter_conversation_from_tweet(
+                archive,
+                load_tweet_from_id(archive, tweet_id).await,
+            )
+            .await;
+            let added = archive.insert_conversation(&conversation);
+            stored(archive, None, &added).await;
             conversation
         }
     }
 }
 
-pub async fn load_tweets_from_twitter_handle(twitter_handle: &str) -> Vec<Tweet> {
-    match io::read::user_tweets_string_from_ron(twitter_handle) {
-        Ok(tweets) => {
-            println!(
-                "Loading @{twitter_handle}'s tweets from \"data/user-tweets_{twitter_handle}.ron\""
-            );
-            ron::from_str(&tweets)
-                .expect("Failed to load tweets from \"data/user-tweets_{twitter_handle}\"")
-        }
-        Err(_error) => {
+pub async fn load_tweets_from_twitter_handle(
+    archive: &Archive,
+    twitter_handle: &str,
+) -> Vec<Tweet> {
+    match archive.user_tweets(twitter_handle) {
+        Some(tweets) => {
+            println!("Loading @{twitter_handle}'s tweets from archive");
+            tweets
+        }
+        None => {
             println!("Loading @{twitter_handle}'s tweets from Twitter API");
-            let mut tweets =
-                api::get_all_tweets_from_user(&load_user_from_twitter_handle(twitter_handle).await)
-                    .await;
-            io::write::user_tweets_to_ron(&tweets, twitter_handle);
-            io::write::tweets_to_ron(&mut tweets);
+            let tweets = api::get_all_tweets_from_user(
+                &load_user_from_twitter_handle(archive, twitter_handle).await,
+            )
+            .await;
+            let added = archive.insert_user_tweets(&tweets, twitter_handle);
+            stored(archive, Some(twitter_handle), &added).await;
             tweets
         }
     }
 }
+
+// Archives the user's tweets newer than the newest one archived and their conversations, and
+// returns how many new tweets there were. A user with nothing archived is loaded in full.
+// Their profile is refreshed and every archived tweet of theirs is checked for deletions too.
+// `progress` is called like in load_conversations_from_twitter_handle_with_progress.
+pub async fn sync_user(
+    archive: &Archive,
+    twitter_handle: &str,
+    progress: impl Fn(usize, usize),
+) -> usize {
+    let archived = match archive.user_tweets(twitter_handle) {
+        Some(tweets) => tweets,
+        None => {
+            let tweets = load_tweets_from_twitter_handle(archive, twitter_handle).await;
+            load_conversations_from_twitter_handle_with_progress(archive, twitter_handle, progress)
+                .await;
+            return tweets.len();
+        }
+    };
+    let user = refresh_user_from_twitter_handle(archive, twitter_handle).await;
+    for tombstone in tombstones::check(archive, twitter_handle, &archived).await {
+        webhooks::notify(archive, webhooks::Event::TombstoneRecorded { tombstone });
+    }
+    let new_tweets = match archived.iter().map(|tweet| tweet.id.as_u64()).max() {
+        Some(newest) => {
+            println!("Loading @{twitter_handle}'s tweets since {newest} from Twitter API");
+            api::get_tweets_from_user_since_id(&user, newest).await
+        }
+        None => api::get_all_tweets_from_user(&user).await,
+    };
+    println!("@{twitter_handle} has {} new tweets", new_tweets.len());
+    if new_tweets.is_empty() {
+        return 0;
+    }
+    let tweets: Vec<Tweet> = new_tweets.iter().cloned().chain(archived).collect();
+    let added = archive.insert_user_tweets(&tweets, twitter_handle);
+    stored(archive, Some(twitter_handle), &added).await;
+    match archive.user_conversations(twitter_handle) {
+        Some(archived_conversations) => {
+            let total = new_tweets.len();
+            let mut conversations = Vec::new();
+            for (loaded, tweet) in new_tweets.iter().enumerate() {
+                let tweet_id = tweet.id.as_u64();
+                let conversation = load_conversation_from_tweet_id(archive, tweet_id).await;
+                progress::emit(Event::ConversationResolved {
+                    tweet_id,
+                    tweets: conversation.len(),
+                    done: loaded + 1,
+                    total,
+                });
+                progress(loaded + 1, total);
+                conversations.push(conversation);
+            }
+            //newest first, like the tweets they come from
+            conversations.extend(archived_conversations);
+            archive.insert_user_conversations(&conversations, twitter_handle);
+        }
+        None => {
+            load_conversations_from_twitter_handle_with_progress(archive, twitter_handle, progress)
+                .await;
+        }
+    }
+    new_tweets.len()
+}
+
+// Downloads the media of tweets just added to the archive, then tells the progress listeners and
+// the webhooks about them. Only the tweets that weren't archived before are passed in, so each
+// tweet is reported once however many timelines and conversations it's loaded from.
+async fn stored(archive: &Archive, twitter_handle: Option<&str>, tweets: &[Tweet]) {
+    if tweets.is_empty() {
+        return;
+    }
+    media::download(archive, tweets).await;
+    progress::emit(Event::TweetsStored {
+        count: tweets.len(),
+    });
+    webhooks::notify(
+        archive,
+        webhooks::Event::TweetsStored {
+            twitter_handle: twitter_handle.map(|twitter_handle| twitter_handle.to_string()),
+            tweets: records::tweets_to_records(tweets),
+        },
+    );
+}
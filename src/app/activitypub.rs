@@ -0,0 +1,746 @@
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use reqwest::{Method, Url};
+use rocket::request::{FromRequest, Outcome, Request};
+use rsa::pkcs1v15::{Signature, SigningKey, VerifyingKey};
+use rsa::pkcs8::{
+    DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey, LineEnding,
+};
+use rsa::signature::{SignatureEncoding, Signer, Verifier};
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use twitter_v2::Tweet;
+
+use super::archive::Archive;
+use super::cache::Cache;
+use super::feed::text_html;
+use super::format::date;
+use super::io;
+use super::site::Site;
+use super::stats;
+
+// Every archived user is published as a read-only ActivityPub actor, so Mastodon users can find
+// them as @<twitter handle>@<host of public_url>, follow them and browse their history:
+//
+//     /.well-known/webfinger?resource=acct:<handle>@<host>   finds the actor
+//     /users/<handle>                                        the actor, a Person
+//     /users/<handle>/outbox                                 their archived tweets as Notes
+//     /users/<handle>/statuses/<tweet id>                    one of those Notes
+//     /users/<handle>/followers                              how many follow them
+//     /users/<handle>/inbox                                  takes follows and unfollows
+//
+// Nothing is pushed to followers as the archive grows, the outbox is the whole story.
+//
+// The servers followers are on are only requested at public addresses, unless
+// `activitypub_private_addresses` is set in Rocket.toml to try it against a local stand-in.
+
+pub const CONTENT_TYPE: &str = "application/activity+json";
+
+const CONTEXT: &str = "https://www.w3.org/ns/activitystreams";
+const SECURITY_CONTEXT: &str = "https://w3id.org/security/v1";
+const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+
+//notes per outbox page
+const PAGE_SIZE: usize = 20;
+
+const KEY_BITS: usize = 2048;
+
+//how far the date of a signed inbox request may be from now, as much as Mastodon allows
+const SIGNATURE_MAX_AGE: Duration = Duration::from_secs(12 * 60 * 60);
+
+//each archive's signing key, once it's been read or generated, held while one is being generated
+static KEYS: tokio::sync::Mutex<BTreeMap<PathBuf, RsaPrivateKey>> =
+    tokio::sync::Mutex::const_new(BTreeMap::new());
+
+//followers.ron is read, changed and written back while holding this
+static FOLLOWERS: Mutex<()> = Mutex::new(());
+
+//twitter handle (lowercase) -> follower actor id -> the inbox their actor document gave
+type Followers = BTreeMap<String, BTreeMap<String, String>>;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ActivityPubConfig {
+    //lets actors and inboxes be on loopback and private addresses, for testing only
+    #[serde(default)]
+    pub activitypub_private_addresses: bool,
+}
+
+static CONFIG: OnceLock<ActivityPubConfig> = OnceLock::new();
+
+//only the first call has any effect, until then only public addresses are requested
+pub fn configure(config: ActivityPubConfig) {
+    if config.activitypub_private_addresses {
+        println!("Requesting activitypub servers at private addresses too, only do this to test");
+    }
+    CONFIG.get_or_init(|| config);
+}
+
+fn private_addresses_allowed() -> bool {
+    CONFIG
+        .get()
+        .is_some_and(|config| config.activitypub_private_addresses)
+}
+
+fn user_url(archive: &Archive, site: &Site, twitter_handle: &str, path: &str) -> String {
+    site.archive_url(archive, &format!("/users/{twitter_handle}{path}"))
+}
+
+fn status_url(archive: &Archive, site: &Site, twitter_handle: &str, id: u64) -> String {
+    user_url(archive, site, twitter_handle, &format!("/statuses/{id}"))
+}
+
+//the host accounts are under, "example.com" in "acct:someone@example.com"
+fn host(site: &Site) -> String {
+    let url = Url::parse(&site.public_url).expect("public_url should be an absolute url");
+    let host = url.host_str().unwrap_or("localhost");
+    match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    }
+}
+
+fn read_or_generate_key(path: &Path) -> Result<RsaPrivateKey, String> {
+    let file = path.display();
+    match std::fs::read_to_string(path) {
+        Ok(pem) => RsaPrivateKey::from_pkcs8_pem(&pem)
+            .map_err(|error| format!("Failed to read the activitypub key \"{file}\": {error}")),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            println!("Generating the activitypub key \"{file}\"");
+            let key = RsaPrivateKey::new(&mut rand::thread_rng(), KEY_BITS)
+                .map_err(|error| format!("Failed to generate the activitypub key: {error}"))?;
+            let pem = key
+                .to_pkcs8_pem(LineEnding::LF)
+                .map_err(|error| format!("Failed to encode the activitypub key: {error}"))?;
+            io::write::string_to_file(&pem, path);
+            Ok(key)
+        }
+        Err(error) => Err(format!(
+            "Failed to read the activitypub key \"{file}\": {error}"
+        )),
+    }
+}
+
+// The archive's key, from its file or generated into it the first time it's needed. Generating
+// one takes seconds, so it's done off the async workers.
+async fn key(archive: &Archive) -> Result<RsaPrivateKey, String> {
+    let path = archive.layout.activitypub_key();
+    let mut keys = KEYS.lock().await;
+    if let Some(key) = keys.get(&path) {
+        return Ok(key.clone());
+    }
+    let key = tokio::task::spawn_blocking({
+        let path = path.clone();
+        move || read_or_generate_key(&path)
+    })
+    .await
+    .map_err(|error| format!("Failed to load the activitypub key: {error}"))??;
+    keys.insert(path, key.clone());
+    Ok(key)
+}
+
+async fn public_key_pem(archive: &Archive) -> Result<String, String> {
+    key(archive)
+        .await?
+        .to_public_key()
+        .to_public_key_pem(LineEnding::LF)
+        .map_err(|error| format!("Failed to encode the activitypub public key: {error}"))
+}
+
+//accepts "acct:<handle>@<host>" (with or without "acct:") or the actor's url
+pub fn webfinger(archive: &Archive, site: &Site, resource: &str) -> Option<Value> {
+    let host = host(site);
+    let twitter_handle = match resource.strip_prefix(&site.url("/users/")) {
+        Some(rest) => rest.split(['/', '?']).next()?,
+        None => {
+            let account = resource.strip_prefix("acct:").unwrap_or(resource);
+            let (twitter_handle, domain) = account.trim_start_matches('@').rsplit_once('@')?;
+            if !domain.eq_ignore_ascii_case(&host) {
+                return None;
+            }
+            twitter_handle
+        }
+    };
+    let cache = archive.read();
+    let handle = &cache.user_from_twitter_handle(twitter_handle)?.username;
+    let actor = user_url(archive, site, handle, "");
+    Some(json!({
+        "subject": format!("acct:{handle}@{host}"),
+        "aliases": [actor],
+        "links": [
+            {
+                "rel": "self",
+                "type": CONTENT_TYPE,
+                "href": actor,
+            },
+            {
+                "rel": "http://webfinger.net/rel/profile-page",
+                "type": "text/html",
+                "href": format!("https://twitter.com/{handle}"),
+            },
+        ],
+    }))
+}
+
+//None if the user isn't archived
+pub async fn actor(
+    archive: &Archive,
+    site: &Site,
+    twitter_handle: &str,
+) -> Result<Option<Value>, String> {
+    let user = match archive.user_from_twitter_handle(twitter_handle) {
+        Some(user) => user,
+        None => return Ok(None),
+    };
+    let handle = &user.username;
+    let id = user_url(archive, site, handle, "");
+    let mut actor = json!({
+        "@context": [CONTEXT, SECURITY_CONTEXT],
+        "id": id,
+        "type": "Person",
+        "preferredUsername": handle,
+        "name": user.name,
+        "summary": user.description.as_deref().map(text_html).unwrap_or_default(),
+        "url": format!("https://twitter.com/{handle}"),
+        "inbox": user_url(archive, site, handle, "/inbox"),
+        "outbox": user_url(archive, site, handle, "/outbox"),
+        "followers": user_url(archive, site, handle, "/followers"),
+        "manuallyApprovesFollowers": false,
+        "discoverable": true,
+        "publicKey": {
+            "id": format!("{id}#main-key"),
+            "owner": id,
+            "publicKeyPem": public_key_pem(archive).await?,
+        },
+    });
+    if let Some(published) = date(user.created_at) {
+        actor["published"] = json!(published);
+    }
+    if let Some(image) = user.profile_image_url {
+        actor["icon"] = json!({ "type": "Image", "url": image.to_string() });
+    }
+    Ok(Some(actor))
+}
+
+// The tweet's parent as a local status if its author is archived too, so Mastodon can fetch
+// the thread, otherwise as a link to twitter.
+fn in_reply_to(archive: &Archive, site: &Site, tweet: &Tweet, cache: &Cache) -> Option<String> {
+    let parent_id = Cache::replied_to_id(tweet)?;
+    let archived_author = cache
+        .tweet(parent_id)
+        .and_then(|parent| parent.author_id)
+        .and_then(|author_id| cache.user(author_id.as_u64()));
+    Some(match archived_author {
+        Some(author) => status_url(archive, site, &author.username, parent_id),
+        None => format!("https://twitter.com/i/web/status/{parent_id}"),
+    })
+}
+
+pub fn note(
+    archive: &Archive,
+    site: &Site,
+    twitter_handle: &str,
+    tweet: &Tweet,
+    cache: &Cache,
+) -> Value {
+    let id = tweet.id.as_u64();
+    json!({
+        "id": status_url(archive, site, twitter_handle, id),
+        "type": "Note",
+        "attributedTo": user_url(archive, site, twitter_handle, ""),
+        "content": format!("<p>{}</p>", text_html(&tweet.text)),
+        "published": date(tweet.created_at),
+        "url": format!("https://twitter.com/{twitter_handle}/status/{id}"),
+        "to": [PUBLIC],
+        "cc": [user_url(archive, site, twitter_handle, "/followers")],
+        "inReplyTo": in_reply_to(archive, site, tweet, cache),
+        "sensitive": false,
+    })
+}
+
+//the activity that publishes a tweet's note
+pub fn create(
+    archive: &Archive,
+    site: &Site,
+    twitter_handle: &str,
+    tweet: &Tweet,
+    note: Value,
+) -> Value {
+    json!({
+        "id": user_url(archive, site, twitter_handle, &format!("/statuses/{}/activity", tweet.id)),
+        "type": "Create",
+        "actor": note["attributedTo"],
+        "published": note["published"],
+        "to": note["to"],
+        "cc": note["cc"],
+        "object": note,
+    })
+}
+
+pub fn status(archive: &Archive, site: &Site, twitter_handle: &str, id: u64) -> Option<Value> {
+    let cache = archive.read();
+    let user = cache.user_from_twitter_handle(twitter_handle)?;
+    let tweet = stats::user_tweets(&cache, user, twitter_handle)
+        .into_iter()
+        .find(|tweet| tweet.id.as_u64() == id)?;
+    let mut note = note(archive, site, &user.username, tweet, &cache);
+    note["@context"] = json!(CONTEXT);
+    Some(note)
+}
+
+// Without a page, the collection with a link to its first page. Pages are numbered from 1,
+// newest tweets first, and None past the last one.
+pub fn outbox(
+    archive: &Archive,
+    site: &Site,
+    twitter_handle: &str,
+    page: Option<usize>,
+) -> Option<Value> {
+    let cache = archive.read();
+    let user = cache.user_from_twitter_handle(twitter_handle)?;
+    let handle = &user.username;
+    let mut tweets = stats::user_tweets(&cache, user, twitter_handle);
+    tweets.sort_by_key(|tweet| Reverse(tweet.id.as_u64()));
+    let outbox = user_url(archive, site, handle, "/outbox");
+    let pages = tweets.len().div_ceil(PAGE_SIZE).max(1);
+    let page_url = |page: usize| user_url(archive, site, handle, &format!("/outbox?page={page}"));
+    match page {
+        None => Some(json!({
+            "@context": CONTEXT,
+            "id": outbox,
+            "type": "OrderedCollection",
+            "totalItems": tweets.len(),
+            "first": page_url(1),
+            "last": page_url(pages),
+        })),
+        Some(page) if page == 0 || page > pages => None,
+        Some(page) => {
+            let items: Vec<Value> = tweets
+                .iter()
+                .skip((page - 1) * PAGE_SIZE)
+                .take(PAGE_SIZE)
+                .map(|tweet| {
+                    let note = note(archive, site, handle, tweet, &cache);
+                    create(archive, site, handle, tweet, note)
+                })
+                .collect();
+            let mut collection_page = json!({
+                "@context": CONTEXT,
+                "id": page_url(page),
+                "type": "OrderedCollectionPage",
+                "partOf": outbox,
+                "totalItems": tweets.len(),
+                "orderedItems": items,
+            });
+            if page < pages {
+                collection_page["next"] = json!(page_url(page + 1));
+            }
+            if page > 1 {
+                collection_page["prev"] = json!(page_url(page - 1));
+            }
+            Some(collection_page)
+        }
+    }
+}
+
+//only the count, who follows an archived account isn't published
+pub fn followers(archive: &Archive, site: &Site, twitter_handle: &str) -> Option<Value> {
+    let handle = archive.user_from_twitter_handle(twitter_handle)?.username;
+    let count = read_followers(archive)
+        .get(&handle.to_lowercase())
+        .map_or(0, |followers| followers.len());
+    Some(json!({
+        "@context": CONTEXT,
+        "id": user_url(archive, site, &handle, "/followers"),
+        "type": "OrderedCollection",
+        "totalItems": count,
+    }))
+}
+
+fn read_followers(archive: &Archive) -> Followers {
+    match io::read::string_from_ron(&archive.layout.followers()) {
+        Ok(followers) => ron::from_str(&followers).expect("Failed to parse followers from ron"),
+        Err(_) => Followers::new(),
+    }
+}
+
+fn update_followers(archive: &Archive, update: impl FnOnce(&mut Followers)) {
+    let _lock = FOLLOWERS.lock().expect("The followers lock was poisoned");
+    let mut followers = read_followers(archive);
+    update(&mut followers);
+    io::write::value_to_ron(&followers, &archive.layout.followers());
+}
+
+// A request signed with the archive's key, the way Mastodon expects: an HTTP signature over
+// the request target, host and date, plus the digest and content type of a body.
+async fn signed_request(
+    archive: &Archive,
+    site: &Site,
+    twitter_handle: &str,
+    method: Method,
+    remote: &Remote,
+    body: Option<String>,
+) -> Result<reqwest::Response, String> {
+    let url = &remote.url;
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{host}:{port}"),
+        (Some(host), None) => host.to_string(),
+        (None, _) => return Err(format!("No host in \"{url}\"")),
+    };
+    let target = match url.query() {
+        Some(query) => format!("{}?{query}", url.path()),
+        None => url.path().to_string(),
+    };
+    let mut headers = vec![
+        (
+            "(request-target)",
+            format!("{} {target}", method.as_str().to_lowercase()),
+        ),
+        ("host", host),
+        ("date", httpdate::fmt_http_date(SystemTime::now())),
+    ];
+    if let Some(body) = &body {
+        let digest = BASE64.encode(Sha256::digest(body.as_bytes()));
+        headers.push(("digest", format!("SHA-256={digest}")));
+        headers.push(("content-type", CONTENT_TYPE.to_string()));
+    }
+    let signature =
+        SigningKey::<Sha256>::new(key(archive).await?).sign(signing_string(&headers).as_bytes());
+    let names: Vec<&str> = headers.iter().map(|(name, _)| *name).collect();
+    let signature = format!(
+        "keyId=\"{}#main-key\",algorithm=\"rsa-sha256\",headers=\"{}\",signature=\"{}\"",
+        user_url(archive, site, twitter_handle, ""),
+        names.join(" "),
+        BASE64.encode(signature.to_bytes())
+    );
+    //the host is pinned to the address it was checked at, so it can't resolve elsewhere by now,
+    //and redirects aren't followed as where they lead wasn't checked
+    let mut client = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none());
+    if let (Some(domain), Some(&address)) = (url.domain(), remote.addresses.first()) {
+        client = client.resolve(domain, address);
+    }
+    let mut request = client
+        .build()
+        .map_err(|error| format!("Failed to make a client for \"{url}\": {error}"))?
+        .request(method, url.clone())
+        .header("accept", CONTENT_TYPE)
+        .header("signature", signature);
+    for (name, value) in headers.into_iter().skip(1) {
+        request = request.header(name, value);
+    }
+    if let Some(body) = body {
+        request = request.body(body);
+    }
+    request
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|error| format!("Request to \"{url}\" failed: {error}"))
+}
+
+fn signing_string<N: AsRef<str>>(headers: &[(N, String)]) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| format!("{}: {value}", name.as_ref()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn digest(body: &str) -> String {
+    format!("SHA-256={}", BASE64.encode(Sha256::digest(body.as_bytes())))
+}
+
+//what's needed of a request to an inbox to check its signature
+#[derive(Debug, Clone)]
+pub struct InboxRequest {
+    //"post /users/<handle>/inbox", as "(request-target)" is signed
+    pub target: String,
+    //lowercase names
+    pub headers: BTreeMap<String, String>,
+}
+
+impl InboxRequest {
+    pub fn is_signed(&self) -> bool {
+        self.headers.contains_key("signature")
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for InboxRequest {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let headers = request
+            .headers()
+            .iter()
+            .map(|header| {
+                (
+                    header.name().as_str().to_lowercase(),
+                    header.value().to_string(),
+                )
+            })
+            .collect();
+        Outcome::Success(InboxRequest {
+            target: format!(
+                "{} {}",
+                request.method().as_str().to_lowercase(),
+                request.uri()
+            ),
+            headers,
+        })
+    }
+}
+
+//the parts of a Signature header, keyed by name, e.g. "keyId" and "headers"
+fn signature_parameters(header: &str) -> BTreeMap<&str, &str> {
+    header
+        .split(',')
+        .filter_map(|parameter| {
+            let (name, value) = parameter.split_once('=')?;
+            Some((name.trim(), value.trim().trim_matches('"')))
+        })
+        .collect()
+}
+
+// Checks that the request was signed with the public key and that the signature covers its
+// target, host, date and body. Returns the keyId the signature names, for the caller to check it
+// is the key it was verified with.
+pub fn verify_signature(
+    request: &InboxRequest,
+    body: &str,
+    public_key_pem: &str,
+) -> Result<String, String> {
+    let header = request
+        .headers
+        .get("signature")
+        .ok_or("The request isn't signed")?;
+    let parameters = signature_parameters(header);
+    let key_id = parameters
+        .get("keyId")
+        .ok_or("The signature has no keyId")?;
+    let names: Vec<&str> = parameters
+        .get("headers")
+        .unwrap_or(&"date")
+        .split_whitespace()
+        .collect();
+    for required in ["(request-target)", "host", "date", "digest"] {
+        if !names.contains(&required) {
+            return Err(format!("The signature doesn't cover \"{required}\""));
+        }
+    }
+    if request.headers.get("digest") != Some(&digest(body)) {
+        return Err("The digest doesn't match the body".to_string());
+    }
+    let date = request
+        .headers
+        .get("date")
+        .and_then(|date| httpdate::parse_http_date(date).ok())
+        .ok_or("The request has no valid date")?;
+    let age = SystemTime::now()
+        .duration_since(date)
+        .unwrap_or_else(|error| error.duration());
+    if age > SIGNATURE_MAX_AGE {
+        return Err("The request's date is too far from now".to_string());
+    }
+    let headers: Vec<(&str, String)> = names
+        .iter()
+        .map(|&name| match name {
+            "(request-target)" => Ok((name, request.target.clone())),
+            _ => request
+                .headers
+                .get(name)
+                .map(|value| (name, value.clone()))
+                .ok_or_else(|| format!("The signed header \"{name}\" is missing")),
+        })
+        .collect::<Result<_, _>>()?;
+    let signature = parameters
+        .get("signature")
+        .and_then(|signature| BASE64.decode(signature).ok())
+        .and_then(|signature| Signature::try_from(signature.as_slice()).ok())
+        .ok_or("The signature isn't valid base64")?;
+    let public_key = RsaPublicKey::from_public_key_pem(public_key_pem)
+        .map_err(|error| format!("Invalid public key: {error}"))?;
+    VerifyingKey::<Sha256>::new(public_key)
+        .verify(signing_string(&headers).as_bytes(), &signature)
+        .map_err(|_| "The signature doesn't match".to_string())?;
+    Ok(key_id.to_string())
+}
+
+//somewhere on the internet, not the archiver's own machine or network
+pub fn is_public_address(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => {
+            let [first, second, ..] = address.octets();
+            !(address.is_loopback()
+                || address.is_private()
+                || address.is_link_local()
+                || address.is_unspecified()
+                || address.is_broadcast()
+                || address.is_multicast()
+                || address.is_documentation()
+                //shared address space, 100.64.0.0/10
+                || (first == 100 && second & 0xc0 == 64)
+                || first == 0)
+        }
+        IpAddr::V6(address) => {
+            let first = address.segments()[0];
+            let mapped = address.to_ipv4_mapped();
+            !(address.is_loopback()
+                || address.is_unspecified()
+                || address.is_multicast()
+                //unique local fc00::/7 and link local fe80::/10
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80)
+                && mapped.is_none_or(|mapped| is_public_address(IpAddr::V4(mapped)))
+        }
+    }
+}
+
+//a url from an activity, with the addresses its host resolved to when it was checked
+struct Remote {
+    url: Url,
+    addresses: Vec<SocketAddr>,
+}
+
+// The actor ids, key ids and inboxes in an activity come from whoever posted it, so before any
+// of them is requested it has to be an http(s) url whose host only resolves to public addresses.
+async fn check_remote(url: &str) -> Result<Remote, String> {
+    let url = Url::parse(url).map_err(|error| format!("Invalid url \"{url}\": {error}"))?;
+    if !["https", "http"].contains(&url.scheme()) {
+        return Err(format!("\"{url}\" isn't an http url"));
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| format!("No host in \"{url}\""))?;
+    let port = url.port_or_known_default().unwrap_or(443);
+    let addresses: Vec<SocketAddr> = match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+        Ok(address) => vec![SocketAddr::new(address, port)],
+        Err(_) => tokio::net::lookup_host((host, port))
+            .await
+            .map_err(|error| format!("Failed to resolve \"{host}\": {error}"))?
+            .collect(),
+    };
+    if addresses.is_empty() {
+        return Err(format!("\"{host}\" has no addresses"));
+    }
+    if !private_addresses_allowed()
+        && !addresses
+            .iter()
+            .all(|address| is_public_address(address.ip()))
+    {
+        return Err(format!("\"{url}\" isn't a public address"));
+    }
+    Ok(Remote { url, addresses })
+}
+
+fn same_host(a: &Url, b: &Url) -> bool {
+    a.host_str() == b.host_str() && a.port_or_known_default() == b.port_or_known_default()
+}
+
+// Handles an activity posted to an archived user's inbox. Follows are accepted and undone follows
+// forgotten, anything else is ignored since the actors are read-only.
+//
+// The request has to be signed by the activity's actor: their actor document is fetched, from a
+// public address only, and the signature checked against the public key it lists. The Accept of
+// a follow only ever goes to the actor's inbox on the actor's own host.
+pub async fn receive(
+    archive: &Archive,
+    site: &Site,
+    twitter_handle: &str,
+    request: &InboxRequest,
+    body: &str,
+) -> Result<(), String> {
+    let handle = archive
+        .user_from_twitter_handle(twitter_handle)
+        .ok_or_else(|| format!("@{twitter_handle} is not in the archive"))?
+        .username;
+    let activity: Value =
+        serde_json::from_str(body).map_err(|error| format!("Invalid activity: {error}"))?;
+    let follower = activity["actor"]
+        .as_str()
+        .ok_or("The activity has no actor")?
+        .to_string();
+    let actor = check_remote(&follower).await?;
+    let document: Value = signed_request(archive, site, &handle, Method::GET, &actor, None)
+        .await?
+        .json()
+        .await
+        .map_err(|error| format!("Failed to read the actor \"{follower}\": {error}"))?;
+    if document["id"].as_str() != Some(follower.as_str()) {
+        return Err(format!("The actor \"{follower}\" has a different id"));
+    }
+    let public_key = &document["publicKey"];
+    if public_key["owner"].as_str() != Some(follower.as_str()) {
+        return Err(format!(
+            "The actor \"{follower}\" has no public key of its own"
+        ));
+    }
+    let key_id = verify_signature(
+        request,
+        body,
+        public_key["publicKeyPem"]
+            .as_str()
+            .ok_or_else(|| format!("The actor \"{follower}\" has no public key"))?,
+    )?;
+    if public_key["id"].as_str() != Some(key_id.as_str()) {
+        return Err(format!(
+            "The request wasn't signed with \"{follower}\"'s key"
+        ));
+    }
+    match activity["type"].as_str() {
+        Some("Follow") => {
+            let inbox = document["inbox"]
+                .as_str()
+                .ok_or_else(|| format!("The actor \"{follower}\" has no inbox"))?;
+            let inbox_remote = check_remote(inbox).await?;
+            if !same_host(&inbox_remote.url, &actor.url) {
+                return Err(format!(
+                    "The inbox \"{inbox}\" isn't on the same host as the actor \"{follower}\""
+                ));
+            }
+            let inbox = inbox.to_string();
+            update_followers(archive, |followers| {
+                followers
+                    .entry(handle.to_lowercase())
+                    .or_default()
+                    .insert(follower.clone(), inbox.clone());
+            });
+            let archived_actor = user_url(archive, site, &handle, "");
+            let accept = json!({
+                "@context": CONTEXT,
+                "id": format!("{archived_actor}#accepts/{:016x}", rand::random::<u64>()),
+                "type": "Accept",
+                "actor": archived_actor,
+                "object": activity,
+            });
+            signed_request(
+                archive,
+                site,
+                &handle,
+                Method::POST,
+                &inbox_remote,
+                Some(accept.to_string()),
+            )
+            .await?;
+            println!("@{handle} was followed by {follower}");
+        }
+        Some("Undo") if activity["object"]["type"] == "Follow" => {
+            update_followers(archive, |followers| {
+                if let Some(followers) = followers.get_mut(&handle.to_lowercase()) {
+                    followers.remove(&follower);
+                }
+            });
+            println!("@{handle} was unfollowed by {follower}");
+        }
+        _ => {}
+    }
+    Ok(())
+}
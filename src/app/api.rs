@@ -1,35 +1,307 @@
-use twitter_v2::authorization::BearerToken;
-use twitter_v2::data::ReferencedTweetKind::RepliedTo;
-use twitter_v2::id::NumericId;
-use twitter_v2::query::{TweetField, UserField};
-use twitter_v2::{Tweet, TwitterApi, User};
+use twitter_v2::{Tweet, User};
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use async_recursion::async_recursion;
+use reqwest::{StatusCode, Url};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use super::archive::Archive;
+use super::cache::Cache;
+use super::progress::{self, Event};
+use cassette::Cassette;
+use tokens::Pool;
+
+pub mod cassette;
+pub mod tokens;
+
+// Requests to the Twitter API v2. The responses are read into twitter_v2's types, but the
+// requests are sent here so the API can be reached somewhere else than api.twitter.com, like the
+// mock server the tests run against, so they can be recorded to and replayed from a cassette, and
+// so they can be shared between several bearer tokens.
+
+//twitter's rate limits reset every 15 minutes
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+//how many pages of 100 tweets are loaded from a user's timeline, twitter stops at 3200 tweets
+const TIMELINE_PAGES: usize = 32;
+
+const TWEET_FIELDS: &str = "attachments,referenced_tweets,author_id,conversation_id,created_at,\
+entities,public_metrics,lang,in_reply_to_user_id";
+const USER_FIELDS: &str = "username,description";
+const MEDIA_FIELDS: &str = "type,url,preview_image_url,variants";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiConfig {
+    //e.g. "http://localhost:9000/2/" for `mock-twitter`
+    #[serde(default = "default_api_base_url")]
+    pub api_base_url: String,
+    //every response is recorded to this cassette, e.g. to reproduce a bug report later
+    #[serde(default)]
+    pub record_cassette: Option<PathBuf>,
+    //responses are played back from this cassette instead of asking the API
+    #[serde(default)]
+    pub replay_cassette: Option<PathBuf>,
+    //more bearer tokens to share the requests between, besides TWITTER_DEV_BEARER_TOKEN
+    #[serde(default)]
+    pub bearer_tokens: Vec<String>,
+    //a file of bearer tokens, one per line, to keep them out of Rocket.toml
+    #[serde(default)]
+    pub bearer_tokens_file: Option<PathBuf>,
+}
+
+fn default_api_base_url() -> String {
+    "https://api.twitter.com/2/".to_string()
+}
+
+impl Default for ApiConfig {
+    fn default() -> ApiConfig {
+        ApiConfig {
+            api_base_url: default_api_base_url(),
+            record_cassette: None,
+            replay_cassette: None,
+            bearer_tokens: Vec::new(),
+            bearer_tokens_file: None,
+        }
+    }
+}
+
+static BASE_URL: OnceLock<Url> = OnceLock::new();
+
+//only the first call has any effect, the API is at api.twitter.com until then
+pub fn configure(config: &ApiConfig) {
+    BASE_URL.get_or_init(|| parse_base_url(&config.api_base_url));
+    tokens::configure(Pool::from_config(config));
+    match (&config.record_cassette, &config.replay_cassette) {
+        (Some(_), Some(_)) => panic!("A cassette can't be recorded and replayed at the same time"),
+        (Some(record), None) => cassette::configure(Cassette::record(record)),
+        (None, Some(replay)) => cassette::configure(Cassette::replay(replay)),
+        (None, None) => {}
+    }
+}
+
+fn parse_base_url(base_url: &str) -> Url {
+    //without the trailing slash the last segment would be replaced when joining paths onto it
+    let base_url = match base_url.ends_with('/') {
+        true => base_url.to_string(),
+        false => format!("{base_url}/"),
+    };
+    Url::parse(&base_url)
+        .unwrap_or_else(|_| panic!("\"{base_url}\" isn't a valid Twitter API base URL"))
+}
+
+fn base_url() -> &'static Url {
+    BASE_URL.get_or_init(|| parse_base_url(&default_api_base_url()))
+}
+
+#[derive(Debug)]
+pub enum Error {
+    //429, with how many seconds are left until the rate limit resets if twitter said
+    RateLimited(Option<u64>),
+    Status(StatusCode, String),
+    Request(reqwest::Error),
+    Json(serde_json::Error),
+    //replaying a cassette that doesn't have the request
+    NotRecorded(String, PathBuf),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::RateLimited(_) => write!(f, "Rate limited"),
+            Error::Status(status, body) => write!(f, "{status}: {body}"),
+            Error::Request(error) => write!(f, "{error}"),
+            Error::Json(error) => write!(f, "{error}"),
+            Error::NotRecorded(url, cassette) => write!(
+                f,
+                "GET {url} is not in the cassette \"{}\"",
+                cassette.display()
+            ),
+        }
+    }
+}
+
+//the parts of twitter's response envelope that are used, "errors" and "meta" are ignored
+#[derive(Deserialize)]
+struct Response<T> {
+    data: Option<T>,
+    #[serde(default)]
+    includes: Includes,
+}
+
+//what "expansions" brought along with the data
+#[derive(Default, Deserialize)]
+struct Includes {
+    #[serde(default)]
+    media: Vec<Media>,
+}
+
+// A photo, video or gif attached to tweets. twitter_v2's Media has no "variants", the files a
+// video or gif can be downloaded as, so it's read here.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Media {
+    pub media_key: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub url: Option<String>,
+    pub preview_image_url: Option<String>,
+    #[serde(default)]
+    pub variants: Vec<Variant>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Variant {
+    pub content_type: String,
+    pub url: String,
+    pub bit_rate: Option<u64>,
+}
+
+impl Media {
+    //a photo as is, the best mp4 of a video or gif, or its preview image if there isn't one
+    pub fn file_url(&self) -> Option<&str> {
+        let mp4 = self
+            .variants
+            .iter()
+            .filter(|variant| variant.content_type == "video/mp4")
+            .max_by_key(|variant| variant.bit_rate.unwrap_or(0));
+        match self.kind.as_str() {
+            "photo" => self.url.as_deref(),
+            _ => mp4
+                .map(|variant| variant.url.as_str())
+                .or(self.preview_image_url.as_deref()),
+        }
+    }
+}
+
+//GETs a path relative to the base URL, e.g. "tweets/20", with the bearer token
+async fn get<T: DeserializeOwned>(
+    path: String,
+    query: Vec<(&'static str, String)>,
+) -> Result<Option<T>, Error> {
+    Ok(get_response(path, query).await?.data)
+}
+
+//the same, with what was included alongside the data
+async fn get_response<T: DeserializeOwned>(
+    path: String,
+    query: Vec<(&'static str, String)>,
+) -> Result<Response<T>, Error> {
+    let mut url = base_url()
+        .join(&path)
+        .unwrap_or_else(|_| panic!("\"{path}\" isn't a valid Twitter API path"));
+    url.query_pairs_mut().extend_pairs(&query);
+    let request = cassette::Request {
+        method: "GET".to_string(),
+        url: url
+            .as_str()
+            .strip_prefix(base_url().as_str())
+            .unwrap_or(url.as_str())
+            .to_string(),
+    };
+    let response = match cassette::current() {
+        Some(cassette) if cassette.is_replaying() => cassette
+            .play(&request)
+            .ok_or_else(|| Error::NotRecorded(request.url.clone(), cassette.path().into()))?,
+        cassette => {
+            let pool = tokens::current();
+            let endpoint = tokens::endpoint(&path);
+            loop {
+                //every token is rate limited, this waits for the first to reset
+                let bearer_token = pool
+                    .pick(&endpoint)
+                    .map_err(|wait| Error::RateLimited(Some(wait)))?;
+                let response = fetch(url.clone(), &bearer_token).await?;
+                pool.update(&bearer_token, &endpoint, response.status, &response.headers);
+                if let Some(cassette) = &cassette {
+                    cassette.write(&request, &response, &bearer_token);
+                }
+                if response.status != StatusCode::TOO_MANY_REQUESTS.as_u16() {
+                    break response;
+                }
+                println!(
+                    "Rate limited by twitter on {endpoint} with bearer token {}",
+                    tokens::hint(&bearer_token)
+                );
+            }
+        }
+    };
+    let status = StatusCode::from_u16(response.status).unwrap_or(StatusCode::BAD_GATEWAY);
+    //only from a cassette, which plays back what happened without trying another token
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("The clock is before 1970")
+            .as_secs();
+        //when the window resets, in seconds since the epoch
+        let reset = response
+            .headers
+            .get("x-rate-limit-reset")
+            .and_then(|reset| reset.parse::<u64>().ok());
+        return Err(Error::RateLimited(
+            reset.map(|reset| reset.saturating_sub(now)),
+        ));
+    }
+    if !status.is_success() {
+        return Err(Error::Status(status, response.body_text()));
+    }
+    serde_json::from_value(response.body).map_err(Error::Json)
+}
+
+//the response as a cassette keeps it
+async fn fetch(url: Url, bearer_token: &str) -> Result<cassette::Response, Error> {
+    let response = reqwest::Client::new()
+        .get(url)
+        .bearer_auth(bearer_token)
+        .send()
+        .await
+        .map_err(Error::Request)?;
+    let status = response.status().as_u16();
+    let headers: BTreeMap<String, String> = response
+        .headers()
+        .iter()
+        .filter(|(name, _)| name.as_str().starts_with("x-rate-limit-"))
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+    let body = response.text().await.map_err(Error::Request)?;
+    Ok(cassette::Response::new(status, headers, &body))
+}
+
+//sends a request, waiting out the rate limit and retrying whenever twitter says to slow down
+async fn send<T, F, R>(request: F, failure: &str) -> Option<T>
+where
+    F: Fn() -> R,
+    R: Future<Output = Result<Option<T>, Error>>,
+{
+    loop {
+        match request().await {
+            Err(Error::RateLimited(wait)) => {
+                //at least a second, so a reset that's already passed doesn't make it spin
+                let seconds = wait.unwrap_or(RATE_LIMIT_WINDOW.as_secs()).max(1);
+                println!("Rate limited by twitter, waiting {seconds} seconds");
+                progress::emit(Event::RateLimited { seconds });
+                tokio::time::sleep(Duration::from_secs(seconds)).await;
+            }
+            result => return result.unwrap_or_else(|error| panic!("{failure}: {error}")),
+        }
+    }
+}
 
 #[async_recursion]
-pub async fn get_twitter_conversation_from_tweet(tweet: Tweet) -> Vec<Tweet> {
+pub async fn get_twitter_conversation_from_tweet(archive: &Archive, tweet: Tweet) -> Vec<Tweet> {
     let mut output = vec![tweet];
-    match &output[0].referenced_tweets {
-        Some(referenced_tweets) => {
-            if referenced_tweets
-                .iter()
-                .any(|tweet| tweet.kind == RepliedTo)
-            {
-                let replied_to_id = referenced_tweets
-                    .iter()
-                    .find(|tweet| tweet.kind == RepliedTo)
-                    .expect("Failed to find replied to tweet")
-                    .id
-                    .as_u64();
-                let replied_to: Tweet = super::load_tweet_from_id(replied_to_id).await;
-                let mut conversation: Vec<Tweet> =
-                    get_twitter_conversation_from_tweet(replied_to).await;
-                output.append(&mut conversation);
-                output
-            } else {
-                output.reverse();
-                output
-            }
+    match Cache::replied_to_id(&output[0]) {
+        Some(replied_to_id) => {
+            let replied_to: Tweet = super::load_tweet_from_id(archive, replied_to_id).await;
+            let mut conversation: Vec<Tweet> =
+                get_twitter_conversation_from_tweet(archive, replied_to).await;
+            output.append(&mut conversation);
+            output
         }
         None => {
             output.reverse();
@@ -39,121 +311,202 @@ pub async fn get_twitter_conversation_from_tweet(tweet: Tweet) -> Vec<Tweet> {
 }
 
 pub async fn get_tweets_from_user(user: &User) -> Vec<Tweet> {
-    load_api()
-        .await
-        .get_user_tweets(user.id)
-        .max_results(10) //this line gets the max results
-        .tweet_fields([
-            TweetField::Attachments,
-            TweetField::ReferencedTweets,
-            TweetField::AuthorId,
-            TweetField::ConversationId,
-            TweetField::CreatedAt,
-        ])
-        .send()
-        .await
-        .expect("Users tweets not loading")
-        .into_data()
-        .expect("Failure to open option<Vec<Tweet>>")
+    send(
+        || {
+            get(
+                format!("users/{}/tweets", user.id),
+                vec![
+                    ("max_results", "10".to_string()), //this line gets the max results
+                    ("tweet.fields", TWEET_FIELDS.to_string()),
+                ],
+            )
+        },
+        "Users tweets not loading",
+    )
+    .await
+    .expect("Failure to open option<Vec<Tweet>>")
 }
 
 pub async fn get_all_tweets_from_user(user: &User) -> Vec<Tweet> {
     let mut output = get_first_hundred_tweets_from_user(user).await;
-    const FIRST_TWEET_ID: u64 = 1012187366587392000; // 1490542591154130947; //@yudapearls first tweet id = 1012187366587392000
-    let mut last_id = output.last().expect("Failed to get last tweet").id.as_u64();
+    progress::emit(Event::PageFetched {
+        twitter_handle: user.username.clone(),
+        page: 1,
+        pages: TIMELINE_PAGES,
+        tweets: output.len(),
+    });
+    //@yudapearls first tweet id = 1012187366587392000
     let mut i = 1;
-    while i < 32 {
+    //a page that isn't full was the last one
+    while i < TIMELINE_PAGES && output.len() == i * 100 {
+        let last_id = output.last().expect("Failed to get last tweet").id.as_u64();
         output.append(&mut get_tweets_from_user_until_id(user, last_id).await);
-        last_id = output.last().expect("Failed to get last tweet").id.as_u64();
         println!("Loading tweets up to {i}00");
+        progress::emit(Event::PageFetched {
+            twitter_handle: user.username.clone(),
+            page: i + 1,
+            pages: TIMELINE_PAGES,
+            tweets: output.len(),
+        });
         i += 1;
     }
 
     output
 }
 
+//empty for a user who hasn't tweeted
 pub async fn get_first_hundred_tweets_from_user(user: &User) -> Vec<Tweet> {
-    load_api()
-        .await
-        .get_user_tweets(user.id)
-        .max_results(100) //this line gets the max results
-        .tweet_fields([
-            TweetField::Attachments,
-            TweetField::ReferencedTweets,
-            TweetField::AuthorId,
-            TweetField::ConversationId,
-            TweetField::CreatedAt,
-        ])
-        .send()
-        .await
-        .expect("Users tweets not loading")
-        .into_data()
-        .expect("Failure to open option<Vec<Tweet>>")
+    send(
+        || {
+            get(
+                format!("users/{}/tweets", user.id),
+                vec![
+                    ("max_results", "100".to_string()), //this line gets the max results
+                    ("tweet.fields", TWEET_FIELDS.to_string()),
+                ],
+            )
+        },
+        "Users tweets not loading",
+    )
+    .await
+    .unwrap_or_default()
 }
 
+//empty once the timeline has run out
 pub async fn get_tweets_from_user_until_id(user: &User, id: u64) -> Vec<Tweet> {
-    load_api()
-        .await
-        .get_user_tweets(user.id)
-        .max_results(100) //this line gets the max results
-        .until_id(id)
-        .tweet_fields([
-            TweetField::Attachments,
-            TweetField::ReferencedTweets,
-            TweetField::AuthorId,
-            TweetField::ConversationId,
-            TweetField::CreatedAt,
-        ])
-        .send()
+    send(
+        || {
+            get(
+                format!("users/{}/tweets", user.id),
+                vec![
+                    ("max_results", "100".to_string()), //this line gets the max results
+                    ("until_id", id.to_string()),
+                    ("tweet.fields", TWEET_FIELDS.to_string()),
+                ],
+            )
+        },
+        "Users tweets not loading",
+    )
+    .await
+    .unwrap_or_default()
+}
+
+//the user's tweets newer than `since_id`, newest first, paging back until there are no more
+pub async fn get_tweets_from_user_since_id(user: &User, since_id: u64) -> Vec<Tweet> {
+    let mut output: Vec<Tweet> = Vec::new();
+    for page in 1..=TIMELINE_PAGES {
+        let mut query = vec![
+            ("max_results", "100".to_string()), //this line gets the max results
+            ("since_id", since_id.to_string()),
+            ("tweet.fields", TWEET_FIELDS.to_string()),
+        ];
+        if let Some(until_id) = output.last().map(|tweet| tweet.id.as_u64()) {
+            query.push(("until_id", until_id.to_string()));
+        }
+        let mut tweets: Vec<Tweet> = send(
+            || get(format!("users/{}/tweets", user.id), query.clone()),
+            "Users tweets not loading",
+        )
         .await
-        .expect("Users tweets not loading")
-        .into_data()
-        .expect("Failure to open option<Vec<Tweet>>")
+        .unwrap_or_default();
+        let full_page = tweets.len() == 100;
+        output.append(&mut tweets);
+        progress::emit(Event::PageFetched {
+            twitter_handle: user.username.clone(),
+            page,
+            pages: TIMELINE_PAGES,
+            tweets: output.len(),
+        });
+        if !full_page {
+            break;
+        }
+    }
+    output
 }
 
 pub async fn get_tweet_by_id(id: u64) -> Tweet {
-    load_api()
-        .await
-        .get_tweet(id)
-        .tweet_fields([
-            TweetField::Attachments,
-            TweetField::ReferencedTweets,
-            TweetField::ConversationId,
-            TweetField::AuthorId,
-            TweetField::CreatedAt,
-        ])
-        .send()
-        .await
-        .expect("this tweet should exist")
-        .into_data()
-        .expect("Failure to open Option<Tweet>")
+    send(
+        || {
+            get(
+                format!("tweets/{id}"),
+                vec![("tweet.fields", TWEET_FIELDS.to_string())],
+            )
+        },
+        "this tweet should exist",
+    )
+    .await
+    .expect("Failure to open Option<Tweet>")
 }
 
-pub async fn get_user_by_twitter_handle(twitter_handle: &str) -> User {
-    load_api()
-        .await
-        .get_user_by_username(twitter_handle)
-        .user_fields([UserField::Username, UserField::Description])
-        .send()
-        .await
-        .expect("This user should exist")
-        .into_data()
-        .expect("Failure to open Option<User>")
+//the ids that still exist on twitter out of at most 100 tweet ids, deleted tweets are left out
+pub async fn get_existing_tweet_ids(ids: &[u64]) -> Vec<u64> {
+    let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+    let tweets: Vec<Tweet> = send(
+        || get("tweets".to_string(), vec![("ids", ids.join(","))]),
+        "Tweets not loading",
+    )
+    .await
+    .unwrap_or_default();
+    tweets.into_iter().map(|tweet| tweet.id.as_u64()).collect()
 }
 
-pub async fn get_user_by_id(id: u64) -> User {
-    load_api()
-        .await
-        .get_user(id)
-        .user_fields([UserField::Username, UserField::Description])
-        .send()
-        .await
-        .expect("This user should exist")
-        .into_data()
-        .expect("Failure to open Option<User>")
+//the media attached to at most 100 tweets
+pub async fn get_media_of_tweets(ids: &[u64]) -> Vec<Media> {
+    let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+    send(
+        || async {
+            let response: Response<Vec<Tweet>> = get_response(
+                "tweets".to_string(),
+                vec![
+                    ("ids", ids.join(",")),
+                    ("expansions", "attachments.media_keys".to_string()),
+                    ("media.fields", MEDIA_FIELDS.to_string()),
+                ],
+            )
+            .await?;
+            Ok(Some(response.includes.media))
+        },
+        "Media not loading",
+    )
+    .await
+    .unwrap_or_default()
 }
 
-pub async fn load_api() -> TwitterApi<BearerToken> {
-    let auth = BearerToken::new(std::env::var("TWITTER_DEV_BEARER_TOKEN").unwrap());
-    TwitterApi::new(auth)
+//a media file, which twitter serves without a bearer token
+pub async fn download(url: &str) -> Result<Vec<u8>, Error> {
+    let response = reqwest::get(url).await.map_err(Error::Request)?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(Error::Status(status, url.to_string()));
+    }
+    let bytes = response.bytes().await.map_err(Error::Request)?;
+    Ok(bytes.to_vec())
+}
+
+pub async fn get_user_by_twitter_handle(twitter_handle: &str) -> User {
+    send(
+        || {
+            get(
+                format!("users/by/username/{twitter_handle}"),
+                vec![("user.fields", USER_FIELDS.to_string())],
+            )
+        },
+        "This user should exist",
+    )
+    .await
+    .expect("Failure to open Option<User>")
+}
+
+pub async fn get_user_by_id(id: u64) -> User {
+    send(
+        || {
+            get(
+                format!("users/{id}"),
+                vec![("user.fields", USER_FIELDS.to_string())],
+            )
+        },
+        "This user should exist",
+    )
+    .await
+    .expect("Failure to open Option<User>")
 }
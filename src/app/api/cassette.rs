@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::future::Future;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// Twitter API responses captured to a file and played back later, to reproduce a bug report or
+// run the tests against real responses without network access. A cassette is JSON Lines, one
+// request and its response per line in the order they were made:
+//
+//     {"request":{"method":"GET","url":"tweets/20?tweet.fields=..."},"response":{"status":200,...}}
+//
+// Urls are relative to the API's base URL, and the bearer token is never written: requests are
+// recorded without their headers and the token is scrubbed from anything twitter echoes back.
+//
+// Replaying answers each request with the first recorded response to the same url that hasn't
+// been played yet, so a request made twice (say rate limited, then answered) plays back the same
+// way. A request made more often than it was recorded gets its last response again, and one
+// that was never recorded fails.
+
+const SCRUBBED: &str = "[scrubbed]";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Request {
+    pub method: String,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub status: u16,
+    //only twitter's rate limit headers, the rest don't change what the archiver does
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    //the JSON twitter answered with, or a string if it wasn't JSON
+    pub body: Value,
+}
+
+impl Response {
+    pub fn new(status: u16, headers: BTreeMap<String, String>, body: &str) -> Response {
+        Response {
+            status,
+            headers,
+            body: serde_json::from_str(body).unwrap_or_else(|_| Value::String(body.to_string())),
+        }
+    }
+
+    pub fn body_text(&self) -> String {
+        match &self.body {
+            Value::String(body) => body.clone(),
+            body => body.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Interaction {
+    request: Request,
+    response: Response,
+}
+
+enum Mode {
+    //appending to the file
+    Record(File),
+    //the recorded interactions and whether each has been played
+    Replay(Vec<(Interaction, bool)>),
+}
+
+pub struct Cassette {
+    path: PathBuf,
+    mode: Mutex<Mode>,
+}
+
+impl Cassette {
+    //starts an empty cassette, replacing one already at the path
+    pub fn record(path: &Path) -> Cassette {
+        let file = path.display();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .unwrap_or_else(|_| panic!("Failed to create directory for \"{file}\""));
+        }
+        let cassette = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)
+            .unwrap_or_else(|_| panic!("Failed to create the cassette \"{file}\""));
+        println!("Recording Twitter API responses to \"{file}\"");
+        Cassette {
+            path: path.to_path_buf(),
+            mode: Mutex::new(Mode::Record(cassette)),
+        }
+    }
+
+    pub fn replay(path: &Path) -> Cassette {
+        let file = path.display();
+        let interactions = fs::read_to_string(path)
+            .unwrap_or_else(|_| panic!("Failed to read the cassette \"{file}\""))
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let interaction: Interaction = serde_json::from_str(line).unwrap_or_else(|error| {
+                    panic!("Failed to parse the cassette \"{file}\": {error}")
+                });
+                (interaction, false)
+            })
+            .collect();
+        println!("Replaying Twitter API responses from \"{file}\"");
+        Cassette {
+            path: path.to_path_buf(),
+            mode: Mutex::new(Mode::Replay(interactions)),
+        }
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        matches!(*self.lock(), Mode::Replay(_))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Mode> {
+        self.mode.lock().expect("The cassette lock was poisoned")
+    }
+
+    //does nothing while replaying
+    pub fn write(&self, request: &Request, response: &Response, bearer_token: &str) {
+        let mut mode = self.lock();
+        let file = match &mut *mode {
+            Mode::Record(file) => file,
+            Mode::Replay(_) => return,
+        };
+        let interaction = Interaction {
+            request: request.clone(),
+            response: response.clone(),
+        };
+        let mut line =
+            serde_json::to_string(&interaction).expect("Failed to serialize an interaction");
+        if !bearer_token.is_empty() {
+            line = line.replace(bearer_token, SCRUBBED);
+        }
+        writeln!(file, "{line}").unwrap_or_else(|_| {
+            panic!(
+                "Failed to write to the cassette \"{}\"",
+                self.path.display()
+            )
+        });
+    }
+
+    //None if the request was never recorded, or while recording
+    pub fn play(&self, request: &Request) -> Option<Response> {
+        let mut mode = self.lock();
+        let interactions = match &mut *mode {
+            Mode::Replay(interactions) => interactions,
+            Mode::Record(_) => return None,
+        };
+        let mut last = None;
+        for (interaction, played) in interactions
+            .iter_mut()
+            .filter(|(interaction, _)| interaction.request == *request)
+        {
+            if !*played {
+                *played = true;
+                return Some(interaction.response.clone());
+            }
+            last = Some(interaction.response.clone());
+        }
+        last
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+//the cassette from the configuration, used by every request outside a `with` scope
+static CASSETTE: OnceLock<Arc<Cassette>> = OnceLock::new();
+
+tokio::task_local! {
+    static SCOPED: Arc<Cassette>;
+}
+
+//only the first call has any effect
+pub fn configure(cassette: Cassette) {
+    CASSETTE.get_or_init(|| Arc::new(cassette));
+}
+
+//runs `future` with its requests going through `cassette` instead of the configured one
+pub async fn with<F: Future>(cassette: Cassette, future: F) -> F::Output {
+    SCOPED.scope(Arc::new(cassette), future).await
+}
+
+pub fn current() -> Option<Arc<Cassette>> {
+    SCOPED
+        .try_with(|cassette| cassette.clone())
+        .ok()
+        .or_else(|| CASSETTE.get().cloned())
+}
@@ -0,0 +1,266 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use super::{ApiConfig, RATE_LIMIT_WINDOW};
+
+// The bearer tokens requests to the Twitter API are shared between. Twitter rate limits each
+// token per endpoint, e.g. 900 requests to "users/:id/tweets" every 15 minutes, and says how
+// much is left in the x-rate-limit headers of every response. Each request goes to the token
+// with the most left for its endpoint, and one answered 429 is sent again with another token
+// until every token is used up, when it waits for the first of them to reset.
+//
+// The tokens are TWITTER_DEV_BEARER_TOKEN, `bearer_tokens` in Rocket.toml and the lines of the
+// `bearer_tokens_file`, blank lines and lines starting with "#" left out.
+
+//a token's budget for one endpoint, as twitter last told it
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Budget {
+    pub requests: u64,
+    //requests answered 429
+    pub rate_limited: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining: Option<u64>,
+    //when the window resets, in seconds since the epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reset: Option<u64>,
+}
+
+impl Budget {
+    //requests that can still be sent before the reset, as many as it takes when unknown
+    fn available(&self, now: u64) -> u64 {
+        match (self.remaining, self.reset) {
+            (Some(remaining), Some(reset)) if now < reset => remaining,
+            _ => u64::MAX,
+        }
+    }
+}
+
+struct Token {
+    secret: String,
+    //endpoint -> budget
+    endpoints: BTreeMap<String, Budget>,
+}
+
+//what "/twitter/usage" reports for a token, which is only ever shown by its last characters
+#[derive(Debug, Clone, Serialize)]
+pub struct Usage {
+    pub token: String,
+    pub requests: u64,
+    pub rate_limited: u64,
+    pub endpoints: BTreeMap<String, Budget>,
+}
+
+pub struct Pool {
+    tokens: Mutex<Vec<Token>>,
+}
+
+impl Pool {
+    //in order of preference, duplicates and empty tokens left out
+    pub fn new(secrets: Vec<String>) -> Pool {
+        let mut tokens: Vec<Token> = Vec::new();
+        for secret in secrets {
+            let secret = secret.trim().to_string();
+            if secret.is_empty() || tokens.iter().any(|token| token.secret == secret) {
+                continue;
+            }
+            tokens.push(Token {
+                secret,
+                endpoints: BTreeMap::new(),
+            });
+        }
+        Pool {
+            tokens: Mutex::new(tokens),
+        }
+    }
+
+    pub fn from_config(config: &ApiConfig) -> Pool {
+        let mut secrets: Vec<String> = std::env::var("TWITTER_DEV_BEARER_TOKEN")
+            .into_iter()
+            .collect();
+        secrets.extend(config.bearer_tokens.iter().cloned());
+        if let Some(path) = &config.bearer_tokens_file {
+            let file = std::fs::read_to_string(path).unwrap_or_else(|_| {
+                panic!("Failed to read bearer tokens from \"{}\"", path.display())
+            });
+            secrets.extend(
+                file.lines()
+                    .filter(|line| !line.trim_start().starts_with('#'))
+                    .map(String::from),
+            );
+        }
+        let pool = Pool::new(secrets);
+        let tokens = pool.lock().len();
+        if tokens > 1 {
+            println!("Sharing Twitter API requests between {tokens} bearer tokens");
+        }
+        pool
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Token>> {
+        self.tokens
+            .lock()
+            .expect("The bearer tokens lock was poisoned")
+    }
+
+    // The token with the most budget left for the endpoint, counting the request against it, or
+    // how many seconds until one of them has some again.
+    pub fn pick(&self, endpoint: &str) -> Result<String, u64> {
+        let now = now();
+        let mut tokens = self.lock();
+        if tokens.is_empty() {
+            panic!("No Twitter API bearer token, set TWITTER_DEV_BEARER_TOKEN or bearer_tokens in Rocket.toml");
+        }
+        //the first of the tokens with the most left, so unused tokens are tried in order
+        let (index, available) = tokens
+            .iter()
+            .map(|token| {
+                token
+                    .endpoints
+                    .get(endpoint)
+                    .map_or(u64::MAX, |budget| budget.available(now))
+            })
+            .enumerate()
+            .fold((0, 0), |best, (index, available)| {
+                match available > best.1 {
+                    true => (index, available),
+                    false => best,
+                }
+            });
+        if available == 0 {
+            let reset = tokens
+                .iter()
+                .filter_map(|token| token.endpoints.get(endpoint)?.reset)
+                .min()
+                .unwrap_or(now + RATE_LIMIT_WINDOW.as_secs());
+            return Err(reset.saturating_sub(now));
+        }
+        let token = &mut tokens[index];
+        let budget = token.endpoints.entry(endpoint.to_string()).or_default();
+        if budget.reset.is_some_and(|reset| now >= reset) {
+            budget.remaining = None;
+            budget.reset = None;
+        }
+        budget.requests += 1;
+        budget.remaining = budget
+            .remaining
+            .map(|remaining| remaining.saturating_sub(1));
+        Ok(token.secret.clone())
+    }
+
+    //what twitter said about the token's budget in the response to a request
+    pub fn update(
+        &self,
+        secret: &str,
+        endpoint: &str,
+        status: u16,
+        headers: &BTreeMap<String, String>,
+    ) {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|value| value.parse::<u64>().ok())
+        };
+        let mut tokens = self.lock();
+        let token = match tokens.iter_mut().find(|token| token.secret == secret) {
+            Some(token) => token,
+            None => return,
+        };
+        let budget = token.endpoints.entry(endpoint.to_string()).or_default();
+        budget.limit = header("x-rate-limit-limit").or(budget.limit);
+        budget.remaining = header("x-rate-limit-remaining").or(budget.remaining);
+        budget.reset = header("x-rate-limit-reset").or(budget.reset);
+        if status == 429 {
+            budget.rate_limited += 1;
+            budget.remaining = Some(0);
+            budget.reset =
+                Some(header("x-rate-limit-reset").unwrap_or(now() + RATE_LIMIT_WINDOW.as_secs()));
+        }
+    }
+
+    pub fn usage(&self) -> Vec<Usage> {
+        self.lock()
+            .iter()
+            .map(|token| Usage {
+                token: hint(&token.secret),
+                requests: token.endpoints.values().map(|budget| budget.requests).sum(),
+                rate_limited: token
+                    .endpoints
+                    .values()
+                    .map(|budget| budget.rate_limited)
+                    .sum(),
+                endpoints: token.endpoints.clone(),
+            })
+            .collect()
+    }
+}
+
+//the last few characters of a token, enough to tell them apart without giving them away
+pub fn hint(secret: &str) -> String {
+    let characters: Vec<char> = secret.chars().collect();
+    match characters.len() >= 12 {
+        true => format!(
+            "…{}",
+            characters[characters.len() - 4..]
+                .iter()
+                .collect::<String>()
+        ),
+        false => "…".to_string(),
+    }
+}
+
+//the endpoint a path is rate limited under, e.g. "users/:id/tweets" for "users/12/tweets"
+pub fn endpoint(path: &str) -> String {
+    let mut previous = "";
+    path.trim_matches('/')
+        .split('/')
+        .map(|segment| {
+            let segment = match segment {
+                _ if previous == "username" => ":username",
+                _ if !segment.is_empty() && segment.bytes().all(|byte| byte.is_ascii_digit()) => {
+                    ":id"
+                }
+                segment => segment,
+            };
+            previous = segment;
+            segment
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("The clock is before 1970")
+        .as_secs()
+}
+
+//the tokens from the configuration, used by every request outside a `with` scope
+static POOL: OnceLock<Arc<Pool>> = OnceLock::new();
+
+tokio::task_local! {
+    static SCOPED: Arc<Pool>;
+}
+
+//only the first call has any effect, until then the pool is TWITTER_DEV_BEARER_TOKEN alone
+pub fn configure(pool: Pool) {
+    POOL.get_or_init(|| Arc::new(pool));
+}
+
+//runs `future` with its requests sent with the tokens of `pool` instead of the configured ones
+pub async fn with<F: Future>(pool: Arc<Pool>, future: F) -> F::Output {
+    SCOPED.scope(pool, future).await
+}
+
+pub fn current() -> Arc<Pool> {
+    SCOPED.try_with(|pool| pool.clone()).unwrap_or_else(|_| {
+        POOL.get_or_init(|| Arc::new(Pool::from_config(&ApiConfig::default())))
+            .clone()
+    })
+}
@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use serde::Deserialize;
+use twitter_v2::{Tweet, User};
+
+use super::cache::Cache;
+use super::io::{self, layout::Layout};
+use super::migrations;
+use super::search::index;
+
+pub const DEFAULT_ARCHIVE: &str = "default";
+
+static ARCHIVES: OnceLock<HashMap<String, Archive>> = OnceLock::new();
+
+//read from Rocket.toml, ROCKET_DATA_DIR / ROCKET_ARCHIVES or the --data-dir flag
+#[derive(Debug, Deserialize)]
+pub struct ArchiveConfig {
+    #[serde(default = "default_data_dir")]
+    pub data_dir: PathBuf,
+    //extra named archives, relative paths are resolved against data_dir
+    #[serde(default)]
+    pub archives: HashMap<String, PathBuf>,
+}
+
+fn default_data_dir() -> PathBuf {
+    PathBuf::from("data")
+}
+
+impl ArchiveConfig {
+    pub fn root(&self, name: &str) -> Option<PathBuf> {
+        if name == DEFAULT_ARCHIVE {
+            Some(self.data_dir.clone())
+        } else {
+            self.archives.get(name).map(|path| self.data_dir.join(path))
+        }
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names = vec![DEFAULT_ARCHIVE.to_string()];
+        names.extend(self.archives.keys().cloned());
+        names
+    }
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> ArchiveConfig {
+        ArchiveConfig {
+            data_dir: default_data_dir(),
+            archives: HashMap::new(),
+        }
+    }
+}
+
+pub struct Archive {
+    pub name: String,
+    pub layout: Layout,
+    cache: RwLock<Cache>,
+}
+
+//loads every configured archive into memory, only the first call has any effect
+pub fn configure(config: &ArchiveConfig) {
+    ARCHIVES.get_or_init(|| open_all(config));
+}
+
+fn open_all(config: &ArchiveConfig) -> HashMap<String, Archive> {
+    config
+        .names()
+        .into_iter()
+        .map(|name| {
+            let root = config.root(&name).expect("Every named archive has a root");
+            let archive = Archive::open(&name, root);
+            (name, archive)
+        })
+        .collect()
+}
+
+pub fn get(name: &str) -> Option<&'static Archive> {
+    ARCHIVES
+        .get_or_init(|| open_all(&ArchiveConfig::default()))
+        .get(name)
+}
+
+pub fn default_archive() -> &'static Archive {
+    get(DEFAULT_ARCHIVE).expect("The default archive should always be configured")
+}
+
+pub fn names() -> Vec<String> {
+    let mut names: Vec<String> = ARCHIVES
+        .get()
+        .map(|archives| archives.keys().cloned().collect())
+        .unwrap_or_default();
+    names.sort();
+    names
+}
+
+impl Archive {
+    pub fn open(name: &str, root: PathBuf) -> Archive {
+        let layout = migrations::migrate(&Layout::new(root));
+        let cache = Cache::from_disk(&layout);
+        Archive {
+            name: name.to_string(),
+            layout,
+            cache: RwLock::new(cache),
+        }
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, Cache> {
+        self.cache.read().expect("Archive cache lock was poisoned")
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Cache> {
+        self.cache.write().expect("Archive cache lock was poisoned")
+    }
+
+    pub fn tweet(&self, id: u64) -> Option<Tweet> {
+        self.read().tweet(id).cloned()
+    }
+
+    pub fn tweets(&self) -> Vec<Tweet> {
+        self.read().tweets.clone()
+    }
+
+    pub fn tweets_in_conversation(&self, conversation_id: u64) -> Vec<Tweet> {
+        self.read()
+            .tweets_in_conversation(conversation_id)
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+
+    pub fn user(&self, id: u64) -> Option<User> {
+        self.read().user(id).cloned()
+    }
+
+    pub fn user_from_twitter_handle(&self, twitter_handle: &str) -> Option<User> {
+        self.read()
+            .user_from_twitter_handle(twitter_handle)
+            .cloned()
+    }
+
+    pub fn conversation(&self, last_tweet_id: u64) -> Option<Vec<Tweet>> {
+        self.read().conversation(last_tweet_id).cloned()
+    }
+
+    pub fn user_tweets(&self, twitter_handle: &str) -> Option<Vec<Tweet>> {
+        self.read()
+            .user_tweets
+            .get(&twitter_handle.to_lowercase())
+            .cloned()
+    }
+
+    pub fn user_conversations(&self, twitter_handle: &str) -> Option<Vec<Vec<Tweet>>> {
+        self.read()
+            .user_conversations
+            .get(&twitter_handle.to_lowercase())
+            .cloned()
+    }
+
+    //returns the tweets that weren't archived yet
+    pub fn insert_tweets(&self, tweets: &[Tweet]) -> Vec<Tweet> {
+        let mut cache = self.write();
+        self.add_tweets(&mut cache, tweets)
+    }
+
+    fn add_tweets(&self, cache: &mut Cache, tweets: &[Tweet]) -> Vec<Tweet> {
+        let added = cache.add_and_index_tweets(tweets.to_vec());
+        if added == 0 {
+            return Vec::new();
+        }
+        io::write::tweets_to_ron(&self.layout, &cache.tweets);
+        index::save_if_grown(&self.layout, &mut cache.index);
+        cache.tweets[cache.tweets.len() - added..].to_vec()
+    }
+
+    pub fn reindex(&self) {
+        let mut cache = self.write();
+        cache.index = index::rebuild(&self.layout, &cache.tweets);
+    }
+
+    pub fn insert_user(&self, user: &User) {
+        let mut cache = self.write();
+        if cache.add_user(user.clone()) {
+            io::write::users_to_ron(&self.layout, &cache.users);
+        }
+    }
+
+    pub fn insert_user_info(&self, user: &User, twitter_handle: &str) {
+        let mut cache = self.write();
+        if cache.add_user_with_handle(user.clone(), twitter_handle) {
+            io::write::users_to_ron(&self.layout, &cache.users);
+        }
+        io::write::user_info_to_ron(&self.layout, user, twitter_handle);
+    }
+
+    //the user's profile as it is now, e.g. after they changed their name or bio
+    pub fn update_user_info(&self, user: &User, twitter_handle: &str) {
+        let mut cache = self.write();
+        cache.replace_user(user.clone(), twitter_handle);
+        io::write::users_to_ron(&self.layout, &cache.users);
+        io::write::user_info_to_ron(&self.layout, user, twitter_handle);
+    }
+
+    //returns the tweets of the conversation that weren't archived yet
+    pub fn insert_conversation(&self, conversation: &[Tweet]) -> Vec<Tweet> {
+        let mut cache = self.write();
+        if cache.add_conversation(conversation.to_vec()) {
+            io::write::conversations_to_ron(&self.layout, &cache.conversations);
+        }
+        self.add_tweets(&mut cache, conversation)
+    }
+
+    //returns the tweets that weren't archived yet, from this or any other timeline
+    pub fn insert_user_tweets(&self, tweets: &[Tweet], twitter_handle: &str) -> Vec<Tweet> {
+        let mut cache = self.write();
+        cache
+            .user_tweets
+            .insert(twitter_handle.to_lowercase(), tweets.to_vec());
+        io::write::user_tweets_to_ron(&self.layout, tweets, twitter_handle);
+        self.add_tweets(&mut cache, tweets)
+    }
+
+    pub fn insert_user_conversations(&self, conversations: &[Vec<Tweet>], twitter_handle: &str) {
+        let mut cache = self.write();
+        cache
+            .user_conversations
+            .insert(twitter_handle.to_lowercase(), conversations.to_vec());
+        io::write::user_conversations_to_ron(&self.layout, conversations, twitter_handle);
+    }
+}
+
+//selects the archive a request works on with "?archive=<name>", defaulting to the data_dir archive
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for &'static Archive {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let name = match request.query_value::<&str>("archive") {
+            Some(Ok(name)) => name,
+            _ => DEFAULT_ARCHIVE,
+        };
+        match get(name) {
+            Some(archive) => Outcome::Success(archive),
+            None => Outcome::Failure((Status::NotFound, ())),
+        }
+    }
+}
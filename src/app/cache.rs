@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+
+use twitter_v2::data::ReferencedTweetKind;
+use twitter_v2::{Tweet, User};
+
+use super::io::{self, layout::Layout};
+use super::records::{self, TweetRecord, UserRecord};
+use super::search::index::{self, Index};
+
+//an archive's tweets, users and conversations, indexed for O(1) lookups
+#[derive(Default)]
+pub struct Cache {
+    pub tweets: Vec<Tweet>,
+    tweets_by_id: HashMap<u64, usize>,
+    tweets_by_conversation_id: HashMap<u64, Vec<u64>>,
+    replies_by_replied_to_id: HashMap<u64, Vec<u64>>,
+    pub users: Vec<User>,
+    users_by_id: HashMap<u64, usize>,
+    users_by_handle: HashMap<String, u64>,
+    //a conversation is keyed by the id of its *last* tweet
+    pub conversations: Vec<Vec<Tweet>>,
+    conversations_by_last_tweet_id: HashMap<u64, usize>,
+    pub user_tweets: HashMap<String, Vec<Tweet>>,
+    pub user_conversations: HashMap<String, Vec<Vec<Tweet>>>,
+    pub index: Index,
+}
+
+impl Cache {
+    pub fn from_disk(layout: &Layout) -> Cache {
+        let root = layout.root().display();
+        println!("Loading archive \"{root}\" into memory");
+        let mut cache = Cache::default();
+        if let Ok(tweets_string) = io::read::tweets_string_from_ron(layout) {
+            let tweets: Vec<TweetRecord> = ron::from_str(&tweets_string)
+                .unwrap_or_else(|_| panic!("Failed to parse tweets from \"{root}/tweets.ron\""));
+            let tweets = records::tweets_from_records(tweets);
+            cache.add_tweets(tweets);
+        }
+        if let Ok(users_string) = io::read::users_string_from_ron(layout) {
+            let users: Vec<UserRecord> = ron::from_str(&users_string)
+                .unwrap_or_else(|_| panic!("Failed to parse users from \"{root}/users.ron\""));
+            records::users_from_records(users)
+                .into_iter()
+                .for_each(|user| {
+                    cache.add_user(user);
+                });
+        }
+        if let Ok(conversations_string) = io::read::conversations_string_from_ron(layout) {
+            let conversations: Vec<Vec<TweetRecord>> = ron::from_str(&conversations_string)
+                .unwrap_or_else(|_| {
+                    panic!("Failed to parse conversations from \"{root}/conversations.ron\"")
+                });
+            records::conversations_from_records(conversations)
+                .into_iter()
+                .for_each(|conversation| {
+                    cache.add_conversation(conversation);
+                });
+        }
+        for twitter_handle in io::read::twitter_handles_with_prefix(layout, "user-info_") {
+            let user_string = io::read::user_info_string_from_ron(layout, &twitter_handle)
+                .expect("Failed to read user info file");
+            let user: UserRecord = ron::from_str(&user_string).unwrap_or_else(|_| {
+                panic!("Failed to parse file \"{root}/user-info_{twitter_handle}.ron\"")
+            });
+            let user: User = user.into();
+            //a username the user has since changed, whose file was left behind
+            let renamed = cache.user(user.id.as_u64()).is_some_and(|archived| {
+                !archived.username.eq_ignore_ascii_case(&user.username)
+                    && !archived.username.eq_ignore_ascii_case(&twitter_handle)
+            });
+            if !renamed {
+                cache.add_user_with_handle(user, &twitter_handle);
+            }
+        }
+        for twitter_handle in io::read::twitter_handles_with_prefix(layout, "user-tweets_") {
+            let tweets_string = io::read::user_tweets_string_from_ron(layout, &twitter_handle)
+                .expect("Failed to read user tweets file");
+            let tweets: Vec<TweetRecord> = ron::from_str(&tweets_string).unwrap_or_else(|_| {
+                panic!("Failed to parse file \"{root}/user-tweets_{twitter_handle}.ron\"")
+            });
+            cache.user_tweets.insert(
+                twitter_handle.to_lowercase(),
+                records::tweets_from_records(tweets),
+            );
+        }
+        for twitter_handle in io::read::twitter_handles_with_prefix(layout, "user-conversations_") {
+            let conversations_string =
+                io::read::user_conversations_string_from_ron(layout, &twitter_handle)
+                    .expect("Failed to read user conversations file");
+            let conversations: Vec<Vec<TweetRecord>> = ron::from_str(&conversations_string)
+                .unwrap_or_else(|_| {
+                    panic!(
+                        "Failed to parse file \"{root}/user-conversations_{twitter_handle}.ron\""
+                    )
+                });
+            cache.user_conversations.insert(
+                twitter_handle.to_lowercase(),
+                records::conversations_from_records(conversations),
+            );
+        }
+        cache.index = index::load(layout, &cache.tweets);
+        let (tweets, users, conversations) = (
+            cache.tweets.len(),
+            cache.users.len(),
+            cache.conversations.len(),
+        );
+        println!("Loaded {tweets} tweets, {users} users and {conversations} conversations");
+        cache
+    }
+
+    pub fn tweet(&self, id: u64) -> Option<&Tweet> {
+        self.tweets_by_id.get(&id).map(|&index| &self.tweets[index])
+    }
+
+    pub fn tweets_in_conversation(&self, conversation_id: u64) -> Vec<&Tweet> {
+        match self.tweets_by_conversation_id.get(&conversation_id) {
+            Some(ids) => ids.iter().filter_map(|&id| self.tweet(id)).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    //the ids of the tweets this tweet replies to, quotes or retweets
+    pub fn referenced_ids(
+        tweet: &Tweet,
+        kind: ReferencedTweetKind,
+    ) -> impl Iterator<Item = u64> + '_ {
+        tweet
+            .referenced_tweets
+            .iter()
+            .flatten()
+            .filter(move |referenced_tweet| referenced_tweet.kind == kind)
+            .map(|referenced_tweet| referenced_tweet.id.as_u64())
+    }
+
+    //the id of the tweet this tweet replies to, if any
+    pub fn replied_to_id(tweet: &Tweet) -> Option<u64> {
+        Self::referenced_ids(tweet, ReferencedTweetKind::RepliedTo).next()
+    }
+
+    //the archived replies to a tweet
+    pub fn replies_to(&self, tweet_id: u64) -> Vec<&Tweet> {
+        match self.replies_by_replied_to_id.get(&tweet_id) {
+            Some(ids) => ids.iter().filter_map(|&id| self.tweet(id)).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    pub fn user(&self, id: u64) -> Option<&User> {
+        self.users_by_id.get(&id).map(|&index| &self.users[index])
+    }
+
+    pub fn user_from_twitter_handle(&self, twitter_handle: &str) -> Option<&User> {
+        self.users_by_handle
+            .get(&twitter_handle.to_lowercase())
+            .and_then(|&id| self.user(id))
+    }
+
+    pub fn conversation(&self, last_tweet_id: u64) -> Option<&Vec<Tweet>> {
+        self.conversations_by_last_tweet_id
+            .get(&last_tweet_id)
+            .map(|&index| &self.conversations[index])
+    }
+
+    //adds the tweets that aren't in the cache yet to the end of `tweets`, returning how many
+    pub fn add_tweets(&mut self, tweets: Vec<Tweet>) -> usize {
+        let mut added = 0;
+        for tweet in tweets {
+            let id = tweet.id.as_u64();
+            if self.tweets_by_id.contains_key(&id) {
+                continue;
+            }
+            if let Some(conversation_id) = tweet.conversation_id {
+                self.tweets_by_conversation_id
+                    .entry(conversation_id.as_u64())
+                    .or_default()
+                    .push(id);
+            }
+            if let Some(replied_to_id) = Cache::replied_to_id(&tweet) {
+                self.replies_by_replied_to_id
+                    .entry(replied_to_id)
+                    .or_default()
+                    .push(id);
+            }
+            self.tweets_by_id.insert(id, self.tweets.len());
+            self.tweets.push(tweet);
+            added += 1;
+        }
+        added
+    }
+
+    //like add_tweets, but also adds the new tweets to the search index
+    pub fn add_and_index_tweets(&mut self, tweets: Vec<Tweet>) -> usize {
+        let start = self.tweets.len();
+        let added = self.add_tweets(tweets);
+        self.index.add(&self.tweets[start..]);
+        added
+    }
+
+    pub fn add_user(&mut self, user: User) -> bool {
+        let id = user.id.as_u64();
+        if self.users_by_id.contains_key(&id) {
+            return false;
+        }
+        self.users_by_handle
+            .insert(user.username.to_lowercase(), id);
+        self.users_by_id.insert(id, self.users.len());
+        self.users.push(user);
+        true
+    }
+
+    //replaces the user with the same id, or adds them if there is none, and forgets the username
+    //they had if it changed
+    pub fn replace_user(&mut self, user: User, twitter_handle: &str) {
+        let id = user.id.as_u64();
+        let twitter_handle = twitter_handle.to_lowercase();
+        self.users_by_handle.insert(twitter_handle.clone(), id);
+        match self.users_by_id.get(&id) {
+            Some(&index) => {
+                let old_username = self.users[index].username.to_lowercase();
+                if old_username != user.username.to_lowercase()
+                    && old_username != twitter_handle
+                    && self.users_by_handle.get(&old_username) == Some(&id)
+                {
+                    self.users_by_handle.remove(&old_username);
+                }
+                self.users_by_handle
+                    .insert(user.username.to_lowercase(), id);
+                self.users[index] = user;
+            }
+            None => {
+                self.add_user(user);
+            }
+        }
+    }
+
+    pub fn add_user_with_handle(&mut self, user: User, twitter_handle: &str) -> bool {
+        self.users_by_handle
+            .insert(twitter_handle.to_lowercase(), user.id.as_u64());
+        self.add_user(user)
+    }
+
+    pub fn add_conversation(&mut self, conversation: Vec<Tweet>) -> bool {
+        match conversation.last() {
+            Some(last_tweet) => {
+                let last_tweet_id = last_tweet.id.as_u64();
+                if self
+                    .conversations_by_last_tweet_id
+                    .contains_key(&last_tweet_id)
+                {
+                    return false;
+                }
+                self.conversations_by_last_tweet_id
+                    .insert(last_tweet_id, self.conversations.len());
+                self.conversations.push(conversation);
+                true
+            }
+            None => false,
+        }
+    }
+}
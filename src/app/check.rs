@@ -0,0 +1,483 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use twitter_v2::{Tweet, User};
+
+use super::api;
+use super::io::{self, layout::Layout};
+use super::migrations::{self, MigrationError};
+use super::records::{self, TweetRecord, UserRecord};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    UnparsableFile {
+        file: PathBuf,
+        error: String,
+    },
+    DuplicateTweet {
+        file: PathBuf,
+        tweet_id: u64,
+    },
+    DuplicateUser {
+        file: PathBuf,
+        user_id: u64,
+    },
+    EmptyConversation {
+        file: PathBuf,
+        index: usize,
+    },
+    ConversationTweetMissing {
+        last_tweet_id: u64,
+        tweet_id: u64,
+    },
+    AuthorMissing {
+        tweet_id: u64,
+        author_id: u64,
+    },
+    UserTweetMissing {
+        twitter_handle: String,
+        tweet_id: u64,
+    },
+    UserTweetDiffers {
+        twitter_handle: String,
+        tweet_id: u64,
+    },
+    OrphanedMedia {
+        file: PathBuf,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::UnparsableFile { file, error } => {
+                write!(f, "\"{}\" can't be parsed: {error}", file.display())
+            }
+            Problem::DuplicateTweet { file, tweet_id } => {
+                write!(
+                    f,
+                    "tweet {tweet_id} appears more than once in \"{}\"",
+                    file.display()
+                )
+            }
+            Problem::DuplicateUser { file, user_id } => {
+                write!(
+                    f,
+                    "user {user_id} appears more than once in \"{}\"",
+                    file.display()
+                )
+            }
+            Problem::EmptyConversation { file, index } => {
+                write!(f, "conversation {index} in \"{}\" is empty", file.display())
+            }
+            Problem::ConversationTweetMissing {
+                last_tweet_id,
+                tweet_id,
+            } => write!(
+                f,
+                "tweet {tweet_id} of conversation {last_tweet_id} is not in tweets.ron"
+            ),
+            Problem::AuthorMissing {
+                tweet_id,
+                author_id,
+            } => write!(
+                f,
+                "author {author_id} of tweet {tweet_id} is not in users.ron"
+            ),
+            Problem::UserTweetMissing {
+                twitter_handle,
+                tweet_id,
+            } => write!(
+                f,
+                "tweet {tweet_id} of @{twitter_handle} is not in tweets.ron"
+            ),
+            Problem::UserTweetDiffers {
+                twitter_handle,
+                tweet_id,
+            } => write!(
+                f,
+                "tweet {tweet_id} of @{twitter_handle} differs from its copy in tweets.ron"
+            ),
+            Problem::OrphanedMedia { file } => write!(
+                f,
+                "media file \"{}\" is not attached to any archived tweet",
+                file.display()
+            ),
+        }
+    }
+}
+
+//what `repair` is allowed to do, pruning is always allowed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RepairOptions {
+    pub refetch: bool,
+}
+
+//the archive's files as they are on disk, duplicates and all
+#[derive(Default)]
+struct Files {
+    tweets: Vec<Tweet>,
+    users: Vec<User>,
+    conversations: Vec<Vec<Tweet>>,
+    user_tweets: Vec<(String, Vec<Tweet>)>,
+    user_conversations: Vec<(String, Vec<Vec<Tweet>>)>,
+    unparsable: Vec<Problem>,
+    //whether one of the files with tweets in it couldn't be parsed
+    tweets_unparsable: bool,
+}
+
+impl Files {
+    //every tweet in every file, stray copies included
+    fn all_tweets(&self) -> impl Iterator<Item = &Tweet> {
+        self.tweets
+            .iter()
+            .chain(self.conversations.iter().flatten())
+            .chain(self.user_tweets.iter().flat_map(|(_, tweets)| tweets))
+            .chain(
+                self.user_conversations
+                    .iter()
+                    .flat_map(|(_, conversations)| conversations.iter().flatten()),
+            )
+    }
+}
+
+fn parse<T: DeserializeOwned>(
+    file: PathBuf,
+    file_string: Result<String, std::io::Error>,
+    unparsable: &mut Vec<Problem>,
+) -> Option<T> {
+    match file_string {
+        Ok(file_string) => match ron::from_str(&file_string) {
+            Ok(value) => Some(value),
+            Err(error) => {
+                unparsable.push(Problem::UnparsableFile {
+                    file,
+                    error: error.to_string(),
+                });
+                None
+            }
+        },
+        Err(_error) => None,
+    }
+}
+
+fn read_files(layout: &Layout) -> Files {
+    let mut files = Files::default();
+    let unparsable = &mut files.unparsable;
+    if let Some(users) = parse::<Vec<UserRecord>>(
+        layout.users(),
+        io::read::users_string_from_ron(layout),
+        unparsable,
+    ) {
+        files.users = records::users_from_records(users);
+    }
+    for twitter_handle in io::read::twitter_handles_with_prefix(layout, "user-info_") {
+        parse::<UserRecord>(
+            layout.user_info(&twitter_handle),
+            io::read::user_info_string_from_ron(layout, &twitter_handle),
+            unparsable,
+        );
+    }
+    //only files with tweets in them from here on
+    let unparsable_users = unparsable.len();
+    if let Some(tweets) = parse::<Vec<TweetRecord>>(
+        layout.tweets(),
+        io::read::tweets_string_from_ron(layout),
+        unparsable,
+    ) {
+        files.tweets = records::tweets_from_records(tweets);
+    }
+    if let Some(conversations) = parse::<Vec<Vec<TweetRecord>>>(
+        layout.conversations(),
+        io::read::conversations_string_from_ron(layout),
+        unparsable,
+    ) {
+        files.conversations = records::conversations_from_records(conversations);
+    }
+    for twitter_handle in io::read::twitter_handles_with_prefix(layout, "user-tweets_") {
+        if let Some(tweets) = parse::<Vec<TweetRecord>>(
+            layout.user_tweets(&twitter_handle),
+            io::read::user_tweets_string_from_ron(layout, &twitter_handle),
+            unparsable,
+        ) {
+            files
+                .user_tweets
+                .push((twitter_handle, records::tweets_from_records(tweets)));
+        }
+    }
+    for twitter_handle in io::read::twitter_handles_with_prefix(layout, "user-conversations_") {
+        if let Some(conversations) = parse::<Vec<Vec<TweetRecord>>>(
+            layout.user_conversations(&twitter_handle),
+            io::read::user_conversations_string_from_ron(layout, &twitter_handle),
+            unparsable,
+        ) {
+            files.user_conversations.push((
+                twitter_handle,
+                records::conversations_from_records(conversations),
+            ));
+        }
+    }
+    files.tweets_unparsable = files.unparsable.len() > unparsable_users;
+    files
+}
+
+fn duplicate_ids(ids: impl Iterator<Item = u64>) -> Vec<u64> {
+    let mut seen = HashSet::new();
+    let mut duplicates = Vec::new();
+    for id in ids {
+        if !seen.insert(id) && !duplicates.contains(&id) {
+            duplicates.push(id);
+        }
+    }
+    duplicates
+}
+
+//files moved aside by an earlier repair, which may still hold tweets worth recovering
+fn moved_aside(layout: &Layout) -> bool {
+    match fs::read_dir(layout.root()) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok()).any(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|file_name| file_name.ends_with(".corrupt"))
+        }),
+        Err(_error) => false,
+    }
+}
+
+// Media files are named after their media key, e.g. "media/3_1544021366923415552.jpg".
+// None of them count as orphaned while tweets can't be read, from an unparsable file or one
+// moved aside, as the media of those tweets can't be told apart from media nothing needs.
+fn orphaned_media(layout: &Layout, files: &Files) -> Vec<PathBuf> {
+    if files.tweets_unparsable || moved_aside(layout) {
+        return Vec::new();
+    }
+    let media_keys: HashSet<String> = files
+        .all_tweets()
+        .filter_map(|tweet| tweet.attachments.as_ref())
+        .flat_map(|attachments| attachments.media_keys.iter().flatten())
+        .map(|media_key| media_key.to_string())
+        .collect();
+    io::read::media_files(layout)
+        .into_iter()
+        .filter(|file| {
+            let media_key = file
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or_default();
+            !media_keys.contains(media_key)
+        })
+        .collect()
+}
+
+fn find_problems(layout: &Layout, files: &Files) -> Vec<Problem> {
+    let mut problems = files.unparsable.clone();
+    for tweet_id in duplicate_ids(files.tweets.iter().map(|tweet| tweet.id.as_u64())) {
+        problems.push(Problem::DuplicateTweet {
+            file: layout.tweets(),
+            tweet_id,
+        });
+    }
+    for user_id in duplicate_ids(files.users.iter().map(|user| user.id.as_u64())) {
+        problems.push(Problem::DuplicateUser {
+            file: layout.users(),
+            user_id,
+        });
+    }
+    let tweets: HashMap<u64, &Tweet> = files
+        .tweets
+        .iter()
+        .map(|tweet| (tweet.id.as_u64(), tweet))
+        .collect();
+    let user_ids: HashSet<u64> = files.users.iter().map(|user| user.id.as_u64()).collect();
+    for (index, conversation) in files.conversations.iter().enumerate() {
+        match conversation.last() {
+            Some(last_tweet) => {
+                for tweet in conversation {
+                    if !tweets.contains_key(&tweet.id.as_u64()) {
+                        problems.push(Problem::ConversationTweetMissing {
+                            last_tweet_id: last_tweet.id.as_u64(),
+                            tweet_id: tweet.id.as_u64(),
+                        });
+                    }
+                }
+            }
+            None => problems.push(Problem::EmptyConversation {
+                file: layout.conversations(),
+                index,
+            }),
+        }
+    }
+    for (twitter_handle, conversations) in &files.user_conversations {
+        for (index, conversation) in conversations.iter().enumerate() {
+            if conversation.is_empty() {
+                problems.push(Problem::EmptyConversation {
+                    file: layout.user_conversations(twitter_handle),
+                    index,
+                });
+            }
+        }
+    }
+    let mut missing_authors = HashSet::new();
+    for tweet in &files.tweets {
+        if let Some(author_id) = tweet.author_id.map(|id| id.as_u64()) {
+            if !user_ids.contains(&author_id) && missing_authors.insert(author_id) {
+                problems.push(Problem::AuthorMissing {
+                    tweet_id: tweet.id.as_u64(),
+                    author_id,
+                });
+            }
+        }
+    }
+    for (twitter_handle, user_tweets) in &files.user_tweets {
+        for tweet in user_tweets {
+            let tweet_id = tweet.id.as_u64();
+            match tweets.get(&tweet_id) {
+                Some(&archived_tweet) if archived_tweet != tweet => {
+                    problems.push(Problem::UserTweetDiffers {
+                        twitter_handle: twitter_handle.clone(),
+                        tweet_id,
+                    })
+                }
+                Some(_) => {}
+                None => problems.push(Problem::UserTweetMissing {
+                    twitter_handle: twitter_handle.clone(),
+                    tweet_id,
+                }),
+            }
+        }
+    }
+    for file in orphaned_media(layout, files) {
+        problems.push(Problem::OrphanedMedia { file });
+    }
+    problems
+}
+
+impl From<MigrationError> for Problem {
+    fn from(error: MigrationError) -> Self {
+        Problem::UnparsableFile {
+            file: error.file,
+            error: error.error,
+        }
+    }
+}
+
+fn move_aside(file: &Path) {
+    let mut aside = file.to_path_buf().into_os_string();
+    aside.push(".corrupt");
+    let aside = PathBuf::from(aside);
+    println!("Moving \"{}\" to \"{}\"", file.display(), aside.display());
+    fs::rename(file, &aside)
+        .unwrap_or_else(|_| panic!("Failed to move \"{}\" aside", file.display()));
+}
+
+// Checks the archive with its files as they are on disk, migrating it to the current version
+// first. An archive too broken to migrate is left as it is and the file that stopped the
+// migration is the problem reported.
+pub fn check_archive(layout: &Layout) -> Vec<Problem> {
+    match migrations::try_migrate(layout) {
+        Ok(layout) => check(&layout),
+        Err(error) => vec![error.into()],
+    }
+}
+
+//the same for `repair`, moving aside the files that stop the archive from being migrated
+pub async fn repair_archive(layout: &Layout, options: RepairOptions) -> Vec<Problem> {
+    loop {
+        match migrations::try_migrate(layout) {
+            Ok(layout) => return repair(&layout, options).await,
+            //without the manifest the other files can't be told apart from older ones
+            Err(error) if error.file == layout.manifest() => return vec![error.into()],
+            Err(error) => move_aside(&error.file),
+        }
+    }
+}
+
+pub fn check(layout: &Layout) -> Vec<Problem> {
+    println!("Checking archive \"{}\"", layout.root().display());
+    find_problems(layout, &read_files(layout))
+}
+
+fn dedup_by_id<T>(items: &mut Vec<T>, id: impl Fn(&T) -> u64) {
+    let mut seen = HashSet::new();
+    items.retain(|item| seen.insert(id(item)));
+}
+
+//fixes what can be fixed and returns the problems that remain
+pub async fn repair(layout: &Layout, options: RepairOptions) -> Vec<Problem> {
+    let mut files = read_files(layout);
+    let problems = find_problems(layout, &files);
+    if problems.is_empty() {
+        return problems;
+    }
+    println!("Repairing archive \"{}\"", layout.root().display());
+
+    //unparsable files are moved aside so that the archive can load without them
+    for problem in &problems {
+        if let Problem::UnparsableFile { file, .. } = problem {
+            move_aside(file);
+        }
+    }
+
+    dedup_by_id(&mut files.tweets, |tweet| tweet.id.as_u64());
+    dedup_by_id(&mut files.users, |user| user.id.as_u64());
+    files
+        .conversations
+        .retain(|conversation| !conversation.is_empty());
+    for (_twitter_handle, conversations) in &mut files.user_conversations {
+        conversations.retain(|conversation| !conversation.is_empty());
+    }
+
+    //tweets only stored in a conversation or a user's file are copied into tweets.ron,
+    //and a user's file is brought in line with tweets.ron where they disagree
+    let mut archived: HashSet<u64> = files.tweets.iter().map(|tweet| tweet.id.as_u64()).collect();
+    let stray_tweets: Vec<Tweet> = files
+        .conversations
+        .iter()
+        .flatten()
+        .chain(files.user_tweets.iter().flat_map(|(_, tweets)| tweets))
+        .filter(|tweet| archived.insert(tweet.id.as_u64()))
+        .cloned()
+        .collect();
+    files.tweets.extend(stray_tweets);
+    let tweets: HashMap<u64, Tweet> = files
+        .tweets
+        .iter()
+        .map(|tweet| (tweet.id.as_u64(), tweet.clone()))
+        .collect();
+    for (_twitter_handle, user_tweets) in &mut files.user_tweets {
+        for tweet in user_tweets.iter_mut() {
+            *tweet = tweets[&tweet.id.as_u64()].clone();
+        }
+    }
+
+    if options.refetch {
+        for problem in &problems {
+            if let Problem::AuthorMissing { author_id, .. } = problem {
+                println!("Loading User of id {author_id} from Twitter API");
+                files.users.push(api::get_user_by_id(*author_id).await);
+            }
+        }
+    }
+
+    //only once every tweet the archive still has is known
+    for file in orphaned_media(layout, &files) {
+        println!("Removing \"{}\"", file.display());
+        fs::remove_file(&file)
+            .unwrap_or_else(|_| panic!("Failed to remove \"{}\"", file.display()));
+    }
+
+    io::write::tweets_to_ron(layout, &files.tweets);
+    io::write::users_to_ron(layout, &files.users);
+    io::write::conversations_to_ron(layout, &files.conversations);
+    for (twitter_handle, tweets) in &files.user_tweets {
+        io::write::user_tweets_to_ron(layout, tweets, twitter_handle);
+    }
+    for (twitter_handle, conversations) in &files.user_conversations {
+        io::write::user_conversations_to_ron(layout, conversations, twitter_handle);
+    }
+    check(layout)
+}
@@ -0,0 +1,77 @@
+use std::fs;
+use std::path::Path;
+
+use super::io::{
+    self,
+    layout::{Layout, StorageFormat},
+};
+use super::migrations::{self, Manifest};
+
+//total size in bytes of an archive's files before and after a conversion
+#[derive(Debug, Clone, Copy)]
+pub struct Sizes {
+    pub files: usize,
+    pub before: u64,
+    pub after: u64,
+}
+
+fn file_size(file_path: &Path) -> u64 {
+    fs::metadata(file_path)
+        .map(|metadata| metadata.len())
+        .unwrap_or(0)
+}
+
+//rewrites every file of the archive in the given storage format and records it in the manifest
+pub fn convert(layout: &Layout, format: StorageFormat) -> Sizes {
+    let root = layout.root().display();
+    let target = layout.with_format(format);
+    let mut files = io::read::archive_files(layout);
+    if layout.search_index().is_file() {
+        files.push(layout.search_index());
+    }
+    let mut sizes = Sizes {
+        files: files.len(),
+        before: 0,
+        after: 0,
+    };
+    if layout.format() == format {
+        println!("Archive \"{root}\" is already stored as {format:?}");
+        sizes.before = files.iter().map(|file_path| file_size(file_path)).sum();
+        sizes.after = sizes.before;
+        return sizes;
+    }
+    println!(
+        "Converting archive \"{root}\" from {:?} to {format:?}",
+        layout.format()
+    );
+    let old_extension = format!(".{}", layout.format().extension());
+    let mut converted = Vec::new();
+    for file_path in &files {
+        let file_name = file_path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .and_then(|file_name| file_name.strip_suffix(&old_extension))
+            .expect("Archive files always end in the archive's extension");
+        let new_path = file_path.with_file_name(format!("{file_name}.{}", format.extension()));
+        let contents = io::read::string_from_ron(file_path)
+            .unwrap_or_else(|_| panic!("Failed to read \"{}\"", file_path.display()));
+        io::write::string_to_file(&contents, &new_path);
+        sizes.before += file_size(file_path);
+        sizes.after += file_size(&new_path);
+        converted.push(new_path);
+    }
+    //the old files are only removed once the manifest points at the new ones
+    io::write::manifest_to_ron(
+        &target,
+        &Manifest {
+            version: migrations::archive_version(layout),
+            format,
+        },
+    );
+    for file_path in &files {
+        println!("Removing \"{}\"", file_path.display());
+        fs::remove_file(file_path)
+            .unwrap_or_else(|_| panic!("Failed to remove \"{}\"", file_path.display()));
+    }
+    sizes
+}
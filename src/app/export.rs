@@ -0,0 +1,4 @@
+pub mod markdown;
+pub mod mastodon;
+pub mod parquet;
+pub mod table;
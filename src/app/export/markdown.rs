@@ -0,0 +1,215 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+use twitter_v2::Tweet;
+
+use crate::app::archive::Archive;
+use crate::app::cache::Cache;
+use crate::app::format::{author, date, tweet_url};
+use crate::app::io;
+
+// Notes for an Obsidian (or any Markdown) vault:
+//
+//     conversations/conversation-<newest tweet id>.md   one per conversation, tweets as blockquotes
+//     users/<twitter handle>.md                         one per author, linking to their conversations
+//
+// A conversation is a chain of replies, named after its newest tweet since the branches of a
+// thread all share their root, the id the cache and the /conversation/<id> route know it by. Notes link to each other with [[wiki-links]], which Obsidian
+// resolves by file name.
+#[derive(Debug, Default)]
+pub struct Exported {
+    pub conversations: usize,
+    pub users: usize,
+}
+
+fn conversation_note(newest_tweet_id: u64) -> String {
+    format!("conversation-{newest_tweet_id}")
+}
+
+//json strings are valid yaml, and take care of quoting and escaping
+fn yaml<T: serde::Serialize + ?Sized>(value: &T) -> String {
+    serde_json::to_string(value).expect("Failed to write front matter")
+}
+
+//stops lines of a tweet from being read as headings, lists or quotes inside the blockquote
+fn escape_line(line: &str) -> String {
+    let trimmed = line.trim_start();
+    match trimmed.split_once(". ") {
+        Some((number, rest))
+            if !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()) =>
+        {
+            format!("{number}\\. {rest}")
+        }
+        _ if ["#", ">", "- ", "* ", "+ ", "[[", "---"]
+            .iter()
+            .any(|marker| trimmed.starts_with(marker)) =>
+        {
+            format!("\\{trimmed}")
+        }
+        _ => line.to_string(),
+    }
+}
+
+fn blockquote(tweet: &Tweet, author: &str) -> String {
+    let text: Vec<String> = tweet
+        .text
+        .lines()
+        .map(|line| match line.trim() {
+            "" => ">".to_string(),
+            _ => format!("> {}", escape_line(line)),
+        })
+        .collect();
+    let when = match tweet.created_at {
+        Some(created_at) => {
+            let (date, time) = (created_at.date(), created_at.time());
+            let (hour, minute) = (time.hour(), time.minute());
+            format!("{date} {hour:02}:{minute:02} UTC")
+        }
+        None => "original".to_string(),
+    };
+    format!(
+        "{}\n>\n> — [[{author}]] · [{when}]({})\n",
+        text.join("\n"),
+        tweet_url(tweet, author)
+    )
+}
+
+fn write_conversation(
+    conversation: &[Tweet],
+    related: &[u64],
+    cache: &Cache,
+    directory: &Path,
+) -> Option<BTreeSet<String>> {
+    //conversations are stored newest first, notes read oldest first
+    let newest_tweet = conversation.first()?;
+    let oldest_tweet = conversation.last()?;
+    let newest_tweet_id = newest_tweet.id.as_u64();
+    let tweets: Vec<&Tweet> = conversation.iter().rev().collect();
+    let authors: Vec<String> = tweets.iter().map(|tweet| author(tweet, cache)).collect();
+    let unique_authors: BTreeSet<String> = authors.iter().cloned().collect();
+    let tweet_ids: Vec<String> = tweets.iter().map(|tweet| tweet.id.to_string()).collect();
+    let mut note = String::from("---\n");
+    note.push_str(&format!(
+        "newest_tweet_id: {}\n",
+        yaml(&newest_tweet_id.to_string())
+    ));
+    if let Some(conversation_id) = newest_tweet.conversation_id {
+        note.push_str(&format!(
+            "conversation_id: {}\n",
+            yaml(&conversation_id.to_string())
+        ));
+    }
+    note.push_str(&format!("tweet_ids: {}\n", yaml(&tweet_ids)));
+    note.push_str(&format!("authors: {}\n", yaml(&unique_authors)));
+    if let Some(started) = date(oldest_tweet.created_at) {
+        note.push_str(&format!("started: {started}\n"));
+    }
+    if let Some(ended) = date(newest_tweet.created_at) {
+        note.push_str(&format!("ended: {ended}\n"));
+    }
+    note.push_str(&format!("tweets: {}\n", conversation.len()));
+    note.push_str("tags: [twitter, conversation]\n---\n\n");
+    note.push_str(&format!("# Conversation {newest_tweet_id}\n\n"));
+    let participants: Vec<String> = unique_authors
+        .iter()
+        .map(|author| format!("[[{author}]]"))
+        .collect();
+    note.push_str(&format!("With {}\n\n", participants.join(", ")));
+    for (tweet, author) in tweets.iter().zip(&authors) {
+        note.push_str(&blockquote(tweet, author));
+        note.push('\n');
+    }
+    if !related.is_empty() {
+        note.push_str("## Related conversations\n\n");
+        for &id in related {
+            note.push_str(&format!("- [[{}]]\n", conversation_note(id)));
+        }
+    }
+    io::write::string_to_file(
+        &note,
+        &directory
+            .join("conversations")
+            .join(format!("{}.md", conversation_note(newest_tweet_id))),
+    );
+    Some(unique_authors)
+}
+
+fn write_user(
+    twitter_handle: &str,
+    conversations: &BTreeSet<u64>,
+    cache: &Cache,
+    directory: &Path,
+) {
+    let mut note = String::from("---\n");
+    note.push_str(&format!("twitter_handle: {}\n", yaml(twitter_handle)));
+    if let Some(user) = cache.user_from_twitter_handle(twitter_handle) {
+        note.push_str(&format!("user_id: {}\n", yaml(&user.id.to_string())));
+        note.push_str(&format!("name: {}\n", yaml(&user.name)));
+    }
+    note.push_str("tags: [twitter, user]\n---\n\n");
+    note.push_str(&format!(
+        "# @{twitter_handle}\n\n[Profile](https://twitter.com/{twitter_handle})\n\n## Conversations\n\n"
+    ));
+    //newest first
+    for id in conversations.iter().rev() {
+        note.push_str(&format!("- [[{}]]\n", conversation_note(*id)));
+    }
+    io::write::string_to_file(
+        &note,
+        &directory.join("users").join(format!("{twitter_handle}.md")),
+    );
+}
+
+//writes the user's conversations, loading them from the Twitter API if they aren't archived yet
+pub async fn export(archive: &Archive, twitter_handle: &str, directory: &Path) -> Exported {
+    let conversations =
+        crate::app::load_conversations_from_twitter_handle(archive, twitter_handle).await;
+    let cache = archive.read();
+    //conversations that are branches of the same twitter conversation link to each other
+    let mut by_conversation_id: HashMap<u64, Vec<u64>> = HashMap::new();
+    for newest_tweet in conversations
+        .iter()
+        .filter_map(|conversation| conversation.first())
+    {
+        if let Some(conversation_id) = newest_tweet.conversation_id {
+            by_conversation_id
+                .entry(conversation_id.as_u64())
+                .or_default()
+                .push(newest_tweet.id.as_u64());
+        }
+    }
+    let mut exported = Exported::default();
+    let mut users: BTreeMap<String, BTreeSet<u64>> = BTreeMap::new();
+    let mut written = BTreeSet::new();
+    for conversation in &conversations {
+        let newest_tweet = match conversation.first() {
+            Some(newest_tweet) => newest_tweet,
+            None => continue,
+        };
+        let newest_tweet_id = newest_tweet.id.as_u64();
+        if !written.insert(newest_tweet_id) {
+            continue;
+        }
+        let related: Vec<u64> = newest_tweet
+            .conversation_id
+            .and_then(|id| by_conversation_id.get(&id.as_u64()))
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(|&id| id != newest_tweet_id)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        if let Some(authors) = write_conversation(conversation, &related, &cache, directory) {
+            for author in authors {
+                users.entry(author).or_default().insert(newest_tweet_id);
+            }
+            exported.conversations += 1;
+        }
+    }
+    for (twitter_handle, conversations) in &users {
+        write_user(twitter_handle, conversations, &cache, directory);
+        exported.users += 1;
+    }
+    exported
+}
@@ -0,0 +1,170 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::{json, Value};
+use twitter_v2::data::ReferencedTweetKind;
+use twitter_v2::Tweet;
+
+use crate::app::activitypub;
+use crate::app::archive::Archive;
+use crate::app::cache::Cache;
+use crate::app::io;
+use crate::app::site::Site;
+use crate::app::stats;
+
+// A user's archived tweets in the layout of Mastodon's own account archive, so the tools that
+// import those can bring a twitter history along:
+//
+//     actor.json                                                  the account, a Person
+//     outbox.json                                                 every tweet as a Create of a Note
+//     media_attachments/files/<media key>/original/<file name>    the downloaded media they attach
+//
+// Ids are the ones the archiver's ActivityPub actor serves. Self-reply threads keep their shape,
+// a reply to one of the user's exported tweets points at its Note and any other reply at the
+// tweet on twitter. Retweets are left out, they aren't the user's to bring along.
+#[derive(Debug, Default)]
+pub struct Exported {
+    pub statuses: usize,
+    pub replies: usize,
+    pub media: usize,
+    pub missing_media: usize,
+}
+
+fn is_retweet(tweet: &Tweet) -> bool {
+    Cache::referenced_ids(tweet, ReferencedTweetKind::Retweeted)
+        .next()
+        .is_some()
+}
+
+fn media_keys(tweet: &Tweet) -> impl Iterator<Item = String> + '_ {
+    tweet
+        .attachments
+        .iter()
+        .flat_map(|attachments| attachments.media_keys.iter().flatten())
+        .map(|media_key| media_key.to_string())
+}
+
+fn media_type(file: &Path) -> &'static str {
+    let extension = file
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_lowercase();
+    match extension.as_str() {
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "mp4" => "video/mp4",
+        "mov" => "video/quicktime",
+        _ => "application/octet-stream",
+    }
+}
+
+//the user's own tweets from their timeline and their conversations, oldest first
+fn own_tweets<'a>(cache: &'a Cache, twitter_handle: &str) -> Option<Vec<&'a Tweet>> {
+    let user = cache.user_from_twitter_handle(twitter_handle)?;
+    let mut seen = BTreeSet::new();
+    let mut tweets: Vec<&Tweet> = stats::user_tweets(cache, user, twitter_handle)
+        .into_iter()
+        .chain(
+            cache
+                .user_conversations
+                .get(&twitter_handle.to_lowercase())
+                .into_iter()
+                .flatten()
+                .flatten()
+                .filter(|tweet| tweet.author_id == Some(user.id)),
+        )
+        .filter(|tweet| !is_retweet(tweet))
+        .filter(|tweet| seen.insert(tweet.id.as_u64()))
+        .collect();
+    tweets.sort_by_key(|tweet| tweet.id.as_u64());
+    Some(tweets)
+}
+
+//None if the user isn't in the archive
+pub async fn export(
+    archive: &Archive,
+    site: &Site,
+    twitter_handle: &str,
+    directory: &Path,
+) -> Option<Exported> {
+    let actor = activitypub::actor(archive, site, twitter_handle)
+        .await
+        .unwrap_or_else(|error| panic!("Failed to export the actor: {error}"))?;
+    let cache = archive.read();
+    let tweets = own_tweets(&cache, twitter_handle)?;
+    let handle = cache
+        .user_from_twitter_handle(twitter_handle)?
+        .username
+        .clone();
+    let files: HashMap<String, PathBuf> = io::read::media_files(&archive.layout)
+        .into_iter()
+        .filter_map(|path| Some((path.file_stem()?.to_str()?.to_string(), path)))
+        .collect();
+    let mut exported = Exported::default();
+    let mut note_ids: HashMap<u64, Value> = HashMap::new();
+    let mut activities = Vec::new();
+    for tweet in tweets {
+        let mut note = activitypub::note(archive, site, &handle, tweet, &cache);
+        if let Some(parent_id) = Cache::replied_to_id(tweet) {
+            note["inReplyTo"] = match note_ids.get(&parent_id) {
+                Some(parent) => {
+                    exported.replies += 1;
+                    parent.clone()
+                }
+                None => json!(format!("https://twitter.com/i/web/status/{parent_id}")),
+            };
+        }
+        let mut attachments = Vec::new();
+        for media_key in media_keys(tweet) {
+            let file = match files.get(&media_key) {
+                Some(file) => file,
+                None => {
+                    exported.missing_media += 1;
+                    continue;
+                }
+            };
+            let file_name = file
+                .file_name()
+                .and_then(|file_name| file_name.to_str())
+                .unwrap_or(&media_key);
+            let path = format!("media_attachments/files/{media_key}/original/{file_name}");
+            let destination = directory.join(&path);
+            fs::create_dir_all(
+                destination
+                    .parent()
+                    .expect("Media is always in a directory"),
+            )
+            .unwrap_or_else(|_| panic!("Failed to create directory for \"{path}\""));
+            fs::copy(file, &destination)
+                .unwrap_or_else(|_| panic!("Failed to copy \"{}\"", file.display()));
+            attachments.push(json!({
+                "type": "Document",
+                "mediaType": media_type(file),
+                "url": format!("/{path}"),
+                "name": null,
+            }));
+            exported.media += 1;
+        }
+        note["attachment"] = json!(attachments);
+        note_ids.insert(tweet.id.as_u64(), note["id"].clone());
+        activities.push(activitypub::create(archive, site, &handle, tweet, note));
+    }
+    exported.statuses = activities.len();
+    let outbox = json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": "outbox.json",
+        "type": "OrderedCollection",
+        "totalItems": activities.len(),
+        "orderedItems": activities,
+    });
+    let pretty = |value: &Value| {
+        serde_json::to_string_pretty(value).expect("Failed to serialize the mastodon archive")
+    };
+    io::write::string_to_file(&pretty(&actor), &directory.join("actor.json"));
+    io::write::string_to_file(&pretty(&outbox), &directory.join("outbox.json"));
+    Some(exported)
+}
@@ -0,0 +1,405 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::path::Path;
+use std::sync::Arc;
+
+use ::parquet::arrow::ArrowWriter;
+use ::parquet::basic::Compression;
+use ::parquet::file::properties::WriterProperties;
+use arrow_array::builder::{ListBuilder, StringBuilder};
+use arrow_array::{
+    ArrayRef, BooleanArray, RecordBatch, StringArray, TimestampMillisecondArray, UInt64Array,
+};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use time::OffsetDateTime;
+
+use crate::app::archive::Archive;
+use crate::app::io;
+use crate::app::records::{ReferenceKind, TweetRecord, UserRecord};
+
+// Typed Parquet files of the whole archive, readable directly with DuckDB or Polars:
+//
+//     tweets/<partition>/tweets.parquet    partitioned hive style, by author=<id> or month=<yyyy-mm>
+//     users.parquet
+//     references.parquet                   one row per reply, quote or retweet
+//     media.parquet                        one row per attached media key
+//
+// e.g. `SELECT * FROM read_parquet('tweets/*/*.parquet', hive_partitioning = true)`
+//
+// Tweets without an author or date go in the standard `__HIVE_DEFAULT_PARTITION__`, which readers
+// take as null, so the partition column still infers as a number or date. Every export replaces
+// the previous one in the same directory, so partitions that no longer exist don't linger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Partition {
+    #[default]
+    Author,
+    Month,
+}
+
+#[derive(Debug, Default)]
+pub struct Exported {
+    pub tweets: usize,
+    pub partitions: usize,
+    pub users: usize,
+    pub references: usize,
+    pub media: usize,
+}
+
+fn timestamp_type() -> DataType {
+    DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into()))
+}
+
+fn timestamps(dates: impl Iterator<Item = Option<OffsetDateTime>>) -> ArrayRef {
+    let millis: Vec<Option<i64>> = dates
+        .map(|date| date.map(|date| (date.unix_timestamp_nanos() / 1_000_000) as i64))
+        .collect();
+    Arc::new(TimestampMillisecondArray::from(millis).with_timezone("UTC"))
+}
+
+fn string_lists<'a>(lists: impl Iterator<Item = Vec<&'a str>>) -> ArrayRef {
+    let mut builder = ListBuilder::new(StringBuilder::new());
+    for list in lists {
+        for value in list {
+            builder.values().append_value(value);
+        }
+        builder.append(true);
+    }
+    Arc::new(builder.finish())
+}
+
+fn reference_kind(kind: ReferenceKind) -> &'static str {
+    match kind {
+        ReferenceKind::RepliedTo => "replied_to",
+        ReferenceKind::Quoted => "quoted",
+        ReferenceKind::Retweeted => "retweeted",
+    }
+}
+
+fn write(batch: &RecordBatch, file_path: &Path) {
+    let file = file_path.display();
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent)
+            .unwrap_or_else(|_| panic!("Failed to create directory for \"{file}\""));
+    }
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    let mut writer = ArrowWriter::try_new(
+        File::create(file_path).unwrap_or_else(|_| panic!("Failed to create \"{file}\"")),
+        batch.schema(),
+        Some(properties),
+    )
+    .unwrap_or_else(|_| panic!("Failed to start writing \"{file}\""));
+    writer
+        .write(batch)
+        .unwrap_or_else(|_| panic!("Failed to write \"{file}\""));
+    writer
+        .close()
+        .unwrap_or_else(|_| panic!("Failed to finish writing \"{file}\""));
+}
+
+fn tweets_batch(tweets: &[&TweetRecord]) -> RecordBatch {
+    let schema = Schema::new(vec![
+        Field::new("id", DataType::UInt64, false),
+        Field::new("author_id", DataType::UInt64, true),
+        Field::new("created_at", timestamp_type(), true),
+        Field::new("text", DataType::Utf8, false),
+        Field::new("lang", DataType::Utf8, true),
+        Field::new("conversation_id", DataType::UInt64, true),
+        Field::new("in_reply_to_user_id", DataType::UInt64, true),
+        Field::new("retweets", DataType::UInt64, true),
+        Field::new("replies", DataType::UInt64, true),
+        Field::new("likes", DataType::UInt64, true),
+        Field::new("quotes", DataType::UInt64, true),
+        Field::new(
+            "hashtags",
+            DataType::List(Arc::new(Field::new("item", DataType::Utf8, true))),
+            true,
+        ),
+        Field::new(
+            "mentions",
+            DataType::List(Arc::new(Field::new("item", DataType::Utf8, true))),
+            true,
+        ),
+        Field::new(
+            "urls",
+            DataType::List(Arc::new(Field::new("item", DataType::Utf8, true))),
+            true,
+        ),
+    ]);
+    let metric = |metric: fn(&TweetRecord) -> Option<usize>| -> ArrayRef {
+        Arc::new(UInt64Array::from(
+            tweets
+                .iter()
+                .map(|tweet| metric(tweet).map(|count| count as u64))
+                .collect::<Vec<_>>(),
+        ))
+    };
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from(
+            tweets.iter().map(|tweet| tweet.id).collect::<Vec<_>>(),
+        )),
+        Arc::new(UInt64Array::from(
+            tweets
+                .iter()
+                .map(|tweet| tweet.author_id)
+                .collect::<Vec<_>>(),
+        )),
+        timestamps(tweets.iter().map(|tweet| tweet.created_at)),
+        Arc::new(StringArray::from(
+            tweets
+                .iter()
+                .map(|tweet| tweet.text.as_str())
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(StringArray::from(
+            tweets
+                .iter()
+                .map(|tweet| tweet.lang.as_deref())
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(UInt64Array::from(
+            tweets
+                .iter()
+                .map(|tweet| tweet.conversation_id)
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(UInt64Array::from(
+            tweets
+                .iter()
+                .map(|tweet| tweet.in_reply_to_user_id)
+                .collect::<Vec<_>>(),
+        )),
+        metric(|tweet| tweet.metrics.as_ref().map(|metrics| metrics.retweets)),
+        metric(|tweet| tweet.metrics.as_ref().map(|metrics| metrics.replies)),
+        metric(|tweet| tweet.metrics.as_ref().map(|metrics| metrics.likes)),
+        metric(|tweet| tweet.metrics.as_ref().and_then(|metrics| metrics.quotes)),
+        string_lists(tweets.iter().map(|tweet| {
+            tweet
+                .hashtags
+                .iter()
+                .map(|hashtag| hashtag.tag.as_str())
+                .collect()
+        })),
+        string_lists(tweets.iter().map(|tweet| {
+            tweet
+                .mentions
+                .iter()
+                .map(|mention| mention.tag.as_str())
+                .collect()
+        })),
+        string_lists(tweets.iter().map(|tweet| {
+            tweet
+                .urls
+                .iter()
+                .map(|url| url.expanded_url.as_str())
+                .collect()
+        })),
+    ];
+    RecordBatch::try_new(Arc::new(schema), columns).expect("Failed to build the tweets table")
+}
+
+fn users_batch(users: &[UserRecord]) -> RecordBatch {
+    let schema = Schema::new(vec![
+        Field::new("id", DataType::UInt64, false),
+        Field::new("handle", DataType::Utf8, false),
+        Field::new("name", DataType::Utf8, false),
+        Field::new("created_at", timestamp_type(), true),
+        Field::new("description", DataType::Utf8, true),
+        Field::new("location", DataType::Utf8, true),
+        Field::new("url", DataType::Utf8, true),
+        Field::new("verified", DataType::Boolean, true),
+        Field::new("protected", DataType::Boolean, true),
+        Field::new("pinned_tweet_id", DataType::UInt64, true),
+    ]);
+    let strings = |field: fn(&UserRecord) -> Option<&str>| -> ArrayRef {
+        Arc::new(StringArray::from(
+            users.iter().map(field).collect::<Vec<_>>(),
+        ))
+    };
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from(
+            users.iter().map(|user| user.id).collect::<Vec<_>>(),
+        )),
+        strings(|user| Some(user.username.as_str())),
+        strings(|user| Some(user.name.as_str())),
+        timestamps(users.iter().map(|user| user.created_at)),
+        strings(|user| user.description.as_deref()),
+        strings(|user| user.location.as_deref()),
+        strings(|user| user.url.as_deref()),
+        Arc::new(BooleanArray::from(
+            users.iter().map(|user| user.verified).collect::<Vec<_>>(),
+        )),
+        Arc::new(BooleanArray::from(
+            users.iter().map(|user| user.protected).collect::<Vec<_>>(),
+        )),
+        Arc::new(UInt64Array::from(
+            users
+                .iter()
+                .map(|user| user.pinned_tweet_id)
+                .collect::<Vec<_>>(),
+        )),
+    ];
+    RecordBatch::try_new(Arc::new(schema), columns).expect("Failed to build the users table")
+}
+
+fn references_batch(tweets: &[TweetRecord]) -> RecordBatch {
+    let references: Vec<(&TweetRecord, u64, ReferenceKind)> = tweets
+        .iter()
+        .flat_map(|tweet| {
+            tweet
+                .referenced_tweets
+                .iter()
+                .map(move |referenced_tweet| (tweet, referenced_tweet.id, referenced_tweet.kind))
+        })
+        .collect();
+    let schema = Schema::new(vec![
+        Field::new("tweet_id", DataType::UInt64, false),
+        Field::new("author_id", DataType::UInt64, true),
+        Field::new("kind", DataType::Utf8, false),
+        Field::new("referenced_tweet_id", DataType::UInt64, false),
+    ]);
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from(
+            references
+                .iter()
+                .map(|(tweet, _, _)| tweet.id)
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(UInt64Array::from(
+            references
+                .iter()
+                .map(|(tweet, _, _)| tweet.author_id)
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(StringArray::from(
+            references
+                .iter()
+                .map(|&(_, _, kind)| reference_kind(kind))
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(UInt64Array::from(
+            references.iter().map(|&(_, id, _)| id).collect::<Vec<_>>(),
+        )),
+    ];
+    RecordBatch::try_new(Arc::new(schema), columns).expect("Failed to build the references table")
+}
+
+//media files are named after their media key, so the file column is only set for downloaded media
+fn media_batch(tweets: &[TweetRecord], files: &HashMap<String, String>) -> RecordBatch {
+    let media: Vec<(&TweetRecord, &str)> = tweets
+        .iter()
+        .flat_map(|tweet| {
+            tweet
+                .media_keys
+                .iter()
+                .map(move |media_key| (tweet, media_key.as_str()))
+        })
+        .collect();
+    let schema = Schema::new(vec![
+        Field::new("tweet_id", DataType::UInt64, false),
+        Field::new("author_id", DataType::UInt64, true),
+        Field::new("created_at", timestamp_type(), true),
+        Field::new("media_key", DataType::Utf8, false),
+        Field::new("file", DataType::Utf8, true),
+    ]);
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from(
+            media.iter().map(|(tweet, _)| tweet.id).collect::<Vec<_>>(),
+        )),
+        Arc::new(UInt64Array::from(
+            media
+                .iter()
+                .map(|(tweet, _)| tweet.author_id)
+                .collect::<Vec<_>>(),
+        )),
+        timestamps(media.iter().map(|(tweet, _)| tweet.created_at)),
+        Arc::new(StringArray::from(
+            media
+                .iter()
+                .map(|&(_, media_key)| media_key)
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(StringArray::from(
+            media
+                .iter()
+                .map(|(_, media_key)| files.get(*media_key).map(String::as_str))
+                .collect::<Vec<_>>(),
+        )),
+    ];
+    RecordBatch::try_new(Arc::new(schema), columns).expect("Failed to build the media table")
+}
+
+const DEFAULT_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+fn partition_name(tweet: &TweetRecord, partition: Partition) -> String {
+    match partition {
+        Partition::Author => match tweet.author_id {
+            Some(author_id) => format!("author={author_id}"),
+            None => format!("author={DEFAULT_PARTITION}"),
+        },
+        Partition::Month => match tweet.created_at {
+            Some(created_at) => {
+                let (year, month) = (created_at.year(), created_at.month() as u8);
+                format!("month={year}-{month:02}")
+            }
+            None => format!("month={DEFAULT_PARTITION}"),
+        },
+    }
+}
+
+//only what an export writes is removed, anything else the directory holds is left alone
+fn clear(directory: &Path) {
+    let tweets = directory.join("tweets");
+    if tweets.exists() {
+        fs::remove_dir_all(&tweets)
+            .unwrap_or_else(|_| panic!("Failed to remove \"{}\"", tweets.display()));
+    }
+    for file_name in ["users.parquet", "references.parquet", "media.parquet"] {
+        let file_path = directory.join(file_name);
+        if file_path.exists() {
+            fs::remove_file(&file_path)
+                .unwrap_or_else(|_| panic!("Failed to remove \"{}\"", file_path.display()));
+        }
+    }
+}
+
+pub fn export(archive: &Archive, directory: &Path, partition: Partition) -> Exported {
+    let cache = archive.read();
+    let tweets: Vec<TweetRecord> = cache.tweets.iter().map(TweetRecord::from).collect();
+    let users: Vec<UserRecord> = cache.users.iter().map(UserRecord::from).collect();
+    let files: HashMap<String, String> = io::read::media_files(&archive.layout)
+        .into_iter()
+        .filter_map(|path| {
+            let media_key = path.file_stem()?.to_str()?.to_string();
+            let file_name = path.file_name()?.to_str()?;
+            Some((media_key, format!("media/{file_name}")))
+        })
+        .collect();
+    let mut partitions: BTreeMap<String, Vec<&TweetRecord>> = BTreeMap::new();
+    for tweet in &tweets {
+        partitions
+            .entry(partition_name(tweet, partition))
+            .or_default()
+            .push(tweet);
+    }
+    clear(directory);
+    for (name, tweets) in &partitions {
+        write(
+            &tweets_batch(tweets),
+            &directory.join("tweets").join(name).join("tweets.parquet"),
+        );
+    }
+    let references = references_batch(&tweets);
+    let media = media_batch(&tweets, &files);
+    write(&users_batch(&users), &directory.join("users.parquet"));
+    write(&references, &directory.join("references.parquet"));
+    write(&media, &directory.join("media.parquet"));
+    Exported {
+        tweets: tweets.len(),
+        partitions: partitions.len(),
+        users: users.len(),
+        references: references.num_rows(),
+        media: media.num_rows(),
+    }
+}
@@ -0,0 +1,171 @@
+use serde::Serialize;
+
+use crate::app::archive::Archive;
+use crate::app::format::date;
+use crate::app::records::{ReferenceKind, TweetRecord, UserRecord};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableFormat {
+    Csv,
+    JsonLines,
+}
+
+impl TableFormat {
+    pub fn from_name(name: &str) -> Option<TableFormat> {
+        match name {
+            "csv" => Some(TableFormat::Csv),
+            "jsonl" => Some(TableFormat::JsonLines),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            TableFormat::Csv => "csv",
+            TableFormat::JsonLines => "jsonl",
+        }
+    }
+}
+
+// One flat row per tweet, from the archived records. Ids are written as strings,
+// like the Twitter API does, because spreadsheets and javascript round numbers this big.
+#[derive(Debug, Clone, Serialize)]
+pub struct TweetRow {
+    pub id: String,
+    pub author_id: Option<String>,
+    pub author_handle: Option<String>,
+    pub created_at: Option<String>,
+    pub text: String,
+    pub lang: Option<String>,
+    pub conversation_id: Option<String>,
+    pub reply_to_id: Option<String>,
+    pub reply_to_user_id: Option<String>,
+    pub quote_id: Option<String>,
+    pub retweet_id: Option<String>,
+    pub retweets: Option<usize>,
+    pub replies: Option<usize>,
+    pub likes: Option<usize>,
+    pub quotes: Option<usize>,
+    pub media: usize,
+    pub urls: usize,
+    pub hashtags: usize,
+    pub mentions: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UserRow {
+    pub id: String,
+    pub handle: String,
+    pub name: String,
+    pub created_at: Option<String>,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    pub url: Option<String>,
+    pub verified: Option<bool>,
+    pub protected: Option<bool>,
+    pub pinned_tweet_id: Option<String>,
+    pub profile_image_url: Option<String>,
+}
+
+fn reference(record: &TweetRecord, kind: ReferenceKind) -> Option<String> {
+    record
+        .referenced_tweets
+        .iter()
+        .find(|referenced_tweet| referenced_tweet.kind == kind)
+        .map(|referenced_tweet| referenced_tweet.id.to_string())
+}
+
+impl TweetRow {
+    pub fn new(record: TweetRecord, author_handle: Option<String>) -> TweetRow {
+        let metrics = record.metrics.as_ref();
+        TweetRow {
+            id: record.id.to_string(),
+            author_id: record.author_id.map(|id| id.to_string()),
+            author_handle,
+            created_at: date(record.created_at),
+            lang: record.lang.clone(),
+            conversation_id: record.conversation_id.map(|id| id.to_string()),
+            reply_to_id: reference(&record, ReferenceKind::RepliedTo),
+            reply_to_user_id: record.in_reply_to_user_id.map(|id| id.to_string()),
+            quote_id: reference(&record, ReferenceKind::Quoted),
+            retweet_id: reference(&record, ReferenceKind::Retweeted),
+            retweets: metrics.map(|metrics| metrics.retweets),
+            replies: metrics.map(|metrics| metrics.replies),
+            likes: metrics.map(|metrics| metrics.likes),
+            quotes: metrics.and_then(|metrics| metrics.quotes),
+            media: record.media_keys.len(),
+            urls: record.urls.len(),
+            hashtags: record.hashtags.len(),
+            mentions: record.mentions.len(),
+            text: record.text,
+        }
+    }
+}
+
+impl From<UserRecord> for UserRow {
+    fn from(record: UserRecord) -> UserRow {
+        UserRow {
+            id: record.id.to_string(),
+            handle: record.username,
+            name: record.name,
+            created_at: date(record.created_at),
+            description: record.description,
+            location: record.location,
+            url: record.url,
+            verified: record.verified,
+            protected: record.protected,
+            pinned_tweet_id: record.pinned_tweet_id.map(|id| id.to_string()),
+            profile_image_url: record.profile_image_url,
+        }
+    }
+}
+
+//the user's archived tweets, oldest first, or None if the user isn't in the archive
+pub fn tweet_rows(archive: &Archive, twitter_handle: &str) -> Option<Vec<TweetRow>> {
+    let cache = archive.read();
+    let user = cache.user_from_twitter_handle(twitter_handle)?;
+    let mut tweets: Vec<_> = cache
+        .tweets
+        .iter()
+        .filter(|tweet| tweet.author_id == Some(user.id))
+        .collect();
+    tweets.sort_by_key(|tweet| tweet.id.as_u64());
+    Some(
+        tweets
+            .into_iter()
+            .map(|tweet| TweetRow::new(tweet.into(), Some(user.username.clone())))
+            .collect(),
+    )
+}
+
+//every archived user
+pub fn user_rows(archive: &Archive) -> Vec<UserRow> {
+    let cache = archive.read();
+    cache
+        .users
+        .iter()
+        .map(|user| UserRecord::from(user).into())
+        .collect()
+}
+
+// The rows one line at a time (the first csv line also carries the header),
+// so they can be streamed without building the whole file in memory.
+pub fn lines<T: Serialize + Send + 'static>(
+    rows: Vec<T>,
+    format: TableFormat,
+) -> impl Iterator<Item = String> + Send + 'static {
+    let mut first = true;
+    rows.into_iter().map(move |row| match format {
+        TableFormat::Csv => {
+            let mut writer = csv::WriterBuilder::new()
+                .has_headers(std::mem::take(&mut first))
+                .from_writer(Vec::new());
+            writer.serialize(&row).expect("Failed to write a csv row");
+            let line = writer.into_inner().expect("Failed to write a csv row");
+            String::from_utf8(line).expect("Csv rows are utf-8")
+        }
+        TableFormat::JsonLines => {
+            serde_json::to_string(&row).expect("Failed to write a json line") + "\n"
+        }
+    })
+}
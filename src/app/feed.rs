@@ -0,0 +1,294 @@
+use time::format_description::well_known::{Rfc2822, Rfc3339};
+use time::OffsetDateTime;
+use twitter_v2::data::ReferencedTweetKind;
+use twitter_v2::Tweet;
+
+use super::archive::Archive;
+use super::cache::Cache;
+use super::format::{author, escape, tweet_url};
+use super::search::{self, Mode, SearchError};
+use super::site::Site;
+
+//how many of the newest tweets a feed holds
+const ENTRIES: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedFormat {
+    Atom,
+    Rss,
+}
+
+impl FeedFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            FeedFormat::Atom => "atom",
+            FeedFormat::Rss => "rss",
+        }
+    }
+}
+
+struct Entry {
+    url: String,
+    title: String,
+    author: String,
+    updated: Option<OffsetDateTime>,
+    content: String,
+}
+
+struct Feed {
+    //the feed's own address
+    url: String,
+    //the page it's a feed of
+    link: String,
+    title: String,
+    description: String,
+    entries: Vec<Entry>,
+}
+
+//the tweet's text as html, with its links made clickable
+pub fn text_html(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            line.split(' ')
+                .map(
+                    |word| match word.starts_with("https://") || word.starts_with("http://") {
+                        true => format!("<a href=\"{0}\">{0}</a>", escape(word)),
+                        false => escape(word),
+                    },
+                )
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect::<Vec<_>>()
+        .join("<br>")
+}
+
+fn quote_html(tweet: &Tweet, cache: &Cache) -> String {
+    let author = author(tweet, cache);
+    format!(
+        "<blockquote>{}<br>— <a href=\"{}\">@{}</a></blockquote>",
+        text_html(&tweet.text),
+        tweet_url(tweet, &author),
+        escape(&author)
+    )
+}
+
+fn referenced<'a>(tweet: &Tweet, kind: ReferencedTweetKind, cache: &'a Cache) -> Option<&'a Tweet> {
+    Cache::referenced_ids(tweet, kind)
+        .next()
+        .and_then(|id| cache.tweet(id))
+}
+
+//the tweet, with the tweet it replies to above it and the tweet it quotes below, if archived
+fn content(tweet: &Tweet, cache: &Cache) -> String {
+    let mut html = String::new();
+    if let Some(parent) = referenced(tweet, ReferencedTweetKind::RepliedTo, cache) {
+        let parent_author = author(parent, cache);
+        html.push_str(&format!(
+            "<p>Replying to <a href=\"https://twitter.com/{0}\">@{0}</a>:</p>{1}",
+            escape(&parent_author),
+            quote_html(parent, cache)
+        ));
+    }
+    html.push_str(&format!("<p>{}</p>", text_html(&tweet.text)));
+    if let Some(quoted) = referenced(tweet, ReferencedTweetKind::Quoted, cache) {
+        html.push_str(&quote_html(quoted, cache));
+    }
+    html
+}
+
+fn title(tweet: &Tweet, author: &str) -> String {
+    let first_line = tweet.text.lines().next().unwrap_or_default();
+    let mut title: String = first_line.chars().take(80).collect();
+    if first_line.chars().count() > 80 {
+        title.push('…');
+    }
+    format!("@{author}: {title}")
+}
+
+fn entries<'a>(tweets: impl Iterator<Item = &'a Tweet>, cache: &Cache) -> Vec<Entry> {
+    let mut tweets: Vec<&Tweet> = tweets.collect();
+    tweets.sort_by_key(|tweet| std::cmp::Reverse(tweet.id.as_u64()));
+    tweets
+        .into_iter()
+        .take(ENTRIES)
+        .map(|tweet| {
+            let author = author(tweet, cache);
+            Entry {
+                url: tweet_url(tweet, &author),
+                title: title(tweet, &author),
+                updated: tweet.created_at,
+                content: content(tweet, cache),
+                author,
+            }
+        })
+        .collect()
+}
+
+fn rfc3339(date: OffsetDateTime) -> String {
+    date.format(&Rfc3339).expect("Failed to format a feed date")
+}
+
+fn rfc2822(date: OffsetDateTime) -> String {
+    date.format(&Rfc2822).expect("Failed to format a feed date")
+}
+
+fn to_atom(feed: &Feed) -> String {
+    let updated = feed
+        .entries
+        .iter()
+        .filter_map(|entry| entry.updated)
+        .max()
+        .unwrap_or(OffsetDateTime::UNIX_EPOCH);
+    let mut atom = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <id>{url}</id>
+  <title>{title}</title>
+  <subtitle>{description}</subtitle>
+  <updated>{updated}</updated>
+  <link rel="self" type="application/atom+xml" href="{url}"/>
+  <link rel="alternate" type="text/html" href="{link}"/>
+  <generator>better-twitter-archiver</generator>
+"#,
+        url = escape(&feed.url),
+        link = escape(&feed.link),
+        title = escape(&feed.title),
+        description = escape(&feed.description),
+        updated = rfc3339(updated),
+    );
+    for entry in &feed.entries {
+        atom.push_str(&format!(
+            r#"  <entry>
+    <id>{url}</id>
+    <title>{title}</title>
+    <updated>{updated}</updated>
+    <author><name>@{author}</name><uri>https://twitter.com/{author}</uri></author>
+    <link rel="alternate" type="text/html" href="{url}"/>
+    <content type="html">{content}</content>
+  </entry>
+"#,
+            url = escape(&entry.url),
+            title = escape(&entry.title),
+            updated = rfc3339(entry.updated.unwrap_or(updated)),
+            author = escape(&entry.author),
+            content = escape(&entry.content),
+        ));
+    }
+    atom.push_str("</feed>\n");
+    atom
+}
+
+fn to_rss(feed: &Feed) -> String {
+    let mut rss = format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom" xmlns:dc="http://purl.org/dc/elements/1.1/">
+  <channel>
+    <title>{title}</title>
+    <link>{link}</link>
+    <description>{description}</description>
+    <atom:link rel="self" type="application/rss+xml" href="{url}"/>
+    <generator>better-twitter-archiver</generator>
+"#,
+        url = escape(&feed.url),
+        link = escape(&feed.link),
+        title = escape(&feed.title),
+        description = escape(&feed.description),
+    );
+    if let Some(updated) = feed.entries.iter().filter_map(|entry| entry.updated).max() {
+        rss.push_str(&format!(
+            "    <lastBuildDate>{}</lastBuildDate>\n",
+            rfc2822(updated)
+        ));
+    }
+    for entry in &feed.entries {
+        rss.push_str(&format!(
+            r#"    <item>
+      <title>{title}</title>
+      <link>{url}</link>
+      <guid isPermaLink="true">{url}</guid>
+      <dc:creator>@{author}</dc:creator>
+"#,
+            url = escape(&entry.url),
+            title = escape(&entry.title),
+            author = escape(&entry.author),
+        ));
+        if let Some(updated) = entry.updated {
+            rss.push_str(&format!("      <pubDate>{}</pubDate>\n", rfc2822(updated)));
+        }
+        rss.push_str(&format!(
+            "      <description>{}</description>\n    </item>\n",
+            escape(&entry.content)
+        ));
+    }
+    rss.push_str("  </channel>\n</rss>\n");
+    rss
+}
+
+fn render(feed: &Feed, format: FeedFormat) -> String {
+    match format {
+        FeedFormat::Atom => to_atom(feed),
+        FeedFormat::Rss => to_rss(feed),
+    }
+}
+
+//None if the user isn't in the archive
+pub fn user_feed(
+    archive: &Archive,
+    twitter_handle: &str,
+    format: FeedFormat,
+    site: &Site,
+) -> Option<String> {
+    let cache = archive.read();
+    let user = cache.user_from_twitter_handle(twitter_handle)?;
+    let extension = format.extension();
+    let handle = &user.username;
+    let feed = Feed {
+        url: site.archive_url(archive, &format!("/user/{handle}/feed.{extension}")),
+        link: format!("https://twitter.com/{handle}"),
+        title: format!("{} (@{handle})", user.name),
+        description: user
+            .description
+            .clone()
+            .unwrap_or_else(|| format!("Archived tweets of @{handle}")),
+        entries: entries(
+            cache
+                .tweets
+                .iter()
+                .filter(|tweet| tweet.author_id == Some(user.id)),
+            &cache,
+        ),
+    };
+    Some(render(&feed, format))
+}
+
+//the newest archived tweets matching a search query
+pub fn search_feed(
+    archive: &Archive,
+    query: &str,
+    format: FeedFormat,
+    site: &Site,
+) -> Result<String, SearchError> {
+    let results = search::search(archive, query, Mode::Query)?;
+    let cache = archive.read();
+    let encoded: String = query
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect();
+    let feed = Feed {
+        url: site.archive_url(
+            archive,
+            &format!("/search/feed.{}?query={encoded}", format.extension()),
+        ),
+        link: site.archive_url(archive, &format!("/search?query={encoded}")),
+        title: format!("Search: {query}"),
+        description: format!("Archived tweets matching \"{query}\""),
+        entries: entries(results.iter(), &cache),
+    };
+    Ok(render(&feed, format))
+}
@@ -0,0 +1,74 @@
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use twitter_v2::Tweet;
+
+use super::cache::Cache;
+
+// How tweets are read and written out by the stats, graphs, feeds, exports and ActivityPub actors
+// alike.
+
+//the author's handle if we know it, otherwise their id
+pub fn author(tweet: &Tweet, cache: &Cache) -> String {
+    match tweet.author_id {
+        Some(author_id) => match cache.user(author_id.as_u64()) {
+            Some(user) => user.username.clone(),
+            None => author_id.as_u64().to_string(),
+        },
+        None => "unknown".to_string(),
+    }
+}
+
+pub fn tweet_url(tweet: &Tweet, author: &str) -> String {
+    format!("https://twitter.com/{author}/status/{}", tweet.id)
+}
+
+pub fn date(created_at: Option<OffsetDateTime>) -> Option<String> {
+    created_at.and_then(|created_at| created_at.format(&Rfc3339).ok())
+}
+
+//hashtags or mentions written in the text, for tweets archived without their entities
+pub fn tags_in_text(text: &str, prefix: char) -> Vec<String> {
+    let mut tags = Vec::new();
+    let mut previous = ' ';
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == prefix && !previous.is_alphanumeric() && previous != '_' {
+            let mut tag = String::new();
+            while let Some(&next) = chars.peek() {
+                if !next.is_alphanumeric() && next != '_' {
+                    break;
+                }
+                tag.push(next);
+                chars.next();
+            }
+            if !tag.is_empty() {
+                tags.push(tag);
+            }
+        }
+        previous = c;
+    }
+    tags
+}
+
+pub fn mentions(tweet: &Tweet) -> Vec<String> {
+    match tweet
+        .entities
+        .as_ref()
+        .and_then(|entities| entities.mentions.as_ref())
+    {
+        Some(mentions) => mentions
+            .iter()
+            .map(|mention| mention.username.clone())
+            .collect(),
+        None => tags_in_text(&tweet.text, '@'),
+    }
+}
+
+//escapes text for html and xml
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
@@ -0,0 +1,296 @@
+use std::collections::{BTreeMap, HashSet};
+
+use serde::Serialize;
+use twitter_v2::data::ReferencedTweetKind;
+use twitter_v2::Tweet;
+
+use super::archive::Archive;
+use super::cache::Cache;
+use super::format::{escape, mentions};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interaction {
+    Reply,
+    Quote,
+    Mention,
+}
+
+impl Interaction {
+    pub const ALL: [Interaction; 3] =
+        [Interaction::Reply, Interaction::Quote, Interaction::Mention];
+
+    pub fn from_name(name: &str) -> Option<Interaction> {
+        match name {
+            "reply" | "replies" => Some(Interaction::Reply),
+            "quote" | "quotes" => Some(Interaction::Quote),
+            "mention" | "mentions" => Some(Interaction::Mention),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Interaction::Reply => "reply",
+            Interaction::Quote => "quote",
+            Interaction::Mention => "mention",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    Ron,
+    GraphMl,
+    Gexf,
+    Dot,
+}
+
+impl GraphFormat {
+    pub fn from_name(name: &str) -> Option<GraphFormat> {
+        match name {
+            "ron" => Some(GraphFormat::Ron),
+            "graphml" => Some(GraphFormat::GraphMl),
+            "gexf" => Some(GraphFormat::Gexf),
+            "dot" => Some(GraphFormat::Dot),
+            _ => None,
+        }
+    }
+}
+
+// A user in the graph. Users in the archive are identified by their id, users we only
+// know from an @mention by "@handle", and users we only know the id of are labelled with it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Node {
+    pub id: String,
+    pub label: String,
+}
+
+//how many times `source` interacted with `target` in this way
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Edge {
+    pub source: String,
+    pub target: String,
+    pub kind: Interaction,
+    pub weight: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+//the @handles twitter puts at the start of a reply, which would only repeat the reply edges
+fn leading_mentions(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .take_while(|word| word.starts_with('@'))
+        .map(|word| {
+            word.trim_start_matches('@')
+                .trim_end_matches(|c: char| !c.is_alphanumeric() && c != '_')
+                .to_lowercase()
+        })
+        .collect()
+}
+
+//users we know are identified by their id, others by their handle
+fn handle_node(twitter_handle: &str, cache: &Cache) -> String {
+    match cache.user_from_twitter_handle(twitter_handle) {
+        Some(user) => user.id.as_u64().to_string(),
+        None => format!("@{twitter_handle}"),
+    }
+}
+
+//the users a tweet interacts with, by node id
+fn interactions(tweet: &Tweet, cache: &Cache) -> Vec<(Interaction, String)> {
+    let mut interactions = Vec::new();
+    let is_reply = Cache::referenced_ids(tweet, ReferencedTweetKind::RepliedTo)
+        .next()
+        .is_some();
+    let leading_mentions = match is_reply {
+        true => leading_mentions(&tweet.text),
+        false => Vec::new(),
+    };
+    //without the replied to tweet, the first handle twitter prefixed the reply with is our best guess
+    let replied_to_author = Cache::referenced_ids(tweet, ReferencedTweetKind::RepliedTo)
+        .find_map(|id| cache.tweet(id).and_then(|tweet| tweet.author_id))
+        .or(tweet.in_reply_to_user_id)
+        .map(|author_id| author_id.as_u64().to_string())
+        .or_else(|| {
+            leading_mentions
+                .first()
+                .map(|twitter_handle| handle_node(twitter_handle, cache))
+        });
+    if let Some(author) = replied_to_author {
+        interactions.push((Interaction::Reply, author));
+    }
+    for quoted in
+        Cache::referenced_ids(tweet, ReferencedTweetKind::Quoted).filter_map(|id| cache.tweet(id))
+    {
+        if let Some(author_id) = quoted.author_id {
+            interactions.push((Interaction::Quote, author_id.as_u64().to_string()));
+        }
+    }
+    for mention in mentions(tweet) {
+        let mention = mention.to_lowercase();
+        if !leading_mentions.contains(&mention) {
+            interactions.push((Interaction::Mention, handle_node(&mention, cache)));
+        }
+    }
+    interactions
+}
+
+fn label(id: &str, cache: &Cache) -> String {
+    match id.strip_prefix('@') {
+        Some(handle) => handle.to_string(),
+        None => id
+            .parse()
+            .ok()
+            .and_then(|id| cache.user(id))
+            .map(|user| user.username.clone())
+            .unwrap_or_else(|| id.to_string()),
+    }
+}
+
+//every interaction of the given kinds between users across the whole archive, without self loops
+pub fn build(cache: &Cache, kinds: &[Interaction]) -> Graph {
+    let mut weights: BTreeMap<(String, String, Interaction), usize> = BTreeMap::new();
+    for tweet in &cache.tweets {
+        let source = match tweet.author_id {
+            Some(author_id) => author_id.as_u64().to_string(),
+            None => continue,
+        };
+        for (kind, target) in interactions(tweet, cache) {
+            if kinds.contains(&kind) && target != source {
+                *weights.entry((source.clone(), target, kind)).or_default() += 1;
+            }
+        }
+    }
+    let mut node_ids: Vec<&String> = weights
+        .keys()
+        .flat_map(|(source, target, _)| [source, target])
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    node_ids.sort();
+    Graph {
+        nodes: node_ids
+            .into_iter()
+            .map(|id| Node {
+                id: id.clone(),
+                label: label(id, cache),
+            })
+            .collect(),
+        edges: weights
+            .iter()
+            .map(|((source, target, kind), &weight)| Edge {
+                source: source.clone(),
+                target: target.clone(),
+                kind: *kind,
+                weight,
+            })
+            .collect(),
+    }
+}
+
+pub fn interaction_graph(archive: &Archive, kinds: &[Interaction]) -> Graph {
+    build(&archive.read(), kinds)
+}
+
+pub fn to_graphml(graph: &Graph) -> String {
+    let mut graphml = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<graphml xmlns="http://graphml.graphdrawing.org/xmlns">
+  <key id="label" for="node" attr.name="label" attr.type="string"/>
+  <key id="kind" for="edge" attr.name="kind" attr.type="string"/>
+  <key id="weight" for="edge" attr.name="weight" attr.type="int"/>
+  <graph id="interactions" edgedefault="directed">
+"#,
+    );
+    for node in &graph.nodes {
+        graphml.push_str(&format!(
+            "    <node id=\"{}\"><data key=\"label\">{}</data></node>\n",
+            escape(&node.id),
+            escape(&node.label)
+        ));
+    }
+    for edge in &graph.edges {
+        graphml.push_str(&format!(
+            "    <edge source=\"{}\" target=\"{}\"><data key=\"kind\">{}</data><data key=\"weight\">{}</data></edge>\n",
+            escape(&edge.source),
+            escape(&edge.target),
+            edge.kind.name(),
+            edge.weight
+        ));
+    }
+    graphml.push_str("  </graph>\n</graphml>\n");
+    graphml
+}
+
+pub fn to_gexf(graph: &Graph) -> String {
+    let mut gexf = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<gexf xmlns="http://gexf.net/1.3" version="1.3">
+  <graph defaultedgetype="directed">
+    <attributes class="edge">
+      <attribute id="kind" title="kind" type="string"/>
+    </attributes>
+    <nodes>
+"#,
+    );
+    for node in &graph.nodes {
+        gexf.push_str(&format!(
+            "      <node id=\"{}\" label=\"{}\"/>\n",
+            escape(&node.id),
+            escape(&node.label)
+        ));
+    }
+    gexf.push_str("    </nodes>\n    <edges>\n");
+    for (i, edge) in graph.edges.iter().enumerate() {
+        gexf.push_str(&format!(
+            "      <edge id=\"{i}\" source=\"{}\" target=\"{}\" weight=\"{}\"><attvalues><attvalue for=\"kind\" value=\"{}\"/></attvalues></edge>\n",
+            escape(&edge.source),
+            escape(&edge.target),
+            edge.weight,
+            edge.kind.name()
+        ));
+    }
+    gexf.push_str("    </edges>\n  </graph>\n</gexf>\n");
+    gexf
+}
+
+fn dot_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+pub fn to_dot(graph: &Graph) -> String {
+    let mut dot = String::from("digraph interactions {\n");
+    for node in &graph.nodes {
+        dot.push_str(&format!(
+            "  {} [label={}];\n",
+            dot_string(&node.id),
+            dot_string(&node.label)
+        ));
+    }
+    for edge in &graph.edges {
+        dot.push_str(&format!(
+            "  {} -> {} [kind={}, weight={}];\n",
+            dot_string(&edge.source),
+            dot_string(&edge.target),
+            edge.kind.name(),
+            edge.weight
+        ));
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+pub fn export(graph: &Graph, format: GraphFormat) -> String {
+    match format {
+        GraphFormat::Ron => ron::ser::to_string_pretty(graph, ron::ser::PrettyConfig::new())
+            .expect("Failed to serialize the interaction graph"),
+        GraphFormat::GraphMl => to_graphml(graph),
+        GraphFormat::Gexf => to_gexf(graph),
+        GraphFormat::Dot => to_dot(graph),
+    }
+}
@@ -0,0 +1,143 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+//how an archive's files are stored, recorded in its manifest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum StorageFormat {
+    //pretty printed RON, "tweets.ron"
+    #[default]
+    Ron,
+    //the same RON compressed with zstd, "tweets.ron.zst"
+    Zstd,
+}
+
+impl StorageFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            StorageFormat::Ron => "ron",
+            StorageFormat::Zstd => "ron.zst",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<StorageFormat> {
+        match name {
+            "ron" => Some(StorageFormat::Ron),
+            "zstd" | "zst" => Some(StorageFormat::Zstd),
+            _ => None,
+        }
+    }
+}
+
+//every path inside an archive is built here, relative to the archive's root directory
+#[derive(Debug, Clone)]
+pub struct Layout {
+    root: PathBuf,
+    format: StorageFormat,
+}
+
+impl Layout {
+    pub fn new(root: impl Into<PathBuf>) -> Layout {
+        Layout {
+            root: root.into(),
+            format: StorageFormat::default(),
+        }
+    }
+
+    pub fn with_format(&self, format: StorageFormat) -> Layout {
+        Layout {
+            root: self.root.clone(),
+            format,
+        }
+    }
+
+    pub fn format(&self) -> StorageFormat {
+        self.format
+    }
+
+    fn file(&self, name: &str) -> PathBuf {
+        self.root
+            .join(format!("{name}.{}", self.format.extension()))
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn manifest(&self) -> PathBuf {
+        self.root.join("manifest.ron")
+    }
+
+    //copies of the archive taken before it is migrated, one directory per migration
+    pub fn backup(&self, version: u32, timestamp: u64) -> PathBuf {
+        self.root
+            .join("backups")
+            .join(format!("v{version}-{timestamp}"))
+    }
+
+    //media files are named after their media key
+    pub fn media(&self) -> PathBuf {
+        self.root.join("media")
+    }
+
+    //the key activitypub requests are signed with, created the first time it's needed
+    pub fn activitypub_key(&self) -> PathBuf {
+        self.root.join("activitypub").join("key.pem")
+    }
+
+    //background archival jobs, kept so queued jobs survive a restart
+    pub fn jobs(&self) -> PathBuf {
+        self.root.join("jobs").join("jobs.ron")
+    }
+
+    //the users synced on a schedule, next to the jobs that sync them
+    pub fn watchlist(&self) -> PathBuf {
+        self.root.join("jobs").join("watchlist.ron")
+    }
+
+    //tweets that were archived and have since been deleted from twitter
+    pub fn tombstones(&self) -> PathBuf {
+        self.root.join("tombstones").join("tombstones.ron")
+    }
+
+    //outgoing webhook deliveries and how each attempt went
+    pub fn deliveries(&self) -> PathBuf {
+        self.root.join("webhooks").join("deliveries.ron")
+    }
+
+    //the fediverse accounts following each archived user
+    pub fn followers(&self) -> PathBuf {
+        self.root.join("activitypub").join("followers.ron")
+    }
+
+    //derived from tweets.ron and rebuilt whenever it's missing or out of date
+    pub fn search_index(&self) -> PathBuf {
+        self.root
+            .join("index")
+            .join(format!("search.{}", self.format.extension()))
+    }
+
+    pub fn tweets(&self) -> PathBuf {
+        self.file("tweets")
+    }
+
+    pub fn users(&self) -> PathBuf {
+        self.file("users")
+    }
+
+    pub fn conversations(&self) -> PathBuf {
+        self.file("conversations")
+    }
+
+    pub fn user_info(&self, twitter_handle: &str) -> PathBuf {
+        self.file(&format!("user-info_{twitter_handle}"))
+    }
+
+    pub fn user_tweets(&self, twitter_handle: &str) -> PathBuf {
+        self.file(&format!("user-tweets_{twitter_handle}"))
+    }
+
+    pub fn user_conversations(&self, twitter_handle: &str) -> PathBuf {
+        self.file(&format!("user-conversations_{twitter_handle}"))
+    }
+}
@@ -1,34 +1,105 @@
 use std::fs::{self};
+use std::path::{Path, PathBuf};
 
-pub fn string_from_ron(file_path: &str) -> Result<String, std::io::Error> {
-    println!("Reading file: \"{file_path}\"");
-    fs::read_to_string(file_path)
+use super::layout::Layout;
+
+//zstd compressed files ("*.zst") are decompressed transparently
+pub fn string_from_ron(file_path: &Path) -> Result<String, std::io::Error> {
+    println!("Reading file: \"{}\"", file_path.display());
+    if file_path.extension().is_some_and(|ext| ext == "zst") {
+        let bytes = zstd::decode_all(fs::File::open(file_path)?)?;
+        String::from_utf8(bytes)
+            .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))
+    } else {
+        fs::read_to_string(file_path)
+    }
+}
+
+pub fn manifest_string_from_ron(layout: &Layout) -> Result<String, std::io::Error> {
+    string_from_ron(&layout.manifest())
+}
+
+pub fn tweets_string_from_ron(layout: &Layout) -> Result<String, std::io::Error> {
+    string_from_ron(&layout.tweets())
+}
+
+pub fn conversations_string_from_ron(layout: &Layout) -> Result<String, std::io::Error> {
+    string_from_ron(&layout.conversations())
+}
+
+pub fn user_info_string_from_ron(
+    layout: &Layout,
+    twitter_handle: &str,
+) -> Result<String, std::io::Error> {
+    string_from_ron(&layout.user_info(twitter_handle))
+}
+
+pub fn user_tweets_string_from_ron(
+    layout: &Layout,
+    twitter_handle: &str,
+) -> Result<String, std::io::Error> {
+    string_from_ron(&layout.user_tweets(twitter_handle))
 }
 
-pub fn tweets_string_from_ron() -> Result<String, std::io::Error> {
-    string_from_ron("data/tweets.ron")
+pub fn user_conversations_string_from_ron(
+    layout: &Layout,
+    twitter_handle: &str,
+) -> Result<String, std::io::Error> {
+    string_from_ron(&layout.user_conversations(twitter_handle))
 }
 
-pub fn conversations_string_from_ron() -> Result<String, std::io::Error> {
-    string_from_ron("data/conversations.ron")
+pub fn search_index_string_from_ron(layout: &Layout) -> Result<String, std::io::Error> {
+    string_from_ron(&layout.search_index())
 }
 
-pub fn user_info_string_from_ron(twitter_handle: &str) -> Result<String, std::io::Error> {
-    let file_path: &str = &format!("data/user-info_{twitter_handle}.ron");
-    string_from_ron(file_path)
+pub fn users_string_from_ron(layout: &Layout) -> Result<String, std::io::Error> {
+    string_from_ron(&layout.users())
 }
 
-pub fn user_tweets_string_from_ron(twitter_handle: &str) -> Result<String, std::io::Error> {
-    let file_path: &str = &format!("data/user-tweets_{twitter_handle}.ron");
-    string_from_ron(file_path)
+//finds the handles of every "<root>/<prefix><twitter_handle>.ron" file
+pub fn twitter_handles_with_prefix(layout: &Layout, prefix: &str) -> Vec<String> {
+    let suffix = format!(".{}", layout.format().extension());
+    match fs::read_dir(layout.root()) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter_map(|file_name| {
+                file_name
+                    .strip_prefix(prefix)
+                    .and_then(|rest| rest.strip_suffix(&suffix))
+                    .map(|twitter_handle| twitter_handle.to_string())
+            })
+            .collect(),
+        Err(_error) => Vec::new(),
+    }
 }
 
-pub fn user_conversations_string_from_ron(twitter_handle: &str) -> Result<String, std::io::Error> {
-    let file_path: &str = &format!("data/user-conversations_{twitter_handle}.ron");
-    string_from_ron(file_path)
+//every data file directly inside the archive's root, in the archive's storage format
+pub fn archive_files(layout: &Layout) -> Vec<PathBuf> {
+    let suffix = format!(".{}", layout.format().extension());
+    let manifest = layout.manifest();
+    match fs::read_dir(layout.root()) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && *path != manifest)
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|file_name| file_name.to_str())
+                    .is_some_and(|file_name| file_name.ends_with(&suffix))
+            })
+            .collect(),
+        Err(_error) => Vec::new(),
+    }
 }
 
-pub fn users_string_from_ron() -> Result<String, std::io::Error> {
-    let file_path: &str = &format!("data/users.ron");
-    string_from_ron(file_path)
+pub fn media_files(layout: &Layout) -> Vec<PathBuf> {
+    match fs::read_dir(layout.media()) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .collect(),
+        Err(_error) => Vec::new(),
+    }
 }
@@ -1,142 +1,127 @@
 use ron::ser::PrettyConfig;
+use serde::Serialize;
 use std::fs::{self};
+use std::path::Path;
 use twitter_v2::{Tweet, User};
 
-pub fn conversation_to_ron(conversation: &mut Vec<Tweet>) {
-    match fs::read_to_string("data/conversations.ron") {
-        Ok(conversations_ron) => {
-            let mut conversations: Vec<Vec<Tweet>> = ron::from_str(&conversations_ron)
-                .expect("Failed to parse conversations from \"conversations.ron\"");
-            if !conversations.iter().any(|conversation_from_ron| {
-                conversation_from_ron[conversation_from_ron.len() - 1].id
-                    == conversation[conversation.len() - 1].id
-            }) {
-                println!("Writing conversation to \"data/conversations.ron\"");
-                conversations.push(conversation.clone());
-                fs::write(
-                    "data/conversations.ron",
-                    ron::ser::to_string_pretty(&conversations, ron::ser::PrettyConfig::new())
-                        .expect("Failed to parse conversations into ron string"),
-                )
-                .expect("Failed to write to \"data/conversations.ron\"");
-            }
-        }
-        Err(_error) => {
-            println!("Creating file \"data/conversations.ron\"");
-            let mut conversations = Vec::<Vec<Tweet>>::new();
-            conversations.push(conversation.clone());
-            fs::write(
-                "data/conversations.ron",
-                ron::ser::to_string_pretty(&conversations, ron::ser::PrettyConfig::new())
-                    .expect("Failed to parse conversations into ron string"),
-            )
-            .expect("Failed to write to \"data/conversations.ron\"");
-        }
-    }
+use super::layout::Layout;
+use crate::app::migrations::Manifest;
+use crate::app::records::{self, UserRecord};
+use crate::app::search::index::Index;
+
+const ZSTD_LEVEL: i32 = 9;
+
+pub fn value_to_ron<T: Serialize + ?Sized>(value: &T, file_path: &Path) {
+    let file = file_path.display();
+    string_to_file(
+        &ron::ser::to_string_pretty(value, PrettyConfig::new())
+            .unwrap_or_else(|_| panic!("Failed to parse \"{file}\" into a ron pretty string")),
+        file_path,
+    );
 }
 
-pub fn tweets_to_ron(tweets: &mut Vec<Tweet>) {
-    match fs::read_to_string("data/tweets.ron") {
-        Ok(tweets_from_ron_string) => {
-            let mut tweets_from_ron: Vec<Tweet> = ron::from_str(&tweets_from_ron_string)
-                .expect("Failed to read tweets from \"data/tweets.ron\"");
-            tweets.into_iter().for_each(|tweet| {
-                if !tweets_from_ron
-                    .iter()
-                    .any(|tweet_from_ron| tweet_from_ron.id == tweet.id)
-                {
-                    tweets_from_ron.push(tweet.clone())
-                }
-            });
-            println!("Writing tweets to \"data/tweets.ron\"");
-            fs::write(
-                "data/tweets.ron",
-                ron::ser::to_string_pretty(&tweets_from_ron, ron::ser::PrettyConfig::new())
-                    .expect("Failed to parse tweets_from_ron back into a string"),
-            )
-            .expect("Failed to write to \"data/tweets.ron\"");
-        }
-        Err(_error) => {
-            println!("Creating new file \"data/tweets.ron\"");
-            fs::write(
-                "data/tweets.ron",
-                ron::ser::to_string_pretty(&tweets, ron::ser::PrettyConfig::new())
-                    .expect("Failed to parse tweets into a ron string"),
-            )
-            .expect("Failed to create a new \"data/tweets.ron\"")
-        }
+//"*.zst" files are compressed with zstd, everything else is written as plain text
+pub fn string_to_file(contents: &str, file_path: &Path) {
+    let file = file_path.display();
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent)
+            .unwrap_or_else(|_| panic!("Failed to create directory for \"{file}\""));
+    }
+    if file_path.extension().is_some_and(|ext| ext == "zst") {
+        let compressed = zstd::encode_all(contents.as_bytes(), ZSTD_LEVEL)
+            .unwrap_or_else(|_| panic!("Failed to compress \"{file}\""));
+        fs::write(file_path, compressed)
+    } else {
+        fs::write(file_path, contents)
     }
+    .unwrap_or_else(|_| panic!("Failed to write to \"{file}\""));
+}
+
+pub fn conversations_to_ron(layout: &Layout, conversations: &[Vec<Tweet>]) {
+    let file_path = layout.conversations();
+    println!("Writing conversations to \"{}\"", file_path.display());
+    value_to_ron(
+        &records::conversations_to_records(conversations),
+        &file_path,
+    );
+}
+
+pub fn tweets_to_ron(layout: &Layout, tweets: &[Tweet]) {
+    let file_path = layout.tweets();
+    println!("Writing tweets to \"{}\"", file_path.display());
+    value_to_ron(&records::tweets_to_records(tweets), &file_path);
+}
+
+pub fn user_info_to_ron(layout: &Layout, user: &User, twitter_handle: &str) {
+    let file_path = layout.user_info(twitter_handle);
+    println!("Creating new file \"{}\"", file_path.display());
+    value_to_ron(&UserRecord::from(user), &file_path);
+}
+
+pub fn user_tweets_to_ron(layout: &Layout, tweets: &[Tweet], twitter_handle: &str) {
+    let file_path = layout.user_tweets(twitter_handle);
+    println!("Creating new file \"{}\"", file_path.display());
+    value_to_ron(&records::tweets_to_records(tweets), &file_path);
+}
+
+pub fn user_conversations_to_ron(
+    layout: &Layout,
+    conversations: &[Vec<Tweet>],
+    twitter_handle: &str,
+) {
+    let file_path = layout.user_conversations(twitter_handle);
+    println!("Creating new file \"{}\"", file_path.display());
+    value_to_ron(
+        &records::conversations_to_records(conversations),
+        &file_path,
+    );
+}
+
+pub fn users_to_ron(layout: &Layout, users: &[User]) {
+    let file_path = layout.users();
+    println!("Writing users to \"{}\"", file_path.display());
+    value_to_ron(&records::users_to_records(users), &file_path);
 }
 
-pub fn user_info_to_ron(user: &User, twitter_handle: &str) {
-    user_to_users_ron(user);
-    println!("Creating new file \"data/user-info_{twitter_handle}.ron\"");
-    fs::write(
-        &format!("data/user-info_{twitter_handle}.ron"),
-        ron::ser::to_string_pretty(user, PrettyConfig::new()).expect(&format!(
-            "Failed to parse user @{twitter_handle} into a ron pretty string"
-        )),
-    )
-    .expect(&format!(
-        "Failed to write info for @{twitter_handle} to \"data/user-info_{twitter_handle}.ron"
-    ));
+//the index is large and never read by people, so it isn't pretty printed
+pub fn search_index_to_ron(layout: &Layout, index: &Index) {
+    let file_path = layout.search_index();
+    println!("Writing search index to \"{}\"", file_path.display());
+    string_to_file(
+        &ron::ser::to_string(index).expect("Failed to parse search index into a ron string"),
+        &file_path,
+    );
 }
 
-pub fn user_tweets_to_ron(tweets: &Vec<Tweet>, twitter_handle: &str) {
-    println!("Creating new file \"data/user-tweets_{twitter_handle}.ron\"");
-    fs::write(
-        &format!("data/user-tweets_{twitter_handle}.ron"),
-        ron::ser::to_string_pretty(tweets, PrettyConfig::new()).expect(&format!(
-            "Failed to parse user @{twitter_handle}'s tweets into a ron pretty string"
-        )),
-    )
-    .expect(&format!(
-        "Failed to write tweets for @{twitter_handle} to \"data/user-tweets_{twitter_handle}.ron"
-    ));
+//a downloaded media file, named after its media key, e.g. "3_1544021366923415552.jpg"
+pub fn media_to_file(layout: &Layout, file_name: &str, contents: &[u8]) {
+    let file_path = layout.media().join(file_name);
+    let file = file_path.display();
+    println!("Writing media to \"{file}\"");
+    fs::create_dir_all(layout.media())
+        .unwrap_or_else(|_| panic!("Failed to create directory for \"{file}\""));
+    fs::write(&file_path, contents).unwrap_or_else(|_| panic!("Failed to write to \"{file}\""));
 }
 
-pub fn user_conversations_to_ron(conversations: &Vec<Vec<Tweet>>, twitter_handle: &str) {
-    println!("Creating new file \"data/user-conversations_{twitter_handle}.ron\"");
-    fs::write(
-        &format!("data/user-conversations_{twitter_handle}.ron"),
-        ron::ser::to_string_pretty(conversations, PrettyConfig::new()).expect(&format!(
-            "Failed to parse user @{twitter_handle}'s conversations into a ron pretty string"
-        )),
-    )
-    .expect(&format!(
-        "Failed to write conversations for @{twitter_handle} to \"data/user-conversations_{twitter_handle}.ron"
-    ));
+pub fn manifest_to_ron(layout: &Layout, manifest: &Manifest) {
+    let file_path = layout.manifest();
+    println!("Writing manifest to \"{}\"", file_path.display());
+    value_to_ron(manifest, &file_path);
 }
 
-pub fn user_to_users_ron(user: &User) {
-    match fs::read_to_string("data/users.ron") {
-        Ok(users_from_ron_string) => {
-            let mut users_from_ron: Vec<User> = ron::from_str(&users_from_ron_string)
-                .expect("Failed to read users from \"data/users.ron\"");
-            if !users_from_ron
-                .iter()
-                .any(|user_from_ron| user.id == user_from_ron.id)
-            {
-                users_from_ron.push(user.clone())
-            }
-            let username = user.username.clone();
-            println!("Writing @{username} to \"data/users.ron\"");
-            fs::write(
-                "data/users.ron",
-                ron::ser::to_string_pretty(&users_from_ron, ron::ser::PrettyConfig::new())
-                    .expect("Failed to parse users_from_ron back into a string"),
-            )
-            .expect("Failed to write to \"data/users.ron\"");
-        }
-        Err(_error) => {
-            println!("Creating new file \"data/users.ron\"");
-            let users = vec![user];
-            fs::write(
-                "data/users.ron",
-                ron::ser::to_string_pretty(&users, ron::ser::PrettyConfig::new())
-                    .expect("Failed to parse user into a ron string"),
-            )
-            .expect("Failed to create a new \"data/users.ron\"")
-        }
+//copies the given files and the manifest of the archive into the backup directory
+pub fn backup_archive(layout: &Layout, files: &[std::path::PathBuf], backup_dir: &Path) {
+    let backup = backup_dir.display();
+    println!("Backing up archive to \"{backup}\"");
+    fs::create_dir_all(backup_dir)
+        .unwrap_or_else(|_| panic!("Failed to create backup directory \"{backup}\""));
+    let manifest = layout.manifest();
+    let manifest = manifest.is_file().then_some(manifest);
+    for file_path in files.iter().chain(manifest.iter()) {
+        let file_name = file_path
+            .strip_prefix(layout.root())
+            .expect("Backed up files should be inside the archive");
+        fs::copy(file_path, backup_dir.join(file_name))
+            .unwrap_or_else(|_| panic!("Failed to back up \"{}\"", file_path.display()));
     }
 }
@@ -0,0 +1,373 @@
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio::sync::Notify;
+
+use super::archive::{self, Archive};
+use super::io;
+use super::progress::{self, Event};
+use super::watchlist;
+use super::webhooks;
+
+// Archiving a user from the Twitter API can take hundreds of requests, so instead of holding an
+// HTTP connection open it can be queued as a job. A pool of workers runs queued jobs oldest
+// first with the same `load_*` functions the routes use, which skip whatever is archived already.
+//
+// Each archive keeps its jobs in "jobs/jobs.ron", written whenever a job is queued, started or
+// finished. Jobs that were queued or running when the server stopped are queued again on start.
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Target {
+    //the user's info, tweets and conversations
+    User(String),
+    Tweet(u64),
+    //the conversation ending in this tweet
+    Conversation(u64),
+    //the user's tweets since their newest archived one, and their conversations
+    Sync(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+//how far a running job has got, e.g. 120 of 3200 in "conversations"
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Progress {
+    pub stage: String,
+    pub done: usize,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Job {
+    pub id: u64,
+    pub archive: String,
+    pub target: Target,
+    pub status: Status,
+    #[serde(default)]
+    pub progress: Progress,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub queued_at: OffsetDateTime,
+    #[serde(
+        default,
+        with = "time::serde::rfc3339::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub started_at: Option<OffsetDateTime>,
+    #[serde(
+        default,
+        with = "time::serde::rfc3339::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub finished_at: Option<OffsetDateTime>,
+}
+
+impl Job {
+    pub fn is_finished(&self) -> bool {
+        matches!(self.status, Status::Done | Status::Failed)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct JobConfig {
+    //how many jobs run at the same time, across every archive
+    #[serde(default = "default_job_workers")]
+    pub job_workers: usize,
+}
+
+fn default_job_workers() -> usize {
+    2
+}
+
+pub struct Jobs {
+    jobs: Mutex<BTreeMap<u64, Job>>,
+    queued: Notify,
+}
+
+static JOBS: OnceLock<Jobs> = OnceLock::new();
+
+//every configured archive's jobs, read from disk the first time they're needed
+pub fn jobs() -> &'static Jobs {
+    JOBS.get_or_init(Jobs::load)
+}
+
+fn read_jobs(archive: &Archive) -> Vec<Job> {
+    match io::read::string_from_ron(&archive.layout.jobs()) {
+        Ok(jobs) => ron::from_str(&jobs).expect("Failed to parse jobs from ron"),
+        Err(_) => Vec::new(),
+    }
+}
+
+impl Jobs {
+    fn load() -> Jobs {
+        let mut jobs = BTreeMap::new();
+        for name in archive::names() {
+            let archive = archive::get(&name).expect("Every named archive is configured");
+            for mut job in read_jobs(archive) {
+                if job.status == Status::Running {
+                    println!("Job {} was interrupted, queueing it again", job.id);
+                    job.status = Status::Queued;
+                    job.progress = Progress::default();
+                    job.started_at = None;
+                }
+                jobs.insert(job.id, job);
+            }
+        }
+        Jobs {
+            jobs: Mutex::new(jobs),
+            queued: Notify::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<u64, Job>> {
+        self.jobs.lock().expect("The jobs lock was poisoned")
+    }
+
+    //writes the jobs of one archive back to its jobs file
+    fn save(jobs: &BTreeMap<u64, Job>, archive_name: &str) {
+        let archive = match archive::get(archive_name) {
+            Some(archive) => archive,
+            None => return,
+        };
+        let archive_jobs: Vec<&Job> = jobs
+            .values()
+            .filter(|job| job.archive == archive_name)
+            .collect();
+        io::write::value_to_ron(&archive_jobs, &archive.layout.jobs());
+    }
+
+    pub fn get(&self, id: u64) -> Option<Job> {
+        self.lock().get(&id).cloned()
+    }
+
+    pub fn enqueue(&self, archive: &Archive, target: Target) -> Job {
+        let mut jobs = self.lock();
+        let id = jobs.keys().next_back().map_or(1, |id| id + 1);
+        let job = Job {
+            id,
+            archive: archive.name.clone(),
+            target,
+            status: Status::Queued,
+            progress: Progress::default(),
+            error: None,
+            queued_at: OffsetDateTime::now_utc(),
+            started_at: None,
+            finished_at: None,
+        };
+        println!("Queued job {id}: {:?}", job.target);
+        jobs.insert(id, job.clone());
+        Jobs::save(&jobs, &archive.name);
+        self.queued.notify_waiters();
+        job
+    }
+
+    //marks the oldest queued job as running and hands it out
+    fn next(&self) -> Option<Job> {
+        let mut jobs = self.lock();
+        let job = jobs.values_mut().find(|job| job.status == Status::Queued)?;
+        job.status = Status::Running;
+        job.started_at = Some(OffsetDateTime::now_utc());
+        let job = job.clone();
+        Jobs::save(&jobs, &job.archive);
+        Some(job)
+    }
+
+    //progress is only kept in memory, it's reset when a job is queued again anyway
+    pub fn progress(&self, id: u64, stage: &str, done: usize, total: usize) {
+        if let Some(job) = self.lock().get_mut(&id) {
+            job.progress = Progress {
+                stage: stage.to_string(),
+                done,
+                total,
+            };
+        }
+    }
+
+    fn finish(&self, id: u64, result: Result<(), String>) {
+        let mut jobs = self.lock();
+        let job = match jobs.get_mut(&id) {
+            Some(job) => job,
+            None => return,
+        };
+        job.finished_at = Some(OffsetDateTime::now_utc());
+        match result {
+            Ok(()) => {
+                println!("Job {id} is done");
+                job.status = Status::Done;
+            }
+            Err(error) => {
+                println!("Job {id} failed: {error}");
+                job.status = Status::Failed;
+                job.error = Some(error);
+            }
+        }
+        let archive = job.archive.clone();
+        Jobs::save(&jobs, &archive);
+    }
+}
+
+//how many tweets a sync found, nothing to count for the others
+async fn run(job: &Job, archive: &'static Archive) -> usize {
+    let jobs = jobs();
+    let id = job.id;
+    match &job.target {
+        Target::User(twitter_handle) => {
+            jobs.progress(id, "user", 0, 1);
+            super::load_user_from_twitter_handle(archive, twitter_handle).await;
+            jobs.progress(id, "tweets", 0, 1);
+            super::load_tweets_from_twitter_handle(archive, twitter_handle).await;
+            super::load_conversations_from_twitter_handle_with_progress(
+                archive,
+                twitter_handle,
+                |done, total| jobs.progress(id, "conversations", done, total),
+            )
+            .await;
+            0
+        }
+        Target::Tweet(tweet_id) => {
+            jobs.progress(id, "tweet", 0, 1);
+            super::load_tweet_from_id(archive, *tweet_id).await;
+            0
+        }
+        Target::Conversation(tweet_id) => {
+            jobs.progress(id, "conversation", 0, 1);
+            super::load_conversation_from_tweet_id(archive, *tweet_id).await;
+            0
+        }
+        Target::Sync(twitter_handle) => {
+            jobs.progress(id, "new tweets", 0, 1);
+            super::sync_user(archive, twitter_handle, |done, total| {
+                jobs.progress(id, "conversations", done, total)
+            })
+            .await
+        }
+    }
+}
+
+//the api functions panic when twitter fails, which fails the job rather than the worker
+async fn execute(job: Job) -> Result<usize, String> {
+    let archive = archive::get(&job.archive)
+        .ok_or_else(|| format!("No archive called \"{}\" is configured", job.archive))?;
+    let id = job.id;
+    tokio::spawn(progress::JOB.scope(id, async move { run(&job, archive).await }))
+        .await
+        .map_err(|error| match error.try_into_panic() {
+            Ok(panic) => match panic.downcast::<String>() {
+                Ok(message) => *message,
+                Err(panic) => panic
+                    .downcast::<&str>()
+                    .map(|message| message.to_string())
+                    .unwrap_or_else(|_| "The job panicked".to_string()),
+            },
+            Err(error) => error.to_string(),
+        })
+}
+
+async fn work() {
+    let jobs = jobs();
+    loop {
+        //created before looking, so a job queued in between still wakes this worker
+        let queued = jobs.queued.notified();
+        match jobs.next() {
+            Some(job) => {
+                let id = job.id;
+                println!("Running job {id}: {:?}", job.target);
+                progress::emit_for(Some(id), Event::JobStarted);
+                let (archive, target) = (job.archive.clone(), job.target.clone());
+                let result = execute(job).await;
+                if let Target::Sync(twitter_handle) = &target {
+                    watchlist::synced(&archive, twitter_handle, &result);
+                }
+                let failed = result.is_err();
+                jobs.finish(id, result.map(|_| ()));
+                progress::emit_for(Some(id), Event::JobFinished { failed });
+                if let (Some(archive), Some(job)) = (archive::get(&archive), jobs.get(id)) {
+                    webhooks::notify(archive, webhooks::Event::JobFinished { job });
+                }
+            }
+            None => queued.await,
+        }
+    }
+}
+
+//starts the worker pool, which runs for as long as the server does
+pub fn start(config: &JobConfig) {
+    let workers = config.job_workers.max(1);
+    println!("Starting {workers} job workers");
+    for _ in 0..workers {
+        tokio::spawn(work());
+    }
+}
+
+//follows "/progress?job=<id>" with an EventSource, for watching a job from a browser
+pub fn watch_page(id: u64) -> String {
+    format!(
+        r#"<!doctype html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Job {id}</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; }}
+progress {{ width: 40em; }}
+pre {{ color: #555; }}
+</style>
+</head>
+<body>
+<h1>Job {id}</h1>
+<p id="stage">Waiting for the job to start</p>
+<progress id="bar"></progress>
+<pre id="log"></pre>
+<script>
+const stage = document.getElementById("stage");
+const bar = document.getElementById("bar");
+const log = document.getElementById("log");
+const events = new EventSource("/progress?job={id}");
+const show = (event) => {{
+  const update = JSON.parse(event.data);
+  log.textContent = event.data + "\n" + log.textContent;
+  switch (update.type) {{
+    case "page_fetched":
+      stage.textContent = `@${{update.twitter_handle}}'s timeline, page ${{update.page}} of ${{update.pages}}`;
+      bar.max = update.pages;
+      bar.value = update.page;
+      break;
+    case "conversation_resolved":
+      stage.textContent = `Conversations, ${{update.done}} of ${{update.total}}`;
+      bar.max = update.total;
+      bar.value = update.done;
+      break;
+    case "rate_limited":
+      stage.textContent = `Rate limited, waiting ${{update.seconds}} seconds`;
+      break;
+    case "job_started":
+      stage.textContent = "Running";
+      break;
+    case "job_finished":
+      stage.textContent = update.failed ? "Failed, see /jobs/{id}" : "Done";
+      bar.max = 1;
+      bar.value = 1;
+      events.close();
+      break;
+  }}
+}};
+for (const name of ["job_started", "job_finished", "page_fetched", "tweets_stored", "conversation_resolved", "rate_limited"]) {{
+  events.addEventListener(name, show);
+}}
+</script>
+</body>
+</html>
+"#
+    )
+}
@@ -0,0 +1,83 @@
+use std::collections::HashSet;
+
+use twitter_v2::Tweet;
+
+use super::api::{self, cassette};
+use super::archive::Archive;
+use super::io;
+
+// The photos, videos and gifs attached to archived tweets are downloaded into the archive's
+// "media" directory, named after their media key with the extension of the file twitter serves,
+// e.g. "media/3_1544021366923415552.jpg". Videos and gifs are downloaded as their best mp4.
+// Media that's already there isn't downloaded again, and nothing is downloaded while a cassette
+// is replayed since the files aren't in it. A download that fails is left for `check` and the
+// exports to report as missing, it doesn't fail the load.
+
+//how many tweets twitter looks up at once
+const LOOKUP_SIZE: usize = 100;
+
+fn media_keys(tweet: &Tweet) -> impl Iterator<Item = String> + '_ {
+    tweet
+        .attachments
+        .iter()
+        .flat_map(|attachments| attachments.media_keys.iter().flatten())
+        .map(|media_key| media_key.to_string())
+}
+
+//"jpg" for "https://pbs.twimg.com/media/FXCRjPDXkAAv0Zo.jpg?name=large"
+fn extension(url: &str) -> Option<&str> {
+    let path = url.split(['?', '#']).next()?;
+    let file_name = path.rsplit('/').next()?;
+    let (_, extension) = file_name.rsplit_once('.')?;
+    (!extension.is_empty() && extension.chars().all(|c| c.is_ascii_alphanumeric()))
+        .then_some(extension)
+}
+
+//returns how many files were downloaded
+pub async fn download(archive: &Archive, tweets: &[Tweet]) -> usize {
+    if cassette::current().is_some_and(|cassette| cassette.is_replaying()) {
+        return 0;
+    }
+    let downloaded: HashSet<String> = io::read::media_files(&archive.layout)
+        .iter()
+        .filter_map(|path| Some(path.file_stem()?.to_str()?.to_string()))
+        .collect();
+    let mut missing: HashSet<String> = HashSet::new();
+    let mut ids: Vec<u64> = Vec::new();
+    for tweet in tweets {
+        let keys: Vec<String> = media_keys(tweet)
+            .filter(|media_key| !downloaded.contains(media_key))
+            .collect();
+        if !keys.is_empty() && !ids.contains(&tweet.id.as_u64()) {
+            ids.push(tweet.id.as_u64());
+            missing.extend(keys);
+        }
+    }
+    let mut count = 0;
+    for ids in ids.chunks(LOOKUP_SIZE) {
+        for media in api::get_media_of_tweets(ids).await {
+            if !missing.remove(&media.media_key) {
+                continue;
+            }
+            let url = match media.file_url() {
+                Some(url) => url,
+                None => {
+                    println!("Media {} has nothing to download", media.media_key);
+                    continue;
+                }
+            };
+            let file_name = match extension(url) {
+                Some(extension) => format!("{}.{extension}", media.media_key),
+                None => media.media_key.clone(),
+            };
+            match api::download(url).await {
+                Ok(contents) => {
+                    io::write::media_to_file(&archive.layout, &file_name, &contents);
+                    count += 1;
+                }
+                Err(error) => println!("Failed to download media {}: {error}", media.media_key),
+            }
+        }
+    }
+    count
+}
@@ -0,0 +1,197 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use twitter_v2::{Tweet, User};
+
+use super::io::{
+    self,
+    layout::{Layout, StorageFormat},
+};
+
+// version 1: no manifest, files hold whatever twitter-v2's serde derives emit
+// version 2: files hold the record types from `records`
+pub const CURRENT_VERSION: u32 = 2;
+
+type Migration = fn(&Layout) -> Result<(), MigrationError>;
+
+//MIGRATIONS[i] upgrades an archive from version i + 1 to version i + 2
+const MIGRATIONS: [Migration; 1] = [migrate_v1_to_v2];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    #[serde(default)]
+    pub format: StorageFormat,
+}
+
+//a file of the archive that couldn't be read to migrate it, which is left as it was
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationError {
+    pub file: PathBuf,
+    pub error: String,
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "\"{}\" can't be parsed: {}",
+            self.file.display(),
+            self.error
+        )
+    }
+}
+
+fn read_manifest(layout: &Layout) -> Result<Option<Manifest>, MigrationError> {
+    match io::read::manifest_string_from_ron(layout) {
+        Ok(manifest_string) => {
+            ron::from_str(&manifest_string)
+                .map(Some)
+                .map_err(|error| MigrationError {
+                    file: layout.manifest(),
+                    error: error.to_string(),
+                })
+        }
+        Err(_error) => Ok(None),
+    }
+}
+
+pub fn manifest(layout: &Layout) -> Option<Manifest> {
+    read_manifest(layout).unwrap_or_else(|error| panic!("{error}"))
+}
+
+fn version(layout: &Layout, manifest: Option<&Manifest>) -> u32 {
+    match manifest {
+        Some(manifest) => manifest.version,
+        //an archive without a manifest predates versioning, unless it is empty
+        None if io::read::archive_files(layout).is_empty() => CURRENT_VERSION,
+        None => 1,
+    }
+}
+
+pub fn archive_version(layout: &Layout) -> u32 {
+    version(layout, manifest(layout).as_ref())
+}
+
+//upgrades the archive in place to CURRENT_VERSION, backing it up first,
+//and returns its layout in the storage format recorded in its manifest
+pub fn migrate(layout: &Layout) -> Layout {
+    try_migrate(layout).unwrap_or_else(|error| panic!("{error}"))
+}
+
+//the same, with the file it couldn't read if the archive can't be migrated
+pub fn try_migrate(layout: &Layout) -> Result<Layout, MigrationError> {
+    let manifest = read_manifest(layout)?;
+    let format = manifest
+        .as_ref()
+        .map(|manifest| manifest.format)
+        .unwrap_or_default();
+    let layout = &layout.with_format(format);
+    let root = layout.root().display();
+    let version = version(layout, manifest.as_ref());
+    if version > CURRENT_VERSION {
+        panic!("Archive \"{root}\" has version {version} but this archiver only understands up to version {CURRENT_VERSION}");
+    }
+    if version < CURRENT_VERSION {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("System clock is before 1970")
+            .as_secs();
+        io::write::backup_archive(
+            layout,
+            &io::read::archive_files(layout),
+            &layout.backup(version, timestamp),
+        );
+        for from in version..CURRENT_VERSION {
+            let to = from + 1;
+            println!("Migrating archive \"{root}\" from version {from} to version {to}");
+            MIGRATIONS[(from - 1) as usize](layout)?;
+        }
+    }
+    if version < CURRENT_VERSION || manifest.is_none() {
+        io::write::manifest_to_ron(
+            layout,
+            &Manifest {
+                version: CURRENT_VERSION,
+                format,
+            },
+        );
+    }
+    Ok(layout.clone())
+}
+
+fn parse_v1<T: serde::de::DeserializeOwned>(
+    file: PathBuf,
+    file_string: Result<String, std::io::Error>,
+) -> Result<Option<T>, MigrationError> {
+    match file_string {
+        Ok(file_string) => ron::from_str(&file_string)
+            .map(Some)
+            .map_err(|error| MigrationError {
+                file,
+                error: format!("not a version 1 archive file, {error}"),
+            }),
+        Err(_error) => Ok(None),
+    }
+}
+
+//every file is read before any is written, so a file that can't be leaves the archive as it was
+fn migrate_v1_to_v2(layout: &Layout) -> Result<(), MigrationError> {
+    let tweets: Option<Vec<Tweet>> =
+        parse_v1(layout.tweets(), io::read::tweets_string_from_ron(layout))?;
+    let users: Option<Vec<User>> =
+        parse_v1(layout.users(), io::read::users_string_from_ron(layout))?;
+    let conversations: Option<Vec<Vec<Tweet>>> = parse_v1(
+        layout.conversations(),
+        io::read::conversations_string_from_ron(layout),
+    )?;
+    let mut user_infos: Vec<(String, User)> = Vec::new();
+    for twitter_handle in io::read::twitter_handles_with_prefix(layout, "user-info_") {
+        if let Some(user) = parse_v1(
+            layout.user_info(&twitter_handle),
+            io::read::user_info_string_from_ron(layout, &twitter_handle),
+        )? {
+            user_infos.push((twitter_handle, user));
+        }
+    }
+    let mut user_tweets: Vec<(String, Vec<Tweet>)> = Vec::new();
+    for twitter_handle in io::read::twitter_handles_with_prefix(layout, "user-tweets_") {
+        if let Some(tweets) = parse_v1(
+            layout.user_tweets(&twitter_handle),
+            io::read::user_tweets_string_from_ron(layout, &twitter_handle),
+        )? {
+            user_tweets.push((twitter_handle, tweets));
+        }
+    }
+    let mut user_conversations: Vec<(String, Vec<Vec<Tweet>>)> = Vec::new();
+    for twitter_handle in io::read::twitter_handles_with_prefix(layout, "user-conversations_") {
+        if let Some(conversations) = parse_v1(
+            layout.user_conversations(&twitter_handle),
+            io::read::user_conversations_string_from_ron(layout, &twitter_handle),
+        )? {
+            user_conversations.push((twitter_handle, conversations));
+        }
+    }
+
+    if let Some(tweets) = tweets {
+        io::write::tweets_to_ron(layout, &tweets);
+    }
+    if let Some(users) = users {
+        io::write::users_to_ron(layout, &users);
+    }
+    if let Some(conversations) = conversations {
+        io::write::conversations_to_ron(layout, &conversations);
+    }
+    for (twitter_handle, user) in user_infos {
+        io::write::user_info_to_ron(layout, &user, &twitter_handle);
+    }
+    for (twitter_handle, tweets) in user_tweets {
+        io::write::user_tweets_to_ron(layout, &tweets, &twitter_handle);
+    }
+    for (twitter_handle, conversations) in user_conversations {
+        io::write::user_conversations_to_ron(layout, &conversations, &twitter_handle);
+    }
+    Ok(())
+}
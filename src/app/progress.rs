@@ -0,0 +1,95 @@
+use std::sync::OnceLock;
+
+use serde::Serialize;
+use time::OffsetDateTime;
+use tokio::sync::broadcast;
+
+// Structured progress of archival, broadcast to whoever is listening (the "/progress"
+// server-sent events and the CLI's `watch`). Events sent while nobody listens are dropped.
+
+//how many events a slow listener can fall behind before it misses some
+const CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    JobStarted,
+    JobFinished {
+        failed: bool,
+    },
+    //a page of a user's timeline from the Twitter API
+    PageFetched {
+        twitter_handle: String,
+        page: usize,
+        pages: usize,
+        tweets: usize,
+    },
+    //tweets loaded from the Twitter API and written to the archive
+    TweetsStored {
+        count: usize,
+    },
+    //one of a user's tweets had its conversation loaded
+    ConversationResolved {
+        tweet_id: u64,
+        tweets: usize,
+        done: usize,
+        total: usize,
+    },
+    //twitter said to slow down, nothing is requested until the wait is over
+    RateLimited {
+        seconds: u64,
+    },
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::JobStarted => "job_started",
+            Event::JobFinished { .. } => "job_finished",
+            Event::PageFetched { .. } => "page_fetched",
+            Event::TweetsStored { .. } => "tweets_stored",
+            Event::ConversationResolved { .. } => "conversation_resolved",
+            Event::RateLimited { .. } => "rate_limited",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Update {
+    //the background job the event happened in, if any
+    pub job: Option<u64>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub at: OffsetDateTime,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+tokio::task_local! {
+    //set while a job runs, so events from deep inside the load functions know which job they belong to
+    pub static JOB: u64;
+}
+
+static CHANNEL: OnceLock<broadcast::Sender<Update>> = OnceLock::new();
+
+fn channel() -> &'static broadcast::Sender<Update> {
+    CHANNEL.get_or_init(|| broadcast::channel(CAPACITY).0)
+}
+
+pub fn subscribe() -> broadcast::Receiver<Update> {
+    channel().subscribe()
+}
+
+pub fn emit_for(job: Option<u64>, event: Event) {
+    let update = Update {
+        job,
+        at: OffsetDateTime::now_utc(),
+        event,
+    };
+    //only fails when nobody is listening
+    let _ = channel().send(update);
+}
+
+//attributed to the job running on this task, if there is one
+pub fn emit(event: Event) {
+    emit_for(JOB.try_with(|job| *job).ok(), event);
+}
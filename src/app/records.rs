@@ -0,0 +1,373 @@
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use twitter_v2::data::{
+    Attachments, FullTextEntities, HashtagEntity, MentionEntity, ReferencedTweet,
+    ReferencedTweetKind, TweetPublicMetrics, UrlEntity,
+};
+use twitter_v2::id::NumericId;
+use twitter_v2::{Tweet, User};
+
+// These are the types the archive is stored as on disk (schema version 2).
+// They belong to us rather than to the twitter-v2 crate, so upgrading that crate can't change
+// the archive format. Any change here needs a new version and a step in `migrations`.
+// Fields of `Tweet` and `User` that the archiver never requests are not stored.
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TweetRecord {
+    pub id: u64,
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author_id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conversation_id: Option<u64>,
+    #[serde(
+        default,
+        with = "time::serde::rfc3339::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub created_at: Option<OffsetDateTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub in_reply_to_user_id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub referenced_tweets: Vec<ReferencedTweetRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub media_keys: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub poll_ids: Vec<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<TweetMetricsRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub urls: Vec<UrlRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hashtags: Vec<TagRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mentions: Vec<TagRecord>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReferenceKind {
+    RepliedTo,
+    Quoted,
+    Retweeted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReferencedTweetRecord {
+    pub kind: ReferenceKind,
+    pub id: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TweetMetricsRecord {
+    pub retweets: usize,
+    pub replies: usize,
+    pub likes: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quotes: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UrlRecord {
+    pub start: usize,
+    pub end: usize,
+    pub url: String,
+    pub expanded_url: String,
+    pub display_url: String,
+}
+
+//a hashtag (without the '#') or a mention (without the '@') with its position in the text
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TagRecord {
+    pub start: usize,
+    pub end: usize,
+    pub tag: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserRecord {
+    pub id: u64,
+    pub name: String,
+    pub username: String,
+    #[serde(
+        default,
+        with = "time::serde::rfc3339::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub created_at: Option<OffsetDateTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pinned_tweet_id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile_image_url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protected: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verified: Option<bool>,
+}
+
+impl From<&Tweet> for TweetRecord {
+    fn from(tweet: &Tweet) -> TweetRecord {
+        let entities = tweet.entities.as_ref();
+        TweetRecord {
+            id: tweet.id.as_u64(),
+            text: tweet.text.clone(),
+            author_id: tweet.author_id.map(NumericId::as_u64),
+            conversation_id: tweet.conversation_id.map(NumericId::as_u64),
+            created_at: tweet.created_at,
+            in_reply_to_user_id: tweet.in_reply_to_user_id.map(NumericId::as_u64),
+            referenced_tweets: tweet
+                .referenced_tweets
+                .iter()
+                .flatten()
+                .map(|referenced_tweet| ReferencedTweetRecord {
+                    kind: match referenced_tweet.kind {
+                        ReferencedTweetKind::RepliedTo => ReferenceKind::RepliedTo,
+                        ReferencedTweetKind::Quoted => ReferenceKind::Quoted,
+                        ReferencedTweetKind::Retweeted => ReferenceKind::Retweeted,
+                    },
+                    id: referenced_tweet.id.as_u64(),
+                })
+                .collect(),
+            media_keys: tweet
+                .attachments
+                .iter()
+                .flat_map(|attachments| attachments.media_keys.iter().flatten())
+                .map(|media_key| media_key.to_string())
+                .collect(),
+            poll_ids: tweet
+                .attachments
+                .iter()
+                .flat_map(|attachments| attachments.poll_ids.iter().flatten())
+                .map(|poll_id| poll_id.as_u64())
+                .collect(),
+            lang: tweet.lang.clone(),
+            metrics: tweet
+                .public_metrics
+                .as_ref()
+                .map(|metrics| TweetMetricsRecord {
+                    retweets: metrics.retweet_count,
+                    replies: metrics.reply_count,
+                    likes: metrics.like_count,
+                    quotes: metrics.quote_count,
+                }),
+            urls: entities
+                .and_then(|entities| entities.urls.as_ref())
+                .iter()
+                .flat_map(|urls| urls.iter())
+                .map(|url| UrlRecord {
+                    start: url.start,
+                    end: url.end,
+                    url: url.url.clone(),
+                    expanded_url: url.expanded_url.clone(),
+                    display_url: url.display_url.clone(),
+                })
+                .collect(),
+            hashtags: entities
+                .and_then(|entities| entities.hashtags.as_ref())
+                .iter()
+                .flat_map(|hashtags| hashtags.iter())
+                .map(|hashtag| TagRecord {
+                    start: hashtag.start,
+                    end: hashtag.end,
+                    tag: hashtag.tag.clone(),
+                })
+                .collect(),
+            mentions: entities
+                .and_then(|entities| entities.mentions.as_ref())
+                .iter()
+                .flat_map(|mentions| mentions.iter())
+                .map(|mention| TagRecord {
+                    start: mention.start,
+                    end: mention.end,
+                    tag: mention.username.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+fn non_empty<T>(items: Vec<T>) -> Option<Vec<T>> {
+    if items.is_empty() {
+        None
+    } else {
+        Some(items)
+    }
+}
+
+impl From<TweetRecord> for Tweet {
+    fn from(record: TweetRecord) -> Tweet {
+        let media_keys = non_empty(
+            record
+                .media_keys
+                .into_iter()
+                .map(|media_key| media_key.into())
+                .collect(),
+        );
+        let poll_ids = non_empty(record.poll_ids.into_iter().map(NumericId::new).collect());
+        let urls = non_empty(
+            record
+                .urls
+                .into_iter()
+                .map(|url| UrlEntity {
+                    start: url.start,
+                    end: url.end,
+                    url: url.url,
+                    expanded_url: url.expanded_url,
+                    display_url: url.display_url,
+                    images: None,
+                    status: None,
+                    title: None,
+                    description: None,
+                    unwound_url: None,
+                })
+                .collect(),
+        );
+        let hashtags = non_empty(
+            record
+                .hashtags
+                .into_iter()
+                .map(|hashtag| HashtagEntity {
+                    start: hashtag.start,
+                    end: hashtag.end,
+                    tag: hashtag.tag,
+                })
+                .collect(),
+        );
+        let mentions = non_empty(
+            record
+                .mentions
+                .into_iter()
+                .map(|mention| MentionEntity {
+                    start: mention.start,
+                    end: mention.end,
+                    username: mention.tag,
+                    id: None,
+                })
+                .collect(),
+        );
+        let has_entities = urls.is_some() || hashtags.is_some() || mentions.is_some();
+        let has_attachments = media_keys.is_some() || poll_ids.is_some();
+        Tweet {
+            id: NumericId::new(record.id),
+            text: record.text,
+            attachments: has_attachments.then_some(Attachments {
+                media_keys,
+                poll_ids,
+            }),
+            author_id: record.author_id.map(NumericId::new),
+            context_annotations: None,
+            conversation_id: record.conversation_id.map(NumericId::new),
+            created_at: record.created_at,
+            entities: has_entities.then_some(FullTextEntities {
+                urls,
+                hashtags,
+                annotations: None,
+                cashtags: None,
+                mentions,
+            }),
+            geo: None,
+            in_reply_to_user_id: record.in_reply_to_user_id.map(NumericId::new),
+            lang: record.lang,
+            non_public_metrics: None,
+            organic_metrics: None,
+            possibly_sensitive: None,
+            promoted_metrics: None,
+            public_metrics: record.metrics.map(|metrics| TweetPublicMetrics {
+                retweet_count: metrics.retweets,
+                reply_count: metrics.replies,
+                like_count: metrics.likes,
+                quote_count: metrics.quotes,
+            }),
+            referenced_tweets: non_empty(
+                record
+                    .referenced_tweets
+                    .into_iter()
+                    .map(|referenced_tweet| ReferencedTweet {
+                        kind: match referenced_tweet.kind {
+                            ReferenceKind::RepliedTo => ReferencedTweetKind::RepliedTo,
+                            ReferenceKind::Quoted => ReferencedTweetKind::Quoted,
+                            ReferenceKind::Retweeted => ReferencedTweetKind::Retweeted,
+                        },
+                        id: NumericId::new(referenced_tweet.id),
+                    })
+                    .collect(),
+            ),
+            reply_settings: None,
+            source: None,
+            withheld: None,
+        }
+    }
+}
+
+impl From<&User> for UserRecord {
+    fn from(user: &User) -> UserRecord {
+        UserRecord {
+            id: user.id.as_u64(),
+            name: user.name.clone(),
+            username: user.username.clone(),
+            created_at: user.created_at,
+            description: user.description.clone(),
+            location: user.location.clone(),
+            pinned_tweet_id: user.pinned_tweet_id.map(NumericId::as_u64),
+            profile_image_url: user.profile_image_url.as_ref().map(|url| url.to_string()),
+            protected: user.protected,
+            url: user.url.clone(),
+            verified: user.verified,
+        }
+    }
+}
+
+impl From<UserRecord> for User {
+    fn from(record: UserRecord) -> User {
+        User {
+            id: NumericId::new(record.id),
+            name: record.name,
+            username: record.username,
+            created_at: record.created_at,
+            description: record.description,
+            entities: None,
+            location: record.location,
+            pinned_tweet_id: record.pinned_tweet_id.map(NumericId::new),
+            profile_image_url: record.profile_image_url.and_then(|url| url.parse().ok()),
+            protected: record.protected,
+            public_metrics: None,
+            url: record.url,
+            verified: record.verified,
+            withheld: None,
+        }
+    }
+}
+
+pub fn tweets_to_records(tweets: &[Tweet]) -> Vec<TweetRecord> {
+    tweets.iter().map(TweetRecord::from).collect()
+}
+
+pub fn tweets_from_records(records: Vec<TweetRecord>) -> Vec<Tweet> {
+    records.into_iter().map(Tweet::from).collect()
+}
+
+pub fn conversations_to_records(conversations: &[Vec<Tweet>]) -> Vec<Vec<TweetRecord>> {
+    conversations
+        .iter()
+        .map(|conversation| tweets_to_records(conversation))
+        .collect()
+}
+
+pub fn conversations_from_records(records: Vec<Vec<TweetRecord>>) -> Vec<Vec<Tweet>> {
+    records.into_iter().map(tweets_from_records).collect()
+}
+
+pub fn users_to_records(users: &[User]) -> Vec<UserRecord> {
+    users.iter().map(UserRecord::from).collect()
+}
+
+pub fn users_from_records(records: Vec<UserRecord>) -> Vec<User> {
+    records.into_iter().map(User::from).collect()
+}
@@ -0,0 +1,152 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use regex::{Regex, RegexBuilder};
+use twitter_v2::Tweet;
+
+use super::archive::Archive;
+use super::cache::Cache;
+
+pub mod index;
+pub mod query;
+pub mod threads;
+
+// The regex crate never backtracks, so matching is linear in the length of the text,
+// but a pattern can still compile to a huge automaton or be slow over a large archive.
+const REGEX_MAX_LENGTH: usize = 1000;
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+const SEARCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mode {
+    //twitter's search operators, see query.rs
+    #[default]
+    Query,
+    //the same operators, with terms and phrases tolerating typos
+    Fuzzy,
+    //a regular expression matched against each tweet's text
+    Regex,
+}
+
+impl Mode {
+    pub fn from_name(name: &str) -> Option<Mode> {
+        match name {
+            "query" => Some(Mode::Query),
+            "fuzzy" => Some(Mode::Fuzzy),
+            "regex" => Some(Mode::Regex),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SearchError {
+    Query(query::ParseError),
+    Regex(String),
+    TimedOut,
+}
+
+impl fmt::Display for SearchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchError::Query(error) => write!(f, "Invalid search query: {error}"),
+            SearchError::Regex(error) => write!(f, "Invalid regex: {error}"),
+            SearchError::TimedOut => write!(
+                f,
+                "The search took longer than {} seconds, try a narrower query",
+                SEARCH_TIMEOUT.as_secs()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SearchError {}
+
+impl From<query::ParseError> for SearchError {
+    fn from(error: query::ParseError) -> Self {
+        SearchError::Query(error)
+    }
+}
+
+fn regex(pattern: &str) -> Result<Regex, SearchError> {
+    if pattern.len() > REGEX_MAX_LENGTH {
+        return Err(SearchError::Regex(format!(
+            "patterns can be at most {REGEX_MAX_LENGTH} characters long"
+        )));
+    }
+    RegexBuilder::new(pattern)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .dfa_size_limit(REGEX_SIZE_LIMIT)
+        .build()
+        .map_err(|error| SearchError::Regex(error.to_string()))
+}
+
+// Every archived tweet that matches, best BM25 match for the tokens first, then newest first.
+// Only the `candidates` are tried if the index could tell which tweets those are.
+fn ranked<'a>(
+    cache: &'a Cache,
+    tokens: &[String],
+    candidates: Option<HashSet<u64>>,
+    matches: impl Fn(&Tweet) -> bool,
+) -> Result<Vec<&'a Tweet>, SearchError> {
+    let started = Instant::now();
+    let tweets: Box<dyn Iterator<Item = &Tweet>> = match candidates {
+        Some(candidates) => Box::new(candidates.into_iter().filter_map(|id| cache.tweet(id))),
+        None => Box::new(cache.tweets.iter()),
+    };
+    let mut results: Vec<(f64, &Tweet)> = Vec::new();
+    for (i, tweet) in tweets.enumerate() {
+        if i % 1000 == 0 && started.elapsed() > SEARCH_TIMEOUT {
+            return Err(SearchError::TimedOut);
+        }
+        if matches(tweet) {
+            results.push((cache.index.score(tweet.id.as_u64(), tokens), tweet));
+        }
+    }
+    results.sort_by(|(a_score, a), (b_score, b)| {
+        b_score
+            .partial_cmp(a_score)
+            .unwrap_or(Ordering::Equal)
+            .then(b.id.cmp(&a.id))
+    });
+    Ok(results.into_iter().map(|(_, tweet)| tweet).collect())
+}
+
+fn find<'a>(cache: &'a Cache, query: &str, mode: Mode) -> Result<Vec<&'a Tweet>, SearchError> {
+    match mode {
+        Mode::Query | Mode::Fuzzy => {
+            let mut query = query::parse(query)?;
+            if mode == Mode::Fuzzy {
+                query = query.fuzzy(&cache.index);
+            }
+            let candidates = query.candidates(&cache.index);
+            ranked(cache, &query.ranking_tokens(), candidates, |tweet| {
+                query.matches(tweet, &tweet.text.to_lowercase(), cache)
+            })
+        }
+        Mode::Regex => {
+            let regex = regex(query)?;
+            ranked(cache, &[], None, |tweet| regex.is_match(&tweet.text))
+        }
+    }
+}
+
+pub fn search(archive: &Archive, query: &str, mode: Mode) -> Result<Vec<Tweet>, SearchError> {
+    let cache = archive.read();
+    Ok(find(&cache, query, mode)?.into_iter().cloned().collect())
+}
+
+//the same results, each shown within its thread and grouped by conversation
+pub fn search_by_conversation(
+    archive: &Archive,
+    query: &str,
+    mode: Mode,
+) -> Result<Vec<threads::ConversationMatches>, SearchError> {
+    let cache = archive.read();
+    Ok(threads::group_by_conversation(
+        &find(&cache, query, mode)?,
+        &cache,
+    ))
+}
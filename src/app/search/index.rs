@@ -0,0 +1,211 @@
+use std::collections::{HashMap, HashSet};
+
+use rust_stemmers::{Algorithm, Stemmer};
+use serde::{Deserialize, Serialize};
+use twitter_v2::Tweet;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::app::io::{self, layout::Layout};
+
+//bump whenever `tokenize` changes, so that indexes built by an older version get rebuilt
+const TOKENIZER_VERSION: u32 = 1;
+
+//BM25 parameters
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+//how many tweets are added to the index before it's written again, unless a quarter of those
+//it had when it was last written is more
+const SAVE_AFTER: usize = 1000;
+
+// An inverted index of every archived tweet's text, persisted next to the archive. It can always
+// be rebuilt from the archived tweets, and `load` adds the ones it's missing, so it isn't
+// written every time a tweet is archived but once it has grown by enough since it last was.
+//
+// The index on disk therefore lags behind the archive, by up to SAVE_AFTER tweets or a quarter of
+// those it had when last written, whichever is more. Nothing is lost: searches use the index in
+// memory, which is always complete, and the next `load` tokenizes the tweets the file is missing
+// before writing it again. Only that catching up, at startup, grows with the lag.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Index {
+    tokenizer_version: u32,
+    //token -> tweet id -> how often the token occurs in that tweet
+    postings: HashMap<String, HashMap<u64, u32>>,
+    //tweet id -> number of tokens in that tweet
+    lengths: HashMap<u64, u32>,
+    total_length: u64,
+    //how many tweets the index had when it was last written or read
+    #[serde(skip)]
+    saved: usize,
+}
+
+fn is_emoji(c: char) -> bool {
+    matches!(c as u32,
+        0x1F000..=0x1FAFF | 0x2600..=0x27BF | 0x2300..=0x23FF | 0x2B00..=0x2BFF | 0x3030 | 0x303D)
+}
+
+// Words are split on Unicode word boundaries, lowercased and stemmed.
+// Hashtags and mentions are kept whole with their '#' or '@' and are not stemmed,
+// and emoji (including ZWJ sequences) become tokens of their own.
+pub fn tokenize(text: &str) -> Vec<String> {
+    let stemmer = Stemmer::create(Algorithm::English);
+    let mut tokens = Vec::new();
+    let mut prefix = None;
+    for segment in text.split_word_bounds() {
+        if segment == "#" || segment == "@" {
+            prefix = Some(segment);
+            continue;
+        }
+        if segment.chars().any(char::is_alphanumeric) {
+            let word = segment.to_lowercase();
+            match prefix.take() {
+                Some(prefix) => tokens.push(format!("{prefix}{word}")),
+                None => tokens.push(stemmer.stem(&word).into_owned()),
+            }
+        } else {
+            prefix = None;
+            if segment.chars().any(is_emoji) {
+                tokens.push(segment.to_string());
+            }
+        }
+    }
+    tokens
+}
+
+impl Index {
+    pub fn len(&self) -> usize {
+        self.lengths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lengths.is_empty()
+    }
+
+    pub fn add(&mut self, tweets: &[Tweet]) {
+        for tweet in tweets {
+            let id = tweet.id.as_u64();
+            if self.lengths.contains_key(&id) {
+                continue;
+            }
+            let tokens = tokenize(&tweet.text);
+            for token in &tokens {
+                *self
+                    .postings
+                    .entry(token.clone())
+                    .or_default()
+                    .entry(id)
+                    .or_default() += 1;
+            }
+            self.lengths.insert(id, tokens.len() as u32);
+            self.total_length += tokens.len() as u64;
+        }
+    }
+
+    pub fn build(tweets: &[Tweet]) -> Index {
+        let mut index = Index {
+            tokenizer_version: TOKENIZER_VERSION,
+            ..Index::default()
+        };
+        index.add(tweets);
+        index
+    }
+
+    //the ids of the tweets the token occurs in
+    pub fn tweets_with(&self, token: &str) -> HashSet<u64> {
+        self.postings
+            .get(token)
+            .map(|tweets| tweets.keys().copied().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn contains(&self, tweet_id: u64, token: &str) -> bool {
+        self.postings
+            .get(token)
+            .is_some_and(|tweets| tweets.contains_key(&tweet_id))
+    }
+
+    //every indexed token within the given edit distance of the token, including the token itself
+    pub fn similar_tokens(&self, token: &str, max_distance: usize) -> Vec<String> {
+        let length = token.chars().count();
+        let mut similar: Vec<String> = self
+            .postings
+            .keys()
+            .filter(|candidate| candidate.chars().count().abs_diff(length) <= max_distance)
+            .filter(|candidate| strsim::levenshtein(token, candidate) <= max_distance)
+            .cloned()
+            .collect();
+        if !similar.iter().any(|candidate| candidate == token) {
+            similar.push(token.to_string());
+        }
+        similar
+    }
+
+    //Okapi BM25 score of a tweet for the given query tokens
+    pub fn score(&self, tweet_id: u64, tokens: &[String]) -> f64 {
+        let length = match self.lengths.get(&tweet_id) {
+            Some(&length) => length as f64,
+            None => return 0.0,
+        };
+        let documents = self.lengths.len() as f64;
+        let average_length = self.total_length as f64 / documents;
+        tokens
+            .iter()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .filter_map(|token| self.postings.get(token))
+            .filter_map(|tweets| {
+                tweets.get(&tweet_id).map(|&frequency| {
+                    let frequency = frequency as f64;
+                    let matching = tweets.len() as f64;
+                    let idf = ((documents - matching + 0.5) / (matching + 0.5) + 1.0).ln();
+                    idf * frequency * (K1 + 1.0)
+                        / (frequency + K1 * (1.0 - B + B * length / average_length))
+                })
+            })
+            .sum()
+    }
+}
+
+//loads the persisted index, bringing it up to date with the archive's tweets
+pub fn load(layout: &Layout, tweets: &[Tweet]) -> Index {
+    let index: Option<Index> = io::read::search_index_string_from_ron(layout)
+        .ok()
+        .and_then(|index_string| ron::from_str(&index_string).ok());
+    let tweet_ids: HashSet<u64> = tweets.iter().map(|tweet| tweet.id.as_u64()).collect();
+    match index {
+        Some(mut index)
+            if index.tokenizer_version == TOKENIZER_VERSION
+                && index.lengths.keys().all(|id| tweet_ids.contains(id)) =>
+        {
+            index.saved = index.len();
+            if index.len() < tweets.len() {
+                let missing = tweets.len() - index.len();
+                println!("Adding {missing} tweets to the search index");
+                index.add(tweets);
+                save(layout, &mut index);
+            }
+            index
+        }
+        _ => rebuild(layout, tweets),
+    }
+}
+
+pub fn rebuild(layout: &Layout, tweets: &[Tweet]) -> Index {
+    let count = tweets.len();
+    println!("Building the search index for {count} tweets");
+    let mut index = Index::build(tweets);
+    save(layout, &mut index);
+    index
+}
+
+pub fn save(layout: &Layout, index: &mut Index) {
+    io::write::search_index_to_ron(layout, index);
+    index.saved = index.len();
+}
+
+//writes the index if enough tweets were added since it last was
+pub fn save_if_grown(layout: &Layout, index: &mut Index) {
+    if index.len() - index.saved >= SAVE_AFTER.max(index.saved / 4) {
+        save(layout, index);
+    }
+}
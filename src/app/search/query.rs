@@ -0,0 +1,456 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time};
+use twitter_v2::data::ReferencedTweetKind;
+use twitter_v2::Tweet;
+
+use super::index::{self, Index};
+use crate::app::cache::Cache;
+
+// A query modeled on Twitter's own search operators, for example
+//
+//     from:yudapearl (causal OR causality) -"machine learning" since:2021-01-01 is:reply
+//
+// Terms next to each other are ANDed, OR binds looser than AND, and NOT or a leading '-'
+// negates the term, phrase or parenthesised group that follows it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    And(Vec<Query>),
+    Or(Vec<Query>),
+    Not(Box<Query>),
+    //a word, matched against the search index by its tokens, or as text if it has none
+    Term { text: String, tokens: Vec<String> },
+    Phrase(String),
+    From(String),
+    To(String),
+    Since(Date),
+    Until(Date),
+    Is(Kind),
+    Has(Feature),
+    Conversation(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Reply,
+    Quote,
+    Retweet,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    Media,
+    Links,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+    pub position: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (at character {})", self.message, self.position)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LeftParen,
+    RightParen,
+    And,
+    Or,
+    Not,
+    Phrase(String),
+    Word(String),
+}
+
+fn tokenize(query: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = query.char_indices().peekable();
+    while let Some(&(position, c)) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push((position, Token::LeftParen));
+            }
+            ')' => {
+                chars.next();
+                tokens.push((position, Token::RightParen));
+            }
+            '"' => {
+                chars.next();
+                let mut phrase = String::new();
+                let mut closed = false;
+                for (_, c) in chars.by_ref() {
+                    if c == '"' {
+                        closed = true;
+                        break;
+                    }
+                    phrase.push(c);
+                }
+                if !closed {
+                    return Err(ParseError {
+                        message: "Unclosed quote".to_string(),
+                        position,
+                    });
+                }
+                tokens.push((position, Token::Phrase(phrase)));
+            }
+            '-' => {
+                chars.next();
+                match chars.peek() {
+                    Some(&(_, next)) if !next.is_whitespace() && next != ')' => {
+                        tokens.push((position, Token::Not))
+                    }
+                    _ => tokens.push((position, Token::Word("-".to_string()))),
+                }
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&(_, c)) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' || c == '"' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                let token = match word.as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    _ => Token::Word(word),
+                };
+                tokens.push((position, token));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens
+            .get(self.next)
+            .map(|&(position, _)| position)
+            .unwrap_or(self.end)
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, ParseError> {
+        Err(ParseError {
+            message: message.into(),
+            position: self.position(),
+        })
+    }
+
+    fn or(&mut self) -> Result<Query, ParseError> {
+        let mut alternatives = vec![self.and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.next += 1;
+            alternatives.push(self.and()?);
+        }
+        Ok(match alternatives.len() {
+            1 => alternatives.remove(0),
+            _ => Query::Or(alternatives),
+        })
+    }
+
+    fn and(&mut self) -> Result<Query, ParseError> {
+        let mut terms = vec![self.unary()?];
+        loop {
+            match self.peek() {
+                Some(Token::And) => {
+                    self.next += 1;
+                    terms.push(self.unary()?);
+                }
+                Some(Token::Or) | Some(Token::RightParen) | None => break,
+                Some(_) => terms.push(self.unary()?),
+            }
+        }
+        Ok(match terms.len() {
+            1 => terms.remove(0),
+            _ => Query::And(terms),
+        })
+    }
+
+    fn unary(&mut self) -> Result<Query, ParseError> {
+        let position = self.position();
+        match self.tokens.get(self.next).map(|(_, token)| token.clone()) {
+            Some(Token::Not) => {
+                self.next += 1;
+                Ok(Query::Not(Box::new(self.unary()?)))
+            }
+            Some(Token::LeftParen) => {
+                self.next += 1;
+                let query = self.or()?;
+                match self.peek() {
+                    Some(Token::RightParen) => {
+                        self.next += 1;
+                        Ok(query)
+                    }
+                    _ => self.error("Expected ')'"),
+                }
+            }
+            Some(Token::Phrase(phrase)) => {
+                self.next += 1;
+                Ok(Query::Phrase(phrase.to_lowercase()))
+            }
+            Some(Token::Word(word)) => {
+                self.next += 1;
+                operator(&word, position)
+            }
+            Some(Token::RightParen) => self.error("Unexpected ')'"),
+            Some(Token::And) | Some(Token::Or) => self.error("Expected a term before AND/OR"),
+            None => self.error("Expected a term"),
+        }
+    }
+}
+
+fn parse_date(value: &str, position: usize) -> Result<Date, ParseError> {
+    let error = || ParseError {
+        message: format!("\"{value}\" is not a date like 2021-01-31"),
+        position,
+    };
+    let mut parts = value.splitn(3, '-');
+    let mut part = || parts.next().ok_or_else(error);
+    let year: i32 = part()?.parse().map_err(|_| error())?;
+    let month: u8 = part()?.parse().map_err(|_| error())?;
+    let day: u8 = part()?.parse().map_err(|_| error())?;
+    let month = Month::try_from(month).map_err(|_| error())?;
+    Date::from_calendar_date(year, month, day).map_err(|_| error())
+}
+
+fn term(word: &str) -> Query {
+    Query::Term {
+        text: word.to_lowercase(),
+        tokens: index::tokenize(word),
+    }
+}
+
+fn operator(word: &str, position: usize) -> Result<Query, ParseError> {
+    let (name, value) = match word.split_once(':') {
+        Some((name, value)) if !value.is_empty() => (name, value),
+        _ => return Ok(term(word)),
+    };
+    let value_position = position + name.len() + 1;
+    let unknown = |what: &str| ParseError {
+        message: format!("Unknown {what} \"{value}\""),
+        position: value_position,
+    };
+    match name {
+        "from" => Ok(Query::From(value.trim_start_matches('@').to_string())),
+        "to" => Ok(Query::To(value.trim_start_matches('@').to_string())),
+        "since" => Ok(Query::Since(parse_date(value, value_position)?)),
+        "until" => Ok(Query::Until(parse_date(value, value_position)?)),
+        "is" => match value {
+            "reply" => Ok(Query::Is(Kind::Reply)),
+            "quote" => Ok(Query::Is(Kind::Quote)),
+            "retweet" => Ok(Query::Is(Kind::Retweet)),
+            _ => Err(unknown("is: filter")),
+        },
+        "has" => match value {
+            "media" => Ok(Query::Has(Feature::Media)),
+            "links" => Ok(Query::Has(Feature::Links)),
+            _ => Err(unknown("has: filter")),
+        },
+        "conversation" | "conversation_id" => value
+            .parse()
+            .map(Query::Conversation)
+            .map_err(|_| unknown("conversation id")),
+        //anything else with a colon, like a url, is searched for as it is
+        _ => Ok(term(word)),
+    }
+}
+
+pub fn parse(query: &str) -> Result<Query, ParseError> {
+    let tokens = tokenize(query)?;
+    let mut parser = Parser {
+        tokens,
+        next: 0,
+        end: query.len(),
+    };
+    let parsed = parser.or()?;
+    match parser.peek() {
+        None => Ok(parsed),
+        Some(_) => parser.error("Unexpected ')'"),
+    }
+}
+
+//how many typos a fuzzy term may contain, short words have to match exactly
+fn max_distance(token: &str) -> usize {
+    match token.chars().count() {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    }
+}
+
+//each token has to match one of the indexed tokens similar to it
+fn fuzzy_tokens(tokens: Vec<String>, index: &Index) -> Query {
+    Query::And(
+        tokens
+            .into_iter()
+            .map(|token| {
+                let similar = index.similar_tokens(&token, max_distance(&token));
+                Query::Or(
+                    similar
+                        .into_iter()
+                        .map(|token| Query::Term {
+                            text: token.clone(),
+                            tokens: vec![token],
+                        })
+                        .collect(),
+                )
+            })
+            .collect(),
+    )
+}
+
+fn start_of_day(date: Date) -> OffsetDateTime {
+    PrimitiveDateTime::new(date, Time::MIDNIGHT).assume_utc()
+}
+
+impl Query {
+    //the tokens of every term and phrase that isn't negated, used to rank the results
+    pub fn ranking_tokens(&self) -> Vec<String> {
+        match self {
+            Query::And(queries) | Query::Or(queries) => {
+                queries.iter().flat_map(Query::ranking_tokens).collect()
+            }
+            Query::Term { tokens, .. } => tokens.clone(),
+            Query::Phrase(phrase) => index::tokenize(phrase),
+            _ => Vec::new(),
+        }
+    }
+
+    // The ids of the tweets the query can match, from the index's postings lists, or None if it
+    // can't tell and every tweet has to be tried. Only terms narrow it down: a phrase matches
+    // text, which can start or end in the middle of an indexed word, and a negation can match
+    // any tweet the index doesn't list.
+    pub fn candidates(&self, index: &Index) -> Option<HashSet<u64>> {
+        match self {
+            Query::And(queries) => queries
+                .iter()
+                .filter_map(|query| query.candidates(index))
+                .reduce(|candidates, other| &candidates & &other),
+            Query::Or(queries) => queries
+                .iter()
+                .map(|query| query.candidates(index))
+                .try_fold(HashSet::new(), |candidates, other| {
+                    Some(&candidates | &other?)
+                }),
+            Query::Term { tokens, .. } if !tokens.is_empty() => tokens
+                .iter()
+                .map(|token| index.tweets_with(token))
+                .reduce(|candidates, other| &candidates & &other),
+            _ => None,
+        }
+    }
+
+    //makes every term and phrase tolerate typos, phrases then match their words in any order
+    pub fn fuzzy(self, index: &Index) -> Query {
+        match self {
+            Query::And(queries) => Query::And(
+                queries
+                    .into_iter()
+                    .map(|query| query.fuzzy(index))
+                    .collect(),
+            ),
+            Query::Or(queries) => Query::Or(
+                queries
+                    .into_iter()
+                    .map(|query| query.fuzzy(index))
+                    .collect(),
+            ),
+            Query::Not(query) => Query::Not(Box::new(query.fuzzy(index))),
+            Query::Term { tokens, .. } if !tokens.is_empty() => fuzzy_tokens(tokens, index),
+            Query::Phrase(phrase) => match index::tokenize(&phrase) {
+                tokens if tokens.is_empty() => Query::Phrase(phrase),
+                tokens => fuzzy_tokens(tokens, index),
+            },
+            query => query,
+        }
+    }
+
+    //text comparisons are made against the tweet's lowercased text
+    pub fn matches(&self, tweet: &Tweet, text: &str, cache: &Cache) -> bool {
+        match self {
+            Query::And(queries) => queries
+                .iter()
+                .all(|query| query.matches(tweet, text, cache)),
+            Query::Or(queries) => queries
+                .iter()
+                .any(|query| query.matches(tweet, text, cache)),
+            Query::Not(query) => !query.matches(tweet, text, cache),
+            Query::Term { text: term, tokens } if tokens.is_empty() => text.contains(term.as_str()),
+            Query::Term { tokens, .. } => {
+                let id = tweet.id.as_u64();
+                tokens.iter().all(|token| cache.index.contains(id, token))
+            }
+            Query::Phrase(phrase) => text.contains(phrase.as_str()),
+            Query::From(twitter_handle) => match cache.user_from_twitter_handle(twitter_handle) {
+                Some(user) => tweet.author_id == Some(user.id),
+                None => false,
+            },
+            Query::To(twitter_handle) => match cache.user_from_twitter_handle(twitter_handle) {
+                Some(user) => {
+                    tweet.in_reply_to_user_id == Some(user.id)
+                        || Cache::referenced_ids(tweet, ReferencedTweetKind::RepliedTo).any(|id| {
+                            cache
+                                .tweet(id)
+                                .is_some_and(|replied_to| replied_to.author_id == Some(user.id))
+                        })
+                }
+                None => false,
+            },
+            Query::Since(date) => tweet
+                .created_at
+                .is_some_and(|created_at| created_at >= start_of_day(*date)),
+            //like Twitter's until:, the date itself is not included
+            Query::Until(date) => tweet
+                .created_at
+                .is_some_and(|created_at| created_at < start_of_day(*date)),
+            Query::Is(kind) => {
+                let kind = match kind {
+                    Kind::Reply => ReferencedTweetKind::RepliedTo,
+                    Kind::Quote => ReferencedTweetKind::Quoted,
+                    Kind::Retweet => ReferencedTweetKind::Retweeted,
+                };
+                Cache::referenced_ids(tweet, kind).next().is_some()
+            }
+            Query::Has(Feature::Media) => tweet
+                .attachments
+                .as_ref()
+                .and_then(|attachments| attachments.media_keys.as_ref())
+                .is_some_and(|media_keys| !media_keys.is_empty()),
+            Query::Has(Feature::Links) => match tweet
+                .entities
+                .as_ref()
+                .and_then(|entities| entities.urls.as_ref())
+            {
+                Some(urls) => !urls.is_empty(),
+                None => text.contains("http://") || text.contains("https://"),
+            },
+            Query::Conversation(conversation_id) => tweet
+                .conversation_id
+                .is_some_and(|id| id.as_u64() == *conversation_id),
+        }
+    }
+}
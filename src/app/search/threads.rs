@@ -0,0 +1,75 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::Serialize;
+use twitter_v2::Tweet;
+
+use crate::app::cache::Cache;
+
+//a matching tweet shown in place within its thread
+#[derive(Debug, Clone, Serialize)]
+pub struct ThreadMatch {
+    //the tweets it replies to, the root of the thread first
+    pub ancestors: Vec<Tweet>,
+    pub tweet: Tweet,
+    //other archived replies to the same tweet, only by id
+    pub collapsed_siblings: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConversationMatches {
+    pub conversation_id: u64,
+    pub matches: Vec<ThreadMatch>,
+}
+
+fn ancestors(tweet: &Tweet, cache: &Cache) -> Vec<Tweet> {
+    let mut ancestors = Vec::new();
+    let mut seen = HashSet::from([tweet.id.as_u64()]);
+    let mut current = tweet;
+    while let Some(parent) = Cache::replied_to_id(current).and_then(|id| cache.tweet(id)) {
+        if !seen.insert(parent.id.as_u64()) {
+            break;
+        }
+        ancestors.push(parent.clone());
+        current = parent;
+    }
+    ancestors.reverse();
+    ancestors
+}
+
+fn thread_match(tweet: &Tweet, cache: &Cache) -> ThreadMatch {
+    let collapsed_siblings = match Cache::replied_to_id(tweet) {
+        Some(replied_to_id) => cache
+            .replies_to(replied_to_id)
+            .into_iter()
+            .map(|sibling| sibling.id.as_u64())
+            .filter(|&id| id != tweet.id.as_u64())
+            .collect(),
+        None => Vec::new(),
+    };
+    ThreadMatch {
+        ancestors: ancestors(tweet, cache),
+        tweet: tweet.clone(),
+        collapsed_siblings,
+    }
+}
+
+//groups ranked results by conversation, keeping the order in which each conversation first appears
+pub fn group_by_conversation(results: &[&Tweet], cache: &Cache) -> Vec<ConversationMatches> {
+    let mut groups: Vec<ConversationMatches> = Vec::new();
+    let mut positions: HashMap<u64, usize> = HashMap::new();
+    for tweet in results {
+        let conversation_id = tweet
+            .conversation_id
+            .map(|id| id.as_u64())
+            .unwrap_or_else(|| tweet.id.as_u64());
+        let position = *positions.entry(conversation_id).or_insert_with(|| {
+            groups.push(ConversationMatches {
+                conversation_id,
+                matches: Vec::new(),
+            });
+            groups.len() - 1
+        });
+        groups[position].matches.push(thread_match(tweet, cache));
+    }
+    groups
+}
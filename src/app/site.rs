@@ -0,0 +1,32 @@
+use serde::Deserialize;
+
+use super::archive::{Archive, DEFAULT_ARCHIVE};
+
+//where the archiver can be reached from outside, for the absolute links in feeds and activitypub documents
+#[derive(Debug, Clone, Deserialize)]
+pub struct Site {
+    #[serde(default = "default_public_url")]
+    pub public_url: String,
+}
+
+fn default_public_url() -> String {
+    "http://localhost:8000".to_string()
+}
+
+impl Site {
+    //path starts with a '/'
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{path}", self.public_url.trim_end_matches('/'))
+    }
+
+    //the same, with "archive=<name>" added to the query unless it's the default archive
+    pub fn archive_url(&self, archive: &Archive, path: &str) -> String {
+        match archive.name.as_str() {
+            DEFAULT_ARCHIVE => self.url(path),
+            name => {
+                let separator = if path.contains('?') { '&' } else { '?' };
+                self.url(&format!("{path}{separator}archive={name}"))
+            }
+        }
+    }
+}
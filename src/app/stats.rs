@@ -0,0 +1,445 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use serde::Serialize;
+use twitter_v2::data::ReferencedTweetKind;
+use twitter_v2::{Tweet, User};
+
+use super::archive::Archive;
+use super::cache::Cache;
+use super::format::{escape, mentions, tags_in_text};
+use super::site::Site;
+
+//how many hashtags, mentions, domains and conversations are listed
+const TOP: usize = 10;
+
+const WEEKDAYS: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+
+// Statistics about a user's archived tweets. Times are in UTC,
+// and tweets without a creation date are only counted in `tweets` and `kinds`.
+#[derive(Debug, Clone, Serialize)]
+pub struct UserStats {
+    pub twitter_handle: String,
+    pub user_id: u64,
+    pub tweets: usize,
+    //keyed by yyyy-mm-dd, yyyy-Www (ISO weeks) and yyyy-mm
+    pub per_day: BTreeMap<String, usize>,
+    pub per_week: BTreeMap<String, usize>,
+    pub per_month: BTreeMap<String, usize>,
+    //tweets per hour of the day, and per hour of each weekday starting on Monday
+    pub hours: [usize; 24],
+    pub weekdays: [usize; 7],
+    pub weekday_hours: [[usize; 24]; 7],
+    pub kinds: Kinds,
+    pub top_hashtags: Vec<Count>,
+    pub top_mentions: Vec<Count>,
+    pub top_domains: Vec<Count>,
+    pub longest_conversations: Vec<ConversationLength>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Kinds {
+    pub original: Share,
+    pub replies: Share,
+    pub quotes: Share,
+    pub retweets: Share,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Share {
+    pub count: usize,
+    //the fraction of all the user's tweets
+    pub ratio: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Count {
+    pub value: String,
+    pub count: usize,
+}
+
+//an archived conversation the user took part in, keyed like everywhere else by its last tweet
+#[derive(Debug, Clone, Serialize)]
+pub struct ConversationLength {
+    pub last_tweet_id: u64,
+    pub conversation_id: Option<u64>,
+    pub tweets: usize,
+    pub tweets_by_user: usize,
+}
+
+fn hashtags(tweet: &Tweet) -> Vec<String> {
+    match tweet
+        .entities
+        .as_ref()
+        .and_then(|entities| entities.hashtags.as_ref())
+    {
+        Some(hashtags) => hashtags.iter().map(|hashtag| hashtag.tag.clone()).collect(),
+        None => tags_in_text(&tweet.text, '#'),
+    }
+}
+
+// The expanded urls if twitter gave them to us, otherwise the links in the text, skipping those
+// cut off at the end of a retweet. Twitter shortens every link in the text to t.co, which says
+// nothing about where it goes, so those are left out too.
+fn urls(tweet: &Tweet) -> Vec<String> {
+    match tweet
+        .entities
+        .as_ref()
+        .and_then(|entities| entities.urls.as_ref())
+    {
+        Some(urls) => urls.iter().map(|url| url.expanded_url.clone()).collect(),
+        None => tweet
+            .text
+            .split_whitespace()
+            .filter(|word| word.starts_with("http://") || word.starts_with("https://"))
+            .filter(|word| !word.ends_with('…'))
+            .filter(|word| domain(word).is_some_and(|domain| domain != "t.co"))
+            .map(str::to_string)
+            .collect(),
+    }
+}
+
+fn domain(url: &str) -> Option<String> {
+    let rest = url.split_once("://").map(|(_, rest)| rest)?;
+    let host = rest.split(['/', '?', '#']).next()?;
+    let host = host.rsplit('@').next()?.split(':').next()?;
+    let host = host.trim_start_matches("www.").to_lowercase();
+    (!host.is_empty()).then_some(host)
+}
+
+fn top(counts: HashMap<String, usize>) -> Vec<Count> {
+    let mut counts: Vec<Count> = counts
+        .into_iter()
+        .map(|(value, count)| Count { value, count })
+        .collect();
+    counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+    counts.truncate(TOP);
+    counts
+}
+
+fn share(count: usize, total: usize) -> Share {
+    Share {
+        count,
+        ratio: match total {
+            0 => 0.0,
+            total => count as f64 / total as f64,
+        },
+    }
+}
+
+//the user's tweets in the archive, from the shared tweets and from their own timeline
+pub fn user_tweets<'a>(cache: &'a Cache, user: &User, twitter_handle: &str) -> Vec<&'a Tweet> {
+    let mut seen = HashSet::new();
+    cache
+        .tweets
+        .iter()
+        .filter(|tweet| tweet.author_id == Some(user.id))
+        .chain(
+            cache
+                .user_tweets
+                .get(&twitter_handle.to_lowercase())
+                .into_iter()
+                .flatten(),
+        )
+        .filter(|tweet| seen.insert(tweet.id.as_u64()))
+        .collect()
+}
+
+fn longest_conversations(
+    cache: &Cache,
+    user: &User,
+    twitter_handle: &str,
+) -> Vec<ConversationLength> {
+    let mut seen = HashSet::new();
+    let mut conversations: Vec<ConversationLength> = cache
+        .conversations
+        .iter()
+        .chain(
+            cache
+                .user_conversations
+                .get(&twitter_handle.to_lowercase())
+                .into_iter()
+                .flatten(),
+        )
+        .filter_map(|conversation| {
+            let last_tweet = conversation.last()?;
+            let tweets_by_user = conversation
+                .iter()
+                .filter(|tweet| tweet.author_id == Some(user.id))
+                .count();
+            (tweets_by_user > 0 && seen.insert(last_tweet.id.as_u64())).then(|| {
+                ConversationLength {
+                    last_tweet_id: last_tweet.id.as_u64(),
+                    conversation_id: last_tweet.conversation_id.map(|id| id.as_u64()),
+                    tweets: conversation.len(),
+                    tweets_by_user,
+                }
+            })
+        })
+        .collect();
+    conversations.sort_by(|a, b| {
+        b.tweets
+            .cmp(&a.tweets)
+            .then(b.last_tweet_id.cmp(&a.last_tweet_id))
+    });
+    conversations.truncate(TOP);
+    conversations
+}
+
+//None if the user isn't in the archive
+pub fn user_stats(archive: &Archive, twitter_handle: &str) -> Option<UserStats> {
+    let cache = archive.read();
+    let user = cache.user_from_twitter_handle(twitter_handle)?;
+    let tweets = user_tweets(&cache, user, twitter_handle);
+    let mut stats = UserStats {
+        twitter_handle: user.username.clone(),
+        user_id: user.id.as_u64(),
+        tweets: tweets.len(),
+        per_day: BTreeMap::new(),
+        per_week: BTreeMap::new(),
+        per_month: BTreeMap::new(),
+        hours: [0; 24],
+        weekdays: [0; 7],
+        weekday_hours: [[0; 24]; 7],
+        kinds: Kinds::default(),
+        top_hashtags: Vec::new(),
+        top_mentions: Vec::new(),
+        top_domains: Vec::new(),
+        longest_conversations: longest_conversations(&cache, user, twitter_handle),
+    };
+    let (mut original, mut replies, mut quotes, mut retweets) = (0, 0, 0, 0);
+    let mut hashtag_counts: HashMap<String, usize> = HashMap::new();
+    let mut mention_counts: HashMap<String, usize> = HashMap::new();
+    let mut domain_counts: HashMap<String, usize> = HashMap::new();
+    for tweet in &tweets {
+        if let Some(created_at) = tweet.created_at {
+            let date = created_at.date();
+            let (week_year, week, _) = date.to_iso_week_date();
+            let (year, month) = (date.year(), date.month() as u8);
+            let weekday = date.weekday().number_days_from_monday() as usize;
+            let hour = created_at.hour() as usize;
+            *stats.per_day.entry(date.to_string()).or_default() += 1;
+            *stats
+                .per_week
+                .entry(format!("{week_year}-W{week:02}"))
+                .or_default() += 1;
+            *stats
+                .per_month
+                .entry(format!("{year}-{month:02}"))
+                .or_default() += 1;
+            stats.hours[hour] += 1;
+            stats.weekdays[weekday] += 1;
+            stats.weekday_hours[weekday][hour] += 1;
+        }
+        if Cache::referenced_ids(tweet, ReferencedTweetKind::Retweeted)
+            .next()
+            .is_some()
+        {
+            retweets += 1;
+        } else if Cache::referenced_ids(tweet, ReferencedTweetKind::RepliedTo)
+            .next()
+            .is_some()
+        {
+            replies += 1;
+        } else if Cache::referenced_ids(tweet, ReferencedTweetKind::Quoted)
+            .next()
+            .is_some()
+        {
+            quotes += 1;
+        } else {
+            original += 1;
+        }
+        for hashtag in hashtags(tweet) {
+            *hashtag_counts.entry(hashtag.to_lowercase()).or_default() += 1;
+        }
+        for mention in mentions(tweet) {
+            *mention_counts.entry(mention.to_lowercase()).or_default() += 1;
+        }
+        for domain in urls(tweet).iter().filter_map(|url| domain(url)) {
+            *domain_counts.entry(domain).or_default() += 1;
+        }
+    }
+    let total = tweets.len();
+    stats.kinds = Kinds {
+        original: share(original, total),
+        replies: share(replies, total),
+        quotes: share(quotes, total),
+        retweets: share(retweets, total),
+    };
+    stats.top_hashtags = top(hashtag_counts);
+    stats.top_mentions = top(mention_counts);
+    stats.top_domains = top(domain_counts);
+    Some(stats)
+}
+
+//a horizontal bar for every value, scaled to the largest
+fn bars<'a>(rows: impl Iterator<Item = (String, usize)> + 'a) -> String {
+    let rows: Vec<(String, usize)> = rows.collect();
+    let max = rows
+        .iter()
+        .map(|(_, count)| *count)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let rows: String = rows
+        .iter()
+        .map(|(label, count)| {
+            let width = count * 100 / max;
+            format!(
+                "<tr><td>{}</td><td class=\"bar\"><div style=\"width:{width}%\"></div></td><td>{count}</td></tr>",
+                escape(label)
+            )
+        })
+        .collect();
+    format!("<table>{rows}</table>")
+}
+
+fn counts(counts: &[Count], prefix: &str) -> String {
+    bars(
+        counts
+            .iter()
+            .map(|count| (format!("{prefix}{}", count.value), count.count)),
+    )
+}
+
+fn heatmap(weekday_hours: &[[usize; 24]; 7]) -> String {
+    let max = weekday_hours
+        .iter()
+        .flatten()
+        .copied()
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let header: String = (0..24).map(|hour| format!("<th>{hour}</th>")).collect();
+    let rows: String = WEEKDAYS
+        .iter()
+        .zip(weekday_hours)
+        .map(|(weekday, hours)| {
+            let cells: String = hours
+                .iter()
+                .map(|&count| {
+                    let opacity = count as f64 / max as f64;
+                    format!(
+                        "<td class=\"cell\" title=\"{count}\" style=\"background:rgba(29,155,240,{opacity:.2})\"></td>"
+                    )
+                })
+                .collect();
+            format!("<tr><th>{weekday}</th>{cells}</tr>")
+        })
+        .collect();
+    format!("<table class=\"heatmap\"><tr><th></th>{header}</tr>{rows}</table>")
+}
+
+//the same statistics as a self-contained page
+pub fn dashboard(stats: &UserStats, archive: &Archive, site: &Site) -> String {
+    let handle = escape(&stats.twitter_handle);
+    let json_url =
+        escape(&site.archive_url(archive, &format!("/user/{}/stats", stats.twitter_handle)));
+    let kinds = [
+        ("Original", &stats.kinds.original),
+        ("Replies", &stats.kinds.replies),
+        ("Quotes", &stats.kinds.quotes),
+        ("Retweets", &stats.kinds.retweets),
+    ];
+    let kinds: String = kinds
+        .iter()
+        .map(|(kind, share)| {
+            format!(
+                "<tr><td>{kind}</td><td>{}</td><td>{:.1}%</td></tr>",
+                share.count,
+                share.ratio * 100.0
+            )
+        })
+        .collect();
+    let conversations: String = stats
+        .longest_conversations
+        .iter()
+        .map(|conversation| {
+            let id = conversation.last_tweet_id;
+            let url = escape(&site.archive_url(archive, &format!("/conversation/{id}")));
+            format!(
+                "<tr><td><a href=\"{url}\">{id}</a></td><td>{}</td><td>{}</td></tr>",
+                conversation.tweets, conversation.tweets_by_user,
+            )
+        })
+        .collect();
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>@{handle}'s archive</title>
+<style>
+body {{ font-family: sans-serif; max-width: 60em; margin: 2em auto; color: #222; }}
+table {{ border-collapse: collapse; margin-bottom: 1em; }}
+td, th {{ padding: 0.2em 0.5em; text-align: left; font-weight: normal; }}
+td.bar {{ width: 30em; }}
+td.bar div {{ background: #1d9bf0; height: 1em; }}
+table.heatmap td.cell {{ width: 1.2em; height: 1.2em; border: 1px solid #eee; }}
+</style>
+</head>
+<body>
+<h1>@{handle}</h1>
+<p>{tweets} archived tweets, times in UTC. <a href="{json_url}">JSON</a></p>
+<h2>Kinds of tweets</h2>
+<table>{kinds}</table>
+<h2>Weekday and hour</h2>
+{heatmap}
+<h2>Hour of the day</h2>
+{hours}
+<h2>Weekday</h2>
+{weekdays}
+<h2>Tweets per month</h2>
+{per_month}
+<h2>Tweets per week</h2>
+{per_week}
+<h2>Top hashtags</h2>
+{hashtags}
+<h2>Top mentions</h2>
+{mentions}
+<h2>Top domains</h2>
+{domains}
+<h2>Longest conversations</h2>
+<table><tr><th>Last tweet</th><th>Tweets</th><th>By @{handle}</th></tr>{conversations}</table>
+</body>
+</html>
+"#,
+        tweets = stats.tweets,
+        heatmap = heatmap(&stats.weekday_hours),
+        hours = bars(
+            stats
+                .hours
+                .iter()
+                .enumerate()
+                .map(|(hour, &count)| (format!("{hour:02}:00"), count))
+        ),
+        weekdays = bars(
+            WEEKDAYS
+                .iter()
+                .zip(stats.weekdays)
+                .map(|(weekday, count)| (weekday.to_string(), count))
+        ),
+        per_month = bars(
+            stats
+                .per_month
+                .iter()
+                .map(|(month, &count)| (month.clone(), count))
+        ),
+        per_week = bars(
+            stats
+                .per_week
+                .iter()
+                .map(|(week, &count)| (week.clone(), count))
+        ),
+        hashtags = counts(&stats.top_hashtags, "#"),
+        mentions = counts(&stats.top_mentions, "@"),
+        domains = counts(&stats.top_domains, ""),
+    )
+}
@@ -0,0 +1,107 @@
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use twitter_v2::Tweet;
+
+use super::api;
+use super::archive::Archive;
+use super::io;
+
+// Tweets that were archived and have since disappeared from twitter, deleted by their author or
+// taken down. The archived tweets themselves are kept, a tombstone only records that and when
+// the deletion was noticed. Each archive keeps its tombstones in "tombstones/tombstones.ron".
+//
+// A sync checks every archived tweet of the user that has no tombstone yet, however old, so a
+// user with many archived tweets costs a lookup request per LOOKUP_SIZE of them.
+
+//how many tweets one lookup request checks, as many as twitter allows
+const LOOKUP_SIZE: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tombstone {
+    pub tweet_id: u64,
+    pub twitter_handle: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub recorded_at: OffsetDateTime,
+}
+
+//tombstones.ron is read, changed and written back while holding this
+static TOMBSTONES: Mutex<()> = Mutex::new(());
+
+pub fn list(archive: &Archive) -> Vec<Tombstone> {
+    let _lock = TOMBSTONES.lock().expect("The tombstones lock was poisoned");
+    read(archive)
+}
+
+fn read(archive: &Archive) -> Vec<Tombstone> {
+    match io::read::string_from_ron(&archive.layout.tombstones()) {
+        Ok(tombstones) => ron::from_str(&tombstones).expect("Failed to parse tombstones from ron"),
+        Err(_) => Vec::new(),
+    }
+}
+
+//records the tweets that don't have a tombstone yet and returns their new tombstones
+fn record(archive: &Archive, twitter_handle: &str, tweet_ids: &[u64]) -> Vec<Tombstone> {
+    let _lock = TOMBSTONES.lock().expect("The tombstones lock was poisoned");
+    let mut tombstones = read(archive);
+    let recorded: BTreeSet<u64> = tombstones
+        .iter()
+        .map(|tombstone| tombstone.tweet_id)
+        .collect();
+    let now = OffsetDateTime::now_utc();
+    let new: Vec<Tombstone> = tweet_ids
+        .iter()
+        .filter(|tweet_id| !recorded.contains(tweet_id))
+        .map(|&tweet_id| Tombstone {
+            tweet_id,
+            twitter_handle: twitter_handle.to_string(),
+            recorded_at: now,
+        })
+        .collect();
+    if !new.is_empty() {
+        tombstones.extend(new.iter().cloned());
+        io::write::value_to_ron(&tombstones, &archive.layout.tombstones());
+    }
+    new
+}
+
+// Looks up the user's archived tweets on twitter, newest first, and records a tombstone for the
+// ones that are gone, returning the tombstones that weren't recorded before.
+pub async fn check(archive: &Archive, twitter_handle: &str, archived: &[Tweet]) -> Vec<Tombstone> {
+    let recorded: BTreeSet<u64> = list(archive)
+        .iter()
+        .map(|tombstone| tombstone.tweet_id)
+        .collect();
+    let mut tweet_ids: Vec<u64> = archived
+        .iter()
+        .map(|tweet| tweet.id.as_u64())
+        .filter(|tweet_id| !recorded.contains(tweet_id))
+        .collect();
+    tweet_ids.sort_unstable_by(|a, b| b.cmp(a));
+    tweet_ids.dedup();
+    if tweet_ids.is_empty() {
+        return Vec::new();
+    }
+    println!(
+        "Checking @{twitter_handle}'s {} archived tweets for deletions",
+        tweet_ids.len()
+    );
+    let mut deleted = Vec::new();
+    for tweet_ids in tweet_ids.chunks(LOOKUP_SIZE) {
+        let existing: BTreeSet<u64> = api::get_existing_tweet_ids(tweet_ids)
+            .await
+            .into_iter()
+            .collect();
+        deleted.extend(
+            tweet_ids
+                .iter()
+                .filter(|tweet_id| !existing.contains(tweet_id)),
+        );
+    }
+    if !deleted.is_empty() {
+        println!("@{twitter_handle} deleted {} tweets", deleted.len());
+    }
+    record(archive, twitter_handle, &deleted)
+}
@@ -0,0 +1,255 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use super::archive::{self, Archive};
+use super::io;
+use super::jobs::{self, Target};
+
+// Users whose new tweets are archived on a schedule. Each archive keeps its watchlist in
+// "jobs/watchlist.ron". A scheduler in the server queues a sync job for a watched user once
+// their interval has passed since the last one was queued, one user at a time with
+// `sync_spacing` seconds in between, so a long watchlist doesn't spend the rate limit at once.
+
+//anything more often and a long watchlist would spend the rate limit on empty syncs
+const MIN_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+//longer and the next sync might as well be never
+const MAX_INTERVAL: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Watched {
+    pub twitter_handle: String,
+    //e.g. "30m", "6h", "1d", "2w", "@hourly", "@daily" or "@weekly"
+    pub interval: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub added_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    pub next_sync: OffsetDateTime,
+    #[serde(
+        default,
+        with = "time::serde::rfc3339::option",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub last_sync: Option<OffsetDateTime>,
+    //tweets found by the last successful sync
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new_tweets: Option<usize>,
+    //why the last sync failed, cleared by the next one that doesn't
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    //the sync job queued or running for the user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SyncConfig {
+    //seconds between two scheduled syncs, across every archive
+    #[serde(default = "default_sync_spacing")]
+    pub sync_spacing: u64,
+}
+
+fn default_sync_spacing() -> u64 {
+    60
+}
+
+//twitter handle (lowercase) -> watched user
+type Watchlist = BTreeMap<String, Watched>;
+
+//watchlist.ron is read, changed and written back while holding this
+static WATCHLIST: Mutex<()> = Mutex::new(());
+
+pub fn parse_interval(interval: &str) -> Result<Duration, String> {
+    let invalid = || {
+        format!(
+            "Invalid interval \"{interval}\", try e.g. \"30m\", \"6h\", \"1d\", \"2w\" or \"@daily\""
+        )
+    };
+    let duration = match interval {
+        "@hourly" => Duration::from_secs(60 * 60),
+        "@daily" => Duration::from_secs(24 * 60 * 60),
+        "@weekly" => Duration::from_secs(7 * 24 * 60 * 60),
+        _ => {
+            let split = interval
+                .find(|c: char| !c.is_ascii_digit())
+                .ok_or_else(invalid)?;
+            let (count, unit) = interval.split_at(split);
+            let count: u64 = count.parse().map_err(|_| invalid())?;
+            let seconds = match unit {
+                "m" => 60,
+                "h" => 60 * 60,
+                "d" => 24 * 60 * 60,
+                "w" => 7 * 24 * 60 * 60,
+                _ => return Err(invalid()),
+            };
+            let too_long = || {
+                format!("The interval \"{interval}\" is too long, syncs have to be at least yearly")
+            };
+            Duration::from_secs(count.checked_mul(seconds).ok_or_else(too_long)?)
+        }
+    };
+    if duration < MIN_INTERVAL {
+        return Err(format!(
+            "The interval \"{interval}\" is too short, syncs can be at most every 15 minutes"
+        ));
+    }
+    if duration > MAX_INTERVAL {
+        return Err(format!(
+            "The interval \"{interval}\" is too long, syncs have to be at least yearly"
+        ));
+    }
+    Ok(duration)
+}
+
+//when the next sync is due, rather than panicking with the watchlist locked if that's past the
+//end of time
+fn next_sync(after: OffsetDateTime, interval: Duration) -> OffsetDateTime {
+    time::Duration::try_from(interval)
+        .ok()
+        .and_then(|interval| after.checked_add(interval))
+        .unwrap_or(after)
+}
+
+fn read(archive: &Archive) -> Watchlist {
+    match io::read::string_from_ron(&archive.layout.watchlist()) {
+        Ok(watchlist) => ron::from_str(&watchlist).expect("Failed to parse watchlist from ron"),
+        Err(_) => Watchlist::new(),
+    }
+}
+
+//so a read never sees the file half written
+fn read_locked(archive: &Archive) -> Watchlist {
+    let _lock = WATCHLIST.lock().expect("The watchlist lock was poisoned");
+    read(archive)
+}
+
+fn update<T>(archive: &Archive, update: impl FnOnce(&mut Watchlist) -> T) -> T {
+    let _lock = WATCHLIST.lock().expect("The watchlist lock was poisoned");
+    let mut watchlist = read(archive);
+    let result = update(&mut watchlist);
+    io::write::value_to_ron(&watchlist, &archive.layout.watchlist());
+    result
+}
+
+pub fn list(archive: &Archive) -> Vec<Watched> {
+    read_locked(archive).into_values().collect()
+}
+
+pub fn get(archive: &Archive, twitter_handle: &str) -> Option<Watched> {
+    read_locked(archive).remove(&twitter_handle.to_lowercase())
+}
+
+// Starts watching a user, synced for the first time as soon as the scheduler gets to them.
+// Watching a user again only changes their interval.
+pub fn watch(archive: &Archive, twitter_handle: &str, interval: &str) -> Result<Watched, String> {
+    let valid_handle = (1..=15).contains(&twitter_handle.len())
+        && twitter_handle
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid_handle {
+        return Err(format!("\"{twitter_handle}\" isn't a twitter handle"));
+    }
+    let duration = parse_interval(interval)?;
+    let now = OffsetDateTime::now_utc();
+    Ok(update(archive, |watchlist| {
+        let watched = watchlist
+            .entry(twitter_handle.to_lowercase())
+            .or_insert_with(|| Watched {
+                twitter_handle: twitter_handle.to_string(),
+                interval: interval.to_string(),
+                added_at: now,
+                next_sync: now,
+                last_sync: None,
+                new_tweets: None,
+                last_error: None,
+                job: None,
+            });
+        watched.interval = interval.to_string();
+        if let Some(last_sync) = watched.last_sync {
+            watched.next_sync = next_sync(last_sync, duration);
+        }
+        watched.clone()
+    }))
+}
+
+//false if the user wasn't watched, their archived tweets are kept either way
+pub fn unwatch(archive: &Archive, twitter_handle: &str) -> bool {
+    update(archive, |watchlist| {
+        watchlist.remove(&twitter_handle.to_lowercase()).is_some()
+    })
+}
+
+//records how a sync job went
+pub fn synced(archive_name: &str, twitter_handle: &str, result: &Result<usize, String>) {
+    let archive = match archive::get(archive_name) {
+        Some(archive) => archive,
+        None => return,
+    };
+    update(archive, |watchlist| {
+        if let Some(watched) = watchlist.get_mut(&twitter_handle.to_lowercase()) {
+            watched.job = None;
+            match result {
+                Ok(new_tweets) => {
+                    watched.last_sync = Some(OffsetDateTime::now_utc());
+                    watched.new_tweets = Some(*new_tweets);
+                    watched.last_error = None;
+                }
+                Err(error) => watched.last_error = Some(error.clone()),
+            }
+        }
+    });
+}
+
+//a job that no longer exists or has finished without telling us doesn't hold the user back
+fn has_pending_job(watched: &Watched) -> bool {
+    watched
+        .job
+        .and_then(|id| jobs::jobs().get(id))
+        .is_some_and(|job| !job.is_finished())
+}
+
+//queues a sync for the watched user who has been due the longest, if anyone is due
+fn queue_next_sync() {
+    let now = OffsetDateTime::now_utc();
+    let due = archive::names()
+        .into_iter()
+        .filter_map(|name| archive::get(&name))
+        .flat_map(|archive| {
+            read_locked(archive)
+                .into_values()
+                .filter(|watched| watched.next_sync <= now && !has_pending_job(watched))
+                .map(move |watched| (archive, watched))
+        })
+        .min_by_key(|(_, watched)| watched.next_sync);
+    let (archive, watched) = match due {
+        Some(due) => due,
+        None => return,
+    };
+    let job = jobs::jobs().enqueue(archive, Target::Sync(watched.twitter_handle.clone()));
+    update(archive, |watchlist| {
+        if let Some(watched) = watchlist.get_mut(&watched.twitter_handle.to_lowercase()) {
+            let interval = parse_interval(&watched.interval).unwrap_or(MIN_INTERVAL);
+            watched.job = Some(job.id);
+            watched.next_sync = next_sync(now, interval);
+        }
+    });
+}
+
+//starts the scheduler, which runs for as long as the server does
+pub fn start(config: &SyncConfig) {
+    let spacing = Duration::from_secs(config.sync_spacing.max(1));
+    println!(
+        "Syncing watched users, one every {} seconds at most",
+        spacing.as_secs()
+    );
+    tokio::spawn(async move {
+        loop {
+            queue_next_sync();
+            tokio::time::sleep(spacing).await;
+        }
+    });
+}
@@ -0,0 +1,357 @@
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use time::OffsetDateTime;
+
+use super::archive::{self, Archive};
+use super::io;
+use super::jobs::Job;
+use super::records::{TweetRecord, UserRecord};
+use super::tombstones::Tombstone;
+
+// Outgoing webhooks, POSTed by the server whenever something happens in an archive. They're
+// configured in Rocket.toml:
+//
+//     [[default.webhooks]]
+//     url = "http://localhost:9000/archive-events"
+//     secret = "a shared secret"
+//     events = ["tweets_stored", "job_finished"]   # every event when left out
+//
+// The body is a JSON payload with the event, the archive and the delivery id. With a secret it's
+// signed in the "X-Archiver-Signature: sha256=<hex>" header, the HMAC-SHA256 of the body keyed
+// with the secret. A delivery that gets no 2xx response is retried with exponential backoff, and
+// every delivery and its attempts is kept in the archive's "webhooks/deliveries.ron".
+
+//the newest deliveries kept in the log, older ones are dropped
+const LOG_SIZE: usize = 1000;
+
+//a receiver that takes longer than this to respond counts as a failed attempt
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+//the longest wait between two attempts, however many attempts and whatever the backoff
+const MAX_BACKOFF: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Webhook {
+    pub url: String,
+    #[serde(default)]
+    pub secret: Option<String>,
+    //event names, e.g. "tweets_stored", every event when empty
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+impl Webhook {
+    fn wants(&self, event: &str) -> bool {
+        self.events.is_empty() || self.events.iter().any(|wanted| wanted == event)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct WebhookConfig {
+    #[serde(default)]
+    pub webhooks: Vec<Webhook>,
+    //attempts per delivery, the first one included
+    #[serde(default = "default_webhook_attempts")]
+    pub webhook_attempts: u32,
+    //seconds before the first retry, doubled for each retry after it up to MAX_BACKOFF
+    #[serde(default = "default_webhook_backoff")]
+    pub webhook_backoff: u64,
+}
+
+fn default_webhook_attempts() -> u32 {
+    5
+}
+
+fn default_webhook_backoff() -> u64 {
+    10
+}
+
+static CONFIG: OnceLock<WebhookConfig> = OnceLock::new();
+
+//only the first call has any effect, nothing is sent before it
+pub fn configure(config: WebhookConfig) {
+    if !config.webhooks.is_empty() {
+        println!(
+            "Sending archive events to {} webhooks",
+            config.webhooks.len()
+        );
+    }
+    CONFIG.get_or_init(|| config);
+    //retries only live as long as the server, so deliveries it was still retrying are given up
+    for name in archive::names() {
+        let archive = archive::get(&name).expect("Every named archive is configured");
+        if !archive.layout.deliveries().exists() {
+            continue;
+        }
+        update(archive, |log| {
+            for delivery in log
+                .iter_mut()
+                .filter(|delivery| delivery.status == DeliveryStatus::Pending)
+            {
+                delivery.status = DeliveryStatus::Failed;
+            }
+        });
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    //tweets loaded from the Twitter API and written to the archive, from the user's timeline if
+    //there's a twitter handle
+    TweetsStored {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        twitter_handle: Option<String>,
+        tweets: Vec<TweetRecord>,
+    },
+    //an archived tweet was found deleted by a sync
+    TombstoneRecorded {
+        tombstone: Tombstone,
+    },
+    //a sync found the user's profile different from the archived one
+    ProfileChanged {
+        twitter_handle: String,
+        before: Box<UserRecord>,
+        after: Box<UserRecord>,
+    },
+    JobFinished {
+        job: Job,
+    },
+    //sent on request to every webhook, to test a receiver
+    Ping,
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::TweetsStored { .. } => "tweets_stored",
+            Event::TombstoneRecorded { .. } => "tombstone_recorded",
+            Event::ProfileChanged { .. } => "profile_changed",
+            Event::JobFinished { .. } => "job_finished",
+            Event::Ping => "ping",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    delivery: u64,
+    archive: &'a str,
+    #[serde(with = "time::serde::rfc3339")]
+    at: OffsetDateTime,
+    #[serde(flatten)]
+    event: &'a Event,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    //being sent, or waiting to be retried
+    Pending,
+    Delivered,
+    //every attempt failed
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attempt {
+    #[serde(with = "time::serde::rfc3339")]
+    pub at: OffsetDateTime,
+    //the receiver's response status, if it responded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Delivery {
+    pub id: u64,
+    pub url: String,
+    pub event: String,
+    pub status: DeliveryStatus,
+    #[serde(with = "time::serde::rfc3339")]
+    pub created_at: OffsetDateTime,
+    #[serde(default)]
+    pub attempts: Vec<Attempt>,
+}
+
+//deliveries.ron is read, changed and written back while holding this
+static DELIVERIES: Mutex<()> = Mutex::new(());
+
+fn read(archive: &Archive) -> Vec<Delivery> {
+    match io::read::string_from_ron(&archive.layout.deliveries()) {
+        Ok(deliveries) => ron::from_str(&deliveries).expect("Failed to parse deliveries from ron"),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn update<T>(archive: &Archive, update: impl FnOnce(&mut Vec<Delivery>) -> T) -> T {
+    let _lock = DELIVERIES.lock().expect("The deliveries lock was poisoned");
+    let mut deliveries = read(archive);
+    let result = update(&mut deliveries);
+    let dropped = deliveries.len().saturating_sub(LOG_SIZE);
+    deliveries.drain(..dropped);
+    io::write::value_to_ron(&deliveries, &archive.layout.deliveries());
+    result
+}
+
+//newest first
+pub fn deliveries(archive: &Archive) -> Vec<Delivery> {
+    let _lock = DELIVERIES.lock().expect("The deliveries lock was poisoned");
+    let mut deliveries = read(archive);
+    deliveries.reverse();
+    deliveries
+}
+
+pub fn delivery(archive: &Archive, id: u64) -> Option<Delivery> {
+    let _lock = DELIVERIES.lock().expect("The deliveries lock was poisoned");
+    read(archive).into_iter().find(|delivery| delivery.id == id)
+}
+
+// Sends the event to every webhook that wants it, in the background, and returns the deliveries
+// that were logged for it. Does nothing unless webhooks were configured, so the CLI never sends any.
+pub fn notify(archive: &Archive, event: Event) -> Vec<Delivery> {
+    let config = match CONFIG.get() {
+        Some(config) => config,
+        None => return Vec::new(),
+    };
+    let name = event.name();
+    let webhooks: Vec<&Webhook> = config
+        .webhooks
+        .iter()
+        .filter(|webhook| matches!(event, Event::Ping) || webhook.wants(name))
+        .collect();
+    if webhooks.is_empty() {
+        return Vec::new();
+    }
+    let now = OffsetDateTime::now_utc();
+    let deliveries = update(archive, |log| {
+        let mut id = log.last().map_or(1, |delivery| delivery.id + 1);
+        webhooks
+            .iter()
+            .map(|webhook| {
+                let delivery = Delivery {
+                    id,
+                    url: webhook.url.clone(),
+                    event: name.to_string(),
+                    status: DeliveryStatus::Pending,
+                    created_at: now,
+                    attempts: Vec::new(),
+                };
+                id += 1;
+                log.push(delivery.clone());
+                delivery
+            })
+            .collect::<Vec<_>>()
+    });
+    for (webhook, delivery) in webhooks.into_iter().zip(&deliveries) {
+        let body = serde_json::to_string(&Payload {
+            delivery: delivery.id,
+            archive: &archive.name,
+            at: now,
+            event: &event,
+        })
+        .expect("Failed to serialize the webhook payload");
+        tokio::spawn(deliver(
+            archive.name.clone(),
+            webhook.clone(),
+            delivery.id,
+            name,
+            body,
+            config,
+        ));
+    }
+    deliveries
+}
+
+async fn deliver(
+    archive_name: String,
+    webhook: Webhook,
+    id: u64,
+    event: &'static str,
+    body: String,
+    config: &'static WebhookConfig,
+) {
+    let client = reqwest::Client::new();
+    let attempts = config.webhook_attempts.max(1);
+    for attempt in 1..=attempts {
+        let mut request = client
+            .post(&webhook.url)
+            .timeout(TIMEOUT)
+            .header("Content-Type", "application/json")
+            .header("X-Archiver-Event", event)
+            .header("X-Archiver-Delivery", id.to_string());
+        if let Some(secret) = &webhook.secret {
+            request = request.header("X-Archiver-Signature", signature(secret, &body));
+        }
+        let (status_code, error) = match request.body(body.clone()).send().await {
+            Ok(response) if response.status().is_success() => {
+                (Some(response.status().as_u16()), None)
+            }
+            Ok(response) => (
+                Some(response.status().as_u16()),
+                Some(format!("Responded with {}", response.status())),
+            ),
+            Err(error) => (None, Some(error.to_string())),
+        };
+        let delivered = error.is_none();
+        let status = match (delivered, attempt == attempts) {
+            (true, _) => DeliveryStatus::Delivered,
+            (false, true) => DeliveryStatus::Failed,
+            (false, false) => DeliveryStatus::Pending,
+        };
+        match &error {
+            Some(error) => println!(
+                "Webhook delivery {id} to {} failed, attempt {attempt} of {attempts}: {error}",
+                webhook.url
+            ),
+            None => println!("Webhook delivery {id} to {} succeeded", webhook.url),
+        }
+        //an archive that's gone has no log to write to, and no one to deliver for
+        let archive = match archive::get(&archive_name) {
+            Some(archive) => archive,
+            None => return,
+        };
+        update(archive, |log| {
+            if let Some(delivery) = log.iter_mut().find(|delivery| delivery.id == id) {
+                delivery.status = status;
+                delivery.attempts.push(Attempt {
+                    at: OffsetDateTime::now_utc(),
+                    status: status_code,
+                    error,
+                });
+            }
+        });
+        if status != DeliveryStatus::Pending {
+            return;
+        }
+        tokio::time::sleep(backoff(config.webhook_backoff, attempt)).await;
+    }
+}
+
+//the wait after a failed attempt, `backoff` seconds doubled for each attempt before it
+pub fn backoff(backoff: u64, attempt: u32) -> Duration {
+    let factor = 2u64
+        .checked_pow(attempt.saturating_sub(1))
+        .unwrap_or(u64::MAX);
+    Duration::from_secs(backoff.saturating_mul(factor)).min(MAX_BACKOFF)
+}
+
+//"sha256=<hex>", what a receiver compares with the HMAC-SHA256 of the body it got
+pub fn signature(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(body.as_bytes());
+    let hex: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    format!("sha256={hex}")
+}
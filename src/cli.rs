@@ -0,0 +1,424 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use rocket::figment::Figment;
+
+use crate::app::api::{self, ApiConfig};
+use crate::app::archive::{Archive, ArchiveConfig, DEFAULT_ARCHIVE};
+use crate::app::check::{self, RepairOptions};
+use crate::app::convert;
+use crate::app::export::parquet::Partition;
+use crate::app::export::{self, table, table::TableFormat};
+use crate::app::graph::{self, GraphFormat, Interaction};
+use crate::app::io;
+use crate::app::io::layout::{Layout, StorageFormat};
+use crate::app::migrations;
+use crate::app::site::Site;
+use crate::mock::{self, Fixtures, Mock};
+
+const USAGE: &str = r#"Usage: better-twitter-archiver [command] [options]
+
+Commands:
+
+serve                   run the web server (the default)
+
+check                   scan the archive for dangling references, duplicates and broken files
+    --repair            prune what can't be fixed, and fill in what can from the archive itself
+    --refetch           with --repair, load missing users from the Twitter API
+
+reindex                 rebuild the search index from scratch and write it. otherwise the index on
+                        disk lags behind the archive, written only once 1000 tweets (or a quarter of
+                        its size) were added, and the rest are indexed again when the archive opens
+
+convert <format>        rewrite the archive as "ron" (pretty printed) or "zstd" (compressed ron)
+
+graph <format> <file> [kinds]
+                        export the reply, quote and mention graph as "ron", "graphml", "gexf" or "dot",
+                        optionally only some kinds of interaction, e.g. "graph gexf out.gexf reply quote"
+
+watch <job id>          follow a background job on the running server (at "public_url") with a
+                        progress bar
+
+export markdown <handle> <directory>
+                        write the user's conversations and their participants as notes for an
+                        Obsidian vault, loading the conversations from the Twitter API if needed
+
+export mastodon <handle> <directory>
+                        write the user's archived tweets and their media in the layout of a Mastodon
+                        account archive (actor.json, outbox.json, media_attachments), for importers
+
+export <csv|jsonl> <handle> <directory>
+                        write the user's archived tweets as tweets_<handle>.csv (or .jsonl),
+                        one row per tweet, and every archived user as users.csv (or .jsonl)
+
+export parquet <directory>
+                        write every archived tweet, user, reply/quote/retweet and media key as
+                        typed parquet files, with the tweets partitioned by author, replacing
+                        any earlier export in the directory
+    --by-month          partition the tweets by the month they were written instead
+
+mock-twitter <fixtures> [port]
+                        serve the users and tweets of a JSON fixtures file as a mock Twitter API at
+                        "http://localhost:<port>/2/" (port 9000 by default), for pointing
+                        "api_base_url" at while working without network access, and the fixtures'
+                        "actors" at "/actors/<name>", keeping whatever is POSTed to it like a
+                        follower's inbox or a webhook receiver would
+
+Options:
+
+--data-dir <path>       the root data directory, overrides "data_dir" in Rocket.toml
+--archive <name>        which configured archive to work on, "default" if not given
+"#;
+
+#[derive(Debug, Default)]
+pub struct Args {
+    pub command: Option<String>,
+    pub data_dir: Option<String>,
+    pub archive: Option<String>,
+    pub flags: Vec<String>,
+    pub positional: Vec<String>,
+}
+
+impl Args {
+    pub fn from_env() -> Args {
+        Args::parse(std::env::args().skip(1))
+    }
+
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Args {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--data-dir" => parsed.data_dir = args.next(),
+                "--archive" => parsed.archive = args.next(),
+                _ if arg.starts_with("--data-dir=") => {
+                    parsed.data_dir = Some(arg["--data-dir=".len()..].to_string())
+                }
+                _ if arg.starts_with("--archive=") => {
+                    parsed.archive = Some(arg["--archive=".len()..].to_string())
+                }
+                _ if arg.starts_with("--") => parsed.flags.push(arg),
+                _ if parsed.command.is_none() => parsed.command = Some(arg),
+                _ => parsed.positional.push(arg),
+            }
+        }
+        parsed
+    }
+
+    pub fn flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|arg| arg == flag)
+    }
+
+    pub fn archive_name(&self) -> &str {
+        self.archive.as_deref().unwrap_or(DEFAULT_ARCHIVE)
+    }
+}
+
+//Rocket's configuration with the command line options merged on top
+pub fn figment(args: &Args) -> Figment {
+    let mut figment = rocket::Config::figment();
+    if let Some(data_dir) = &args.data_dir {
+        figment = figment.merge(("data_dir", data_dir));
+    }
+    figment
+}
+
+pub fn archive_config(figment: &Figment) -> ArchiveConfig {
+    figment
+        .extract()
+        .expect("Failed to read the archive configuration")
+}
+
+fn root(args: &Args) -> PathBuf {
+    let config = archive_config(&figment(args));
+    let name = args.archive_name();
+    config.root(name).unwrap_or_else(|| {
+        eprintln!("No archive called \"{name}\" is configured");
+        std::process::exit(2)
+    })
+}
+
+//the layout of the archive selected with --archive, migrated to the current version
+fn layout(args: &Args) -> Layout {
+    migrations::migrate(&Layout::new(root(args)))
+}
+
+pub async fn run(args: &Args) {
+    api::configure(
+        &figment(args)
+            .extract::<ApiConfig>()
+            .expect("Failed to read the Twitter API configuration"),
+    );
+    match args.command.as_deref() {
+        Some("check") => run_check(args).await,
+        Some("convert") => run_convert(args),
+        Some("reindex") => run_reindex(args),
+        Some("graph") => run_graph(args),
+        Some("export") => run_export(args).await,
+        Some("watch") => run_watch(args).await,
+        Some("mock-twitter") => run_mock_twitter(args).await,
+        _ => {
+            eprint!("{USAGE}");
+            std::process::exit(2)
+        }
+    }
+}
+
+async fn run_check(args: &Args) {
+    let layout = Layout::new(root(args));
+    let problems = if args.flag("--repair") {
+        let options = RepairOptions {
+            refetch: args.flag("--refetch"),
+        };
+        check::repair_archive(&layout, options).await
+    } else {
+        check::check_archive(&layout)
+    };
+    for problem in &problems {
+        println!("{problem}");
+    }
+    match problems.len() {
+        0 => println!("No problems found"),
+        count => {
+            println!("{count} problems found");
+            std::process::exit(1)
+        }
+    }
+}
+
+fn run_convert(args: &Args) {
+    let format = args
+        .positional
+        .first()
+        .and_then(|name| StorageFormat::from_name(name))
+        .unwrap_or_else(|| {
+            eprint!("{USAGE}");
+            std::process::exit(2)
+        });
+    let sizes = convert::convert(&layout(args), format);
+    let (files, before, after) = (sizes.files, sizes.before, sizes.after);
+    let percent = if before == 0 {
+        100.0
+    } else {
+        after as f64 / before as f64 * 100.0
+    };
+    println!("Converted {files} files: {before} bytes -> {after} bytes ({percent:.1}%)");
+}
+
+fn run_reindex(args: &Args) {
+    let archive = Archive::open(args.archive_name(), root(args));
+    archive.reindex();
+}
+
+fn run_graph(args: &Args) {
+    let usage = || -> ! {
+        eprint!("{USAGE}");
+        std::process::exit(2)
+    };
+    let format = args
+        .positional
+        .first()
+        .and_then(|name| GraphFormat::from_name(name))
+        .unwrap_or_else(|| usage());
+    let file = args.positional.get(1).unwrap_or_else(|| usage());
+    let kinds: Vec<Interaction> = match args.positional.len() {
+        2 => Interaction::ALL.to_vec(),
+        _ => args.positional[2..]
+            .iter()
+            .map(|name| Interaction::from_name(name).unwrap_or_else(|| usage()))
+            .collect(),
+    };
+    let archive = Archive::open(args.archive_name(), root(args));
+    let graph = graph::interaction_graph(&archive, &kinds);
+    let (nodes, edges) = (graph.nodes.len(), graph.edges.len());
+    std::fs::write(file, graph::export(&graph, format))
+        .unwrap_or_else(|error| panic!("Failed to write \"{file}\": {error}"));
+    println!("Wrote {nodes} users and {edges} edges to \"{file}\"");
+}
+
+async fn run_export(args: &Args) {
+    if let [format, directory] = args.positional.as_slice() {
+        if format == "parquet" {
+            return run_parquet_export(args, Path::new(directory));
+        }
+    }
+    let (format, twitter_handle, directory) = match args.positional.as_slice() {
+        [format, twitter_handle, directory] => (format, twitter_handle, directory),
+        _ => {
+            eprint!("{USAGE}");
+            std::process::exit(2)
+        }
+    };
+    let archive = Archive::open(args.archive_name(), root(args));
+    match format.as_str() {
+        "markdown" => {
+            let exported =
+                export::markdown::export(&archive, twitter_handle, Path::new(directory)).await;
+            let (conversations, users) = (exported.conversations, exported.users);
+            println!("Wrote {conversations} conversations and {users} users to \"{directory}\"");
+        }
+        "mastodon" => {
+            let site: Site = figment(args)
+                .extract()
+                .expect("Failed to read the site configuration");
+            let exported =
+                export::mastodon::export(&archive, &site, twitter_handle, Path::new(directory))
+                    .await
+                    .unwrap_or_else(|| {
+                        eprintln!("@{twitter_handle} is not in the archive");
+                        std::process::exit(1)
+                    });
+            let (statuses, replies) = (exported.statuses, exported.replies);
+            let (media, missing_media) = (exported.media, exported.missing_media);
+            println!(
+                "Wrote {statuses} statuses ({replies} in threads) and {media} media to \"{directory}\", {missing_media} media weren't downloaded"
+            );
+        }
+        name => {
+            let format = TableFormat::from_name(name).unwrap_or_else(|| {
+                eprint!("{USAGE}");
+                std::process::exit(2)
+            });
+            let tweets = table::tweet_rows(&archive, twitter_handle).unwrap_or_else(|| {
+                eprintln!("@{twitter_handle} is not in the archive");
+                std::process::exit(1)
+            });
+            let users = table::user_rows(&archive);
+            let (tweet_count, user_count) = (tweets.len(), users.len());
+            let extension = format.extension();
+            let directory = Path::new(directory);
+            io::write::string_to_file(
+                &table::lines(tweets, format).collect::<String>(),
+                &directory.join(format!("tweets_{twitter_handle}.{extension}")),
+            );
+            io::write::string_to_file(
+                &table::lines(users, format).collect::<String>(),
+                &directory.join(format!("users.{extension}")),
+            );
+            let directory = directory.display();
+            println!("Wrote {tweet_count} tweets and {user_count} users to \"{directory}\"");
+        }
+    }
+}
+
+fn run_parquet_export(args: &Args, directory: &Path) {
+    let partition = match args.flag("--by-month") {
+        true => Partition::Month,
+        false => Partition::Author,
+    };
+    let archive = Archive::open(args.archive_name(), root(args));
+    let exported = export::parquet::export(&archive, directory, partition);
+    let (tweets, partitions, users) = (exported.tweets, exported.partitions, exported.users);
+    let (references, media) = (exported.references, exported.media);
+    let directory = directory.display();
+    println!(
+        "Wrote {tweets} tweets in {partitions} partitions, {users} users, {references} references and {media} media to \"{directory}\""
+    );
+}
+
+fn progress_bar(done: u64, total: u64) -> String {
+    const WIDTH: u64 = 30;
+    let filled = match total {
+        0 => WIDTH,
+        total => (done * WIDTH / total).min(WIDTH),
+    };
+    format!(
+        "[{}{}] {done}/{total}",
+        "#".repeat(filled as usize),
+        "-".repeat((WIDTH - filled) as usize)
+    )
+}
+
+//one line describing a progress event, None for events that don't change the picture
+fn progress_line(update: &serde_json::Value) -> Option<String> {
+    let number = |field: &str| update[field].as_u64().unwrap_or_default();
+    match update["type"].as_str()? {
+        "job_started" => Some("Running".to_string()),
+        "page_fetched" => Some(format!(
+            "@{}'s timeline {}",
+            update["twitter_handle"].as_str().unwrap_or_default(),
+            progress_bar(number("page"), number("pages"))
+        )),
+        "conversation_resolved" => Some(format!(
+            "Conversations {}",
+            progress_bar(number("done"), number("total"))
+        )),
+        "rate_limited" => Some(format!("Rate limited, waiting {}s", number("seconds"))),
+        _ => None,
+    }
+}
+
+async fn run_watch(args: &Args) {
+    let id: u64 = match args.positional.as_slice() {
+        [id] => id.parse().ok(),
+        _ => None,
+    }
+    .unwrap_or_else(|| {
+        eprint!("{USAGE}");
+        std::process::exit(2)
+    });
+    let site: Site = figment(args)
+        .extract()
+        .expect("Failed to read the site configuration");
+    let url = site.url(&format!("/progress?job={id}"));
+    let mut response = match reqwest::get(&url)
+        .await
+        .and_then(|response| response.error_for_status())
+    {
+        Ok(response) => response,
+        Err(error) => {
+            eprintln!("Failed to follow job {id} at \"{url}\": {error}");
+            std::process::exit(1)
+        }
+    };
+    let mut buffer = String::new();
+    let mut failed = false;
+    while let Some(chunk) = response.chunk().await.expect("The progress stream broke") {
+        buffer.push_str(&String::from_utf8_lossy(&chunk));
+        while let Some(end) = buffer.find("\n\n") {
+            let message: String = buffer.drain(..end + 2).collect();
+            for data in message
+                .lines()
+                .filter_map(|line| line.strip_prefix("data:"))
+            {
+                let update: serde_json::Value =
+                    serde_json::from_str(data.trim()).expect("Failed to read a progress event");
+                if update["type"] == "job_finished" {
+                    failed = update["failed"].as_bool().unwrap_or_default();
+                } else if let Some(line) = progress_line(&update) {
+                    //redrawn in place
+                    eprint!("\r\x1b[2K{line}");
+                }
+            }
+        }
+    }
+    eprintln!();
+    match failed {
+        true => {
+            eprintln!("Job {id} failed, see {}", site.url(&format!("/jobs/{id}")));
+            std::process::exit(1)
+        }
+        false => println!("Job {id} is done"),
+    }
+}
+
+async fn run_mock_twitter(args: &Args) {
+    let (fixtures, port) = match args.positional.as_slice() {
+        [fixtures] => (fixtures, Some(9000)),
+        [fixtures, port] => (fixtures, port.parse().ok()),
+        _ => {
+            eprint!("{USAGE}");
+            std::process::exit(2)
+        }
+    };
+    let port: u16 = port.unwrap_or_else(|| {
+        eprint!("{USAGE}");
+        std::process::exit(2)
+    });
+    let mock = Arc::new(Mock::new(Fixtures::from_file(fixtures)));
+    println!("Serving \"{fixtures}\" as the Twitter API at http://localhost:{port}/2/");
+    mock::rocket(mock, port)
+        .launch()
+        .await
+        .expect("Failed to launch the mock Twitter API");
+}
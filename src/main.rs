@@ -1,8 +1,29 @@
 #[macro_use]
 extern crate rocket;
+use app::activitypub::ActivityPubConfig;
+use app::api::ApiConfig;
+use app::archive::{self, Archive};
+use app::export::table::TableFormat;
+use app::feed::FeedFormat;
+use app::jobs::JobConfig;
+use app::site::Site;
+use app::watchlist::SyncConfig;
+use app::webhooks::WebhookConfig;
 use dotenvy::dotenv;
+use rocket::fairing::AdHoc;
+use rocket::figment::Figment;
+use rocket::futures::stream::{self, Stream};
+use rocket::http::{ContentType, Status};
+use rocket::response::content::{Html, Json};
+use rocket::response::status::{BadRequest, Custom, NotFound};
+use rocket::response::stream::{EventStream, TextStream};
+use rocket::{Build, Rocket, State};
 
 pub mod app;
+mod cli;
+pub mod mock;
+#[cfg(test)]
+mod tests;
 
 #[get("/")]
 fn index() -> &'static str {
@@ -24,15 +45,105 @@ Available routes:
 
 #[get("/user/<twitter_handle>/conversations")]
 
+#[get("/user/<twitter_handle>/stats")]
+
+#[get("/user/<twitter_handle>/stats/dashboard")]
+
+#[get("/user/<twitter_handle>/feed.atom")]
+
+#[get("/user/<twitter_handle>/feed.rss")]
+
+#[get("/search/feed.atom?<query>")]
+
 #[get("/search?<query>")]
 
+    operators: from:<handle> to:<handle> since:<yyyy-mm-dd> until:<yyyy-mm-dd> is:reply is:quote
+    is:retweet has:media has:links conversation:<id>, "quoted phrases", AND, OR, NOT or -, (groups)
+
+#[get("/search?<query>&mode=fuzzy")]
+
+#[get("/search?<query>&mode=regex")]
+
+#[get("/search?<query>&group=conversation")]
+
+#[get("/userid/<id>")]
+
+#[get("/export/<file>")]
+
+    <twitter_handle>.csv or <twitter_handle>.jsonl for a user's tweets
+
+#[get("/export/users/<file>")]
+
+    all.csv or all.jsonl for every archived user
+
+#[get("/graph?<format>&<kinds>")]
+
+    format: ron (the default), graphml, gexf or dot
+    kinds: a comma separated list of reply, quote and mention, all of them by default
+
+#[post("/jobs")]
+
+    body: {"user": "<twitter_handle>"}, {"tweet": <id>}, {"conversation": <id>} or {"sync": "<twitter_handle>"},
+    archived in the background
+
+#[get("/jobs/<id>")]
+
+#[get("/jobs/<id>/watch")]
+
+#[get("/progress?<job>")]
+
+    archival progress as server-sent events, only one job's with "?job=<id>"
+
+#[get("/watchlist")]
+
+#[get("/watchlist/<twitter_handle>")]
+
+#[post("/watchlist")]
+
+    body: {"twitter_handle": "<twitter_handle>", "interval": "6h"}, new tweets are archived every interval
+    intervals: <n>m, <n>h, <n>d, <n>w, @hourly, @daily (the default) or @weekly, from 15 minutes to a year
+
+#[delete("/watchlist/<twitter_handle>")]
+
+#[get("/tombstones")]
+
+    archived tweets that a sync found deleted from twitter
+
+#[get("/webhooks/deliveries")]
+
+#[get("/webhooks/deliveries/<id>")]
+
+#[post("/webhooks/ping")]
+
+    webhooks are configured in Rocket.toml, this sends every one of them a "ping" event
+
+#[get("/twitter/usage")]
+
+    requests sent with each Twitter API bearer token and what twitter says is left of its rate limits
+
+#[get("/.well-known/webfinger?<resource>")]
+
+#[get("/users/<twitter_handle>")]
+
+#[get("/users/<twitter_handle>/outbox?<page>")]
+
+#[get("/users/<twitter_handle>/followers")]
+
+#[get("/users/<twitter_handle>/statuses/<id>")]
+
+#[post("/users/<twitter_handle>/inbox")]
+
+    archived users as read-only ActivityPub actors, to follow from Mastodon as @<twitter_handle>@<host>
+
+Every route accepts "?archive=<name>" to select one of the archives configured in Rocket.toml.
+
 "#
 }
 
 #[get("/tweet/<id>")]
-async fn tweet_by_id(id: u64) -> String {
+async fn tweet_by_id(archive: &Archive, id: u64) -> String {
     ron::ser::to_string_pretty(
-        &app::load_tweet_from_id(id).await,
+        &app::load_tweet_from_id(archive, id).await,
         ron::ser::PrettyConfig::new(),
     )
     .expect("Failed to serve tweet from id")
@@ -40,46 +151,55 @@ async fn tweet_by_id(id: u64) -> String {
 
 //for your purposes a conversation id might be the *last* tweet id in the conversation
 #[get("/conversation/<id>")]
-async fn conversation_by_id(id: u64) -> String {
+async fn conversation_by_id(archive: &Archive, id: u64) -> String {
     ron::ser::to_string_pretty(
-        &app::load_conversation_from_tweet_id(id).await,
+        &app::load_conversation_from_tweet_id(archive, id).await,
         ron::ser::PrettyConfig::new(),
     )
     .expect("Failed to serve twitter conversation")
 }
 
-//here a conversation id is the id of the *last* tweet in a conversation
+//here a conversation id is the id of the *last* tweet in a conversation, and the tweet one of its tweets
 #[get("/conversation/<id>/<tweet_id>")]
-async fn tweet_in_conversation_by_id(id: u64, tweet_id: u64) -> String {
-    ron::ser::to_string_pretty(
-        &(tweet_id, &app::load_conversation_from_tweet_id(id).await),
-        ron::ser::PrettyConfig::new(),
-    )
-    .expect("Failed to serve twitter conversation")
+async fn tweet_in_conversation_by_id(
+    archive: &Archive,
+    id: u64,
+    tweet_id: u64,
+) -> Result<String, NotFound<String>> {
+    let conversation = app::load_conversation_from_tweet_id(archive, id).await;
+    match conversation.iter().find(|tweet| tweet.id == tweet_id) {
+        Some(tweet) => Ok(
+            ron::ser::to_string_pretty(tweet, ron::ser::PrettyConfig::new())
+                .expect("Failed to serve tweet in conversation"),
+        ),
+        None => Err(NotFound(format!(
+            "Tweet {tweet_id} is not in conversation {id}"
+        ))),
+    }
 }
 // will just get info on a user
 #[get("/user/<twitter_handle>")]
-async fn user_by_twitter_handle(twitter_handle: &str) -> String {
+async fn user_by_twitter_handle(archive: &Archive, twitter_handle: &str) -> String {
     ron::ser::to_string_pretty(
-        &app::load_user_from_twitter_handle(twitter_handle).await,
+        &app::load_user_from_twitter_handle(archive, twitter_handle).await,
         ron::ser::PrettyConfig::new(),
     )
     .expect("Failed to serve user from id")
 }
 
 #[get("/userid/<id>")]
-async fn user_by_id(id: u64) -> String {
+async fn user_by_id(archive: &Archive, id: u64) -> String {
     ron::ser::to_string_pretty(
-        &app::load_user_from_id(id).await,
+        &app::load_user_from_id(archive, id).await,
         ron::ser::PrettyConfig::new(),
     )
     .expect("Failed to serve user from id")
 }
 //exact same as get user_by_twitter_handle
 #[get("/user/<twitter_handle>/info")]
-async fn user_info_by_twitter_handle(twitter_handle: &str) -> String {
+async fn user_info_by_twitter_handle(archive: &Archive, twitter_handle: &str) -> String {
     ron::ser::to_string_pretty(
-        &app::load_user_from_twitter_handle(twitter_handle).await,
+        &app::load_user_from_twitter_handle(archive, twitter_handle).await,
         ron::ser::PrettyConfig::new(),
     )
     .expect("Failed to serve user from id")
@@ -87,9 +207,9 @@ async fn user_info_by_twitter_handle(twitter_handle: &str) -> String {
 
 //will bet a user's tweets, for now the recent ten
 #[get("/user/<twitter_handle>/tweets")]
-async fn tweets_by_user(twitter_handle: &str) -> String {
+async fn tweets_by_user(archive: &Archive, twitter_handle: &str) -> String {
     ron::ser::to_string_pretty(
-        &app::load_tweets_from_twitter_handle(twitter_handle).await,
+        &app::load_tweets_from_twitter_handle(archive, twitter_handle).await,
         ron::ser::PrettyConfig::new(),
     )
     .expect("Failed to serve user's tweets from this twitter handle")
@@ -97,28 +217,603 @@ async fn tweets_by_user(twitter_handle: &str) -> String {
 
 //will get a user's conversations
 #[get("/user/<twitter_handle>/conversations")]
-async fn conversations_by_twitter_handle(twitter_handle: &str) -> String {
+async fn conversations_by_twitter_handle(archive: &Archive, twitter_handle: &str) -> String {
     ron::ser::to_string_pretty(
-        &app::load_conversations_from_twitter_handle(twitter_handle).await,
+        &app::load_conversations_from_twitter_handle(archive, twitter_handle).await,
         ron::ser::PrettyConfig::new(),
     )
     .expect("Failed to serve user's tweets from this twitter handle")
 }
-//in the url the query will look like "/search?query=whatever"
-#[get("/search?<query>")]
-fn search(query: &str) -> String {
-    format!(
-        "This route will run a search of \"{}\" through all the tweets locally stored",
-        query
+//statistics computed from the user's archived tweets, nothing is fetched from twitter
+#[get("/user/<twitter_handle>/stats")]
+fn stats_by_twitter_handle(
+    archive: &Archive,
+    twitter_handle: &str,
+) -> Result<Json<String>, NotFound<String>> {
+    match app::stats::user_stats(archive, twitter_handle) {
+        Some(stats) => Ok(Json(
+            serde_json::to_string_pretty(&stats).expect("Failed to serve user's stats"),
+        )),
+        None => Err(NotFound(format!("@{twitter_handle} is not in the archive"))),
+    }
+}
+
+//the same statistics rendered as a page
+#[get("/user/<twitter_handle>/stats/dashboard")]
+fn stats_dashboard_by_twitter_handle(
+    archive: &Archive,
+    site: &State<Site>,
+    twitter_handle: &str,
+) -> Result<Html<String>, NotFound<String>> {
+    match app::stats::user_stats(archive, twitter_handle) {
+        Some(stats) => Ok(Html(app::stats::dashboard(&stats, archive, site))),
+        None => Err(NotFound(format!("@{twitter_handle} is not in the archive"))),
+    }
+}
+
+//who replies to, quotes and mentions whom across the whole archive
+//e.g. "/graph?format=gexf&kinds=reply,quote" to open in Gephi
+#[get("/graph?<format>&<kinds>")]
+fn interaction_graph(
+    archive: &Archive,
+    format: Option<&str>,
+    kinds: Option<&str>,
+) -> Result<(ContentType, String), BadRequest<String>> {
+    use app::graph::{GraphFormat, Interaction};
+    let format = match format {
+        Some(name) => GraphFormat::from_name(name).ok_or_else(|| {
+            BadRequest(Some(format!(
+                "Unknown graph format \"{name}\", try \"ron\", \"graphml\", \"gexf\" or \"dot\""
+            )))
+        })?,
+        None => GraphFormat::Ron,
+    };
+    let kinds = match kinds {
+        Some(kinds) => kinds
+            .split(',')
+            .map(|name| {
+                Interaction::from_name(name).ok_or_else(|| {
+                    BadRequest(Some(format!(
+                        "Unknown interaction \"{name}\", try \"reply\", \"quote\" or \"mention\""
+                    )))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+        None => Interaction::ALL.to_vec(),
+    };
+    let content_type = match format {
+        GraphFormat::Ron | GraphFormat::Dot => ContentType::Plain,
+        GraphFormat::GraphMl | GraphFormat::Gexf => ContentType::XML,
+    };
+    let graph = app::graph::interaction_graph(archive, &kinds);
+    Ok((content_type, app::graph::export(&graph, format)))
+}
+
+//the name and format of an export's file, e.g. "yudapearl.csv"
+fn export_file(file: &str) -> Result<(&str, TableFormat, ContentType), NotFound<String>> {
+    let (name, format) = file
+        .rsplit_once('.')
+        .and_then(|(name, extension)| Some((name, TableFormat::from_name(extension)?)))
+        .ok_or_else(|| NotFound(format!("No export called \"{file}\"")))?;
+    let content_type = match format {
+        TableFormat::Csv => ContentType::CSV,
+        TableFormat::JsonLines => ContentType::new("application", "x-ndjson"),
+    };
+    Ok((name, format, content_type))
+}
+
+//flat exports of the archive, "/export/<twitter_handle>.csv" or ".jsonl" for a user's tweets
+#[get("/export/<file>")]
+fn export_table(
+    archive: &Archive,
+    file: &str,
+) -> Result<(ContentType, TextStream<impl Stream<Item = String>>), NotFound<String>> {
+    let (twitter_handle, format, content_type) = export_file(file)?;
+    let rows = app::export::table::tweet_rows(archive, twitter_handle)
+        .ok_or_else(|| NotFound(format!("@{twitter_handle} is not in the archive")))?;
+    let lines = app::export::table::lines(rows, format);
+    Ok((content_type, TextStream(stream::iter(lines))))
+}
+
+//and "/export/users/all.csv" or ".jsonl" for every archived user, in a directory of its own so
+//it can't be mistaken for the tweets of an account called "users"
+#[get("/export/users/<file>")]
+fn export_users_table(
+    archive: &Archive,
+    file: &str,
+) -> Result<(ContentType, TextStream<impl Stream<Item = String>>), NotFound<String>> {
+    match export_file(file)? {
+        ("all", format, content_type) => {
+            let lines = app::export::table::lines(app::export::table::user_rows(archive), format);
+            Ok((content_type, TextStream(stream::iter(lines))))
+        }
+        _ => Err(NotFound(format!("No export called \"users/{file}\""))),
+    }
+}
+
+//feeds of the newest archived tweets, to follow an account from a feed reader
+#[get("/user/<twitter_handle>/feed.atom")]
+fn atom_feed_by_twitter_handle(
+    archive: &Archive,
+    site: &State<Site>,
+    twitter_handle: &str,
+) -> Result<(ContentType, String), NotFound<String>> {
+    match app::feed::user_feed(archive, twitter_handle, FeedFormat::Atom, site) {
+        Some(feed) => Ok((ContentType::new("application", "atom+xml"), feed)),
+        None => Err(NotFound(format!("@{twitter_handle} is not in the archive"))),
+    }
+}
+
+#[get("/user/<twitter_handle>/feed.rss")]
+fn rss_feed_by_twitter_handle(
+    archive: &Archive,
+    site: &State<Site>,
+    twitter_handle: &str,
+) -> Result<(ContentType, String), NotFound<String>> {
+    match app::feed::user_feed(archive, twitter_handle, FeedFormat::Rss, site) {
+        Some(feed) => Ok((ContentType::new("application", "rss+xml"), feed)),
+        None => Err(NotFound(format!("@{twitter_handle} is not in the archive"))),
+    }
+}
+
+#[get("/search/feed.atom?<query>")]
+async fn search_feed(
+    archive: &'static Archive,
+    site: &State<Site>,
+    query: &str,
+) -> Result<(ContentType, String), BadRequest<String>> {
+    //searched off the async workers, like "/search"
+    let (site, query) = (site.inner().clone(), query.to_string());
+    let feed = tokio::task::spawn_blocking(move || {
+        app::feed::search_feed(archive, &query, FeedFormat::Atom, &site)
+    })
+    .await
+    .expect("Search task failed");
+    match feed {
+        Ok(feed) => Ok((ContentType::new("application", "atom+xml"), feed)),
+        Err(error) => Err(BadRequest(Some(error.to_string()))),
+    }
+}
+
+//archiving in the background instead of holding the connection open while twitter is called
+//e.g. POST {"user": "yudapearl"}, {"tweet": 1234} or {"conversation": 1234}
+#[post("/jobs", data = "<target>")]
+fn enqueue_job(
+    archive: &Archive,
+    target: &str,
+) -> Result<Custom<Json<String>>, BadRequest<String>> {
+    let target: app::jobs::Target = serde_json::from_str(target).map_err(|error| {
+        BadRequest(Some(format!(
+            "Invalid job, try {{\"user\": \"<twitter_handle>\"}}, {{\"tweet\": <id>}}, {{\"conversation\": <id>}} or {{\"sync\": \"<twitter_handle>\"}}: {error}"
+        )))
+    })?;
+    let job = app::jobs::jobs().enqueue(archive, target);
+    Ok(Custom(
+        Status::Accepted,
+        Json(serde_json::to_string_pretty(&job).expect("Failed to serve job")),
+    ))
+}
+
+//a job's status and progress
+#[get("/jobs/<id>")]
+fn job_by_id(id: u64) -> Result<Json<String>, NotFound<String>> {
+    match app::jobs::jobs().get(id) {
+        Some(job) => Ok(Json(
+            serde_json::to_string_pretty(&job).expect("Failed to serve job"),
+        )),
+        None => Err(NotFound(format!("No job {id}"))),
+    }
+}
+
+fn progress_event(update: &app::progress::Update) -> rocket::response::stream::Event {
+    rocket::response::stream::Event::data(
+        serde_json::to_string(update).expect("Failed to serve progress"),
+    )
+    .event(update.event.name())
+}
+
+//live archival progress as server-sent events, each named after its "type"
+//with "?job=<id>" only that job's events, and the stream ends when the job does
+#[get("/progress?<job>")]
+fn progress_events(job: Option<u64>) -> Result<EventStream![], NotFound<String>> {
+    use app::progress::{Event, Update};
+    use rocket::tokio::sync::broadcast::error::RecvError;
+    //subscribed before looking at the job, so its end can't slip in between
+    let mut updates = app::progress::subscribe();
+    let finished = match job.map(|id| (id, app::jobs::jobs().get(id))) {
+        Some((_, Some(job))) => job
+            .is_finished()
+            .then(|| job.status == app::jobs::Status::Failed),
+        Some((id, None)) => return Err(NotFound(format!("No job {id}"))),
+        None => None,
+    };
+    Ok(EventStream! {
+        match finished {
+            Some(failed) => {
+                yield progress_event(&Update {
+                    job,
+                    at: time::OffsetDateTime::now_utc(),
+                    event: Event::JobFinished { failed },
+                });
+            }
+            None => loop {
+                let update = match updates.recv().await {
+                    Ok(update) => update,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                };
+                if job.is_some() && update.job != job {
+                    continue;
+                }
+                yield progress_event(&update);
+                if job.is_some() && matches!(update.event, Event::JobFinished { .. }) {
+                    break;
+                }
+            },
+        }
+    })
+}
+
+//a page with a progress bar following the job's events
+#[get("/jobs/<id>/watch")]
+fn watch_job(id: u64) -> Result<Html<String>, NotFound<String>> {
+    match app::jobs::jobs().get(id) {
+        Some(_) => Ok(Html(app::jobs::watch_page(id))),
+        None => Err(NotFound(format!("No job {id}"))),
+    }
+}
+
+//users whose new tweets are archived on a schedule
+#[get("/watchlist")]
+fn watchlist(archive: &Archive) -> Json<String> {
+    Json(
+        serde_json::to_string_pretty(&app::watchlist::list(archive))
+            .expect("Failed to serve the watchlist"),
+    )
+}
+
+#[get("/watchlist/<twitter_handle>")]
+fn watched_user(archive: &Archive, twitter_handle: &str) -> Result<Json<String>, NotFound<String>> {
+    match app::watchlist::get(archive, twitter_handle) {
+        Some(watched) => Ok(Json(
+            serde_json::to_string_pretty(&watched).expect("Failed to serve the watched user"),
+        )),
+        None => Err(NotFound(format!("@{twitter_handle} is not watched"))),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct Watch {
+    twitter_handle: String,
+    #[serde(default = "default_interval")]
+    interval: String,
+}
+
+fn default_interval() -> String {
+    "@daily".to_string()
+}
+
+//e.g. POST {"twitter_handle": "yudapearl", "interval": "6h"}, watching a user again changes the interval
+#[post("/watchlist", data = "<watch>")]
+fn watch_user(archive: &Archive, watch: &str) -> Result<Json<String>, BadRequest<String>> {
+    let watch: Watch = serde_json::from_str(watch).map_err(|error| {
+        BadRequest(Some(format!(
+            "Invalid watch, try {{\"twitter_handle\": \"<twitter_handle>\", \"interval\": \"6h\"}}: {error}"
+        )))
+    })?;
+    match app::watchlist::watch(archive, &watch.twitter_handle, &watch.interval) {
+        Ok(watched) => Ok(Json(
+            serde_json::to_string_pretty(&watched).expect("Failed to serve the watched user"),
+        )),
+        Err(error) => Err(BadRequest(Some(error))),
+    }
+}
+
+//stops syncing the user, what's archived stays archived
+#[delete("/watchlist/<twitter_handle>")]
+fn unwatch_user(archive: &Archive, twitter_handle: &str) -> Result<Status, NotFound<String>> {
+    match app::watchlist::unwatch(archive, twitter_handle) {
+        true => Ok(Status::NoContent),
+        false => Err(NotFound(format!("@{twitter_handle} is not watched"))),
+    }
+}
+
+#[get("/tombstones")]
+fn tombstones(archive: &Archive) -> Json<String> {
+    Json(
+        serde_json::to_string_pretty(&app::tombstones::list(archive))
+            .expect("Failed to serve tombstones"),
+    )
+}
+
+//newest first
+#[get("/webhooks/deliveries")]
+fn webhook_deliveries(archive: &Archive) -> Json<String> {
+    Json(
+        serde_json::to_string_pretty(&app::webhooks::deliveries(archive))
+            .expect("Failed to serve webhook deliveries"),
+    )
+}
+
+#[get("/webhooks/deliveries/<id>")]
+fn webhook_delivery(archive: &Archive, id: u64) -> Result<Json<String>, NotFound<String>> {
+    match app::webhooks::delivery(archive, id) {
+        Some(delivery) => Ok(Json(
+            serde_json::to_string_pretty(&delivery).expect("Failed to serve webhook delivery"),
+        )),
+        None => Err(NotFound(format!("No webhook delivery {id}"))),
+    }
+}
+
+//for checking a receiver is reachable and verifies signatures, follow the deliveries it returns
+#[post("/webhooks/ping")]
+fn ping_webhooks(archive: &Archive) -> Custom<Json<String>> {
+    let deliveries = app::webhooks::notify(archive, app::webhooks::Event::Ping);
+    Custom(
+        Status::Accepted,
+        Json(
+            serde_json::to_string_pretty(&deliveries).expect("Failed to serve webhook deliveries"),
+        ),
+    )
+}
+
+//each bearer token by its last characters, with its requests and rate limits per endpoint
+#[get("/twitter/usage")]
+fn twitter_usage() -> Json<String> {
+    Json(
+        serde_json::to_string_pretty(&app::api::tokens::current().usage())
+            .expect("Failed to serve bearer token usage"),
+    )
+}
+
+//archived users as read-only activitypub actors, see app::activitypub
+fn activity_json(document: serde_json::Value) -> (ContentType, String) {
+    (
+        ContentType::new("application", "activity+json"),
+        document.to_string(),
     )
 }
 
-#[launch]
-pub fn rocket() -> _ {
+fn not_archived(twitter_handle: &str) -> NotFound<String> {
+    NotFound(format!("@{twitter_handle} is not in the archive"))
+}
+
+#[get("/.well-known/webfinger?<resource>")]
+fn webfinger(
+    archive: &Archive,
+    site: &State<Site>,
+    resource: &str,
+) -> Result<(ContentType, String), NotFound<String>> {
+    match app::activitypub::webfinger(archive, site, resource) {
+        Some(document) => Ok((
+            ContentType::new("application", "jrd+json"),
+            document.to_string(),
+        )),
+        None => Err(NotFound(format!("No archived account \"{resource}\""))),
+    }
+}
+
+//the first request for an archive's actor waits for its key to be generated
+#[get("/users/<twitter_handle>")]
+async fn activitypub_actor(
+    archive: &'static Archive,
+    site: &State<Site>,
+    twitter_handle: &str,
+) -> Result<(ContentType, String), Custom<String>> {
+    match app::activitypub::actor(archive, site, twitter_handle).await {
+        Ok(Some(actor)) => Ok(activity_json(actor)),
+        Ok(None) => Err(Custom(
+            Status::NotFound,
+            format!("@{twitter_handle} is not in the archive"),
+        )),
+        Err(error) => Err(Custom(Status::InternalServerError, error)),
+    }
+}
+
+#[get("/users/<twitter_handle>/outbox?<page>")]
+fn activitypub_outbox(
+    archive: &Archive,
+    site: &State<Site>,
+    twitter_handle: &str,
+    page: Option<usize>,
+) -> Result<(ContentType, String), NotFound<String>> {
+    app::activitypub::outbox(archive, site, twitter_handle, page)
+        .map(activity_json)
+        .ok_or_else(|| NotFound(format!("No such outbox page for @{twitter_handle}")))
+}
+
+#[get("/users/<twitter_handle>/followers")]
+fn activitypub_followers(
+    archive: &Archive,
+    site: &State<Site>,
+    twitter_handle: &str,
+) -> Result<(ContentType, String), NotFound<String>> {
+    app::activitypub::followers(archive, site, twitter_handle)
+        .map(activity_json)
+        .ok_or_else(|| not_archived(twitter_handle))
+}
+
+#[get("/users/<twitter_handle>/statuses/<id>")]
+fn activitypub_status(
+    archive: &Archive,
+    site: &State<Site>,
+    twitter_handle: &str,
+    id: u64,
+) -> Result<(ContentType, String), NotFound<String>> {
+    app::activitypub::status(archive, site, twitter_handle, id)
+        .map(activity_json)
+        .ok_or_else(|| NotFound(format!("No archived tweet {id} by @{twitter_handle}")))
+}
+
+//the activity is handled after responding, once its signature is checked, and the follower's
+//server is sent an Accept when it's done
+#[post("/users/<twitter_handle>/inbox", data = "<activity>")]
+fn activitypub_inbox(
+    archive: &'static Archive,
+    site: &State<Site>,
+    twitter_handle: &str,
+    request: app::activitypub::InboxRequest,
+    activity: &str,
+) -> Result<Status, Custom<String>> {
+    if archive.user_from_twitter_handle(twitter_handle).is_none() {
+        return Err(Custom(
+            Status::NotFound,
+            format!("@{twitter_handle} is not in the archive"),
+        ));
+    }
+    if !request.is_signed() {
+        return Err(Custom(
+            Status::Unauthorized,
+            "Activities have to be signed with an HTTP Signature".to_string(),
+        ));
+    }
+    if let Err(error) = serde_json::from_str::<serde_json::Value>(activity) {
+        return Err(Custom(
+            Status::BadRequest,
+            format!("Invalid activity: {error}"),
+        ));
+    }
+    let site = site.inner().clone();
+    let twitter_handle = twitter_handle.to_string();
+    let activity = activity.to_string();
+    tokio::spawn(async move {
+        if let Err(error) =
+            app::activitypub::receive(archive, &site, &twitter_handle, &request, &activity).await
+        {
+            println!("Failed to handle an activity for @{twitter_handle}: {error}");
+        }
+    });
+    Ok(Status::Accepted)
+}
+
+//in the url the query will look like "/search?query=whatever"
+//the query supports twitter's operators, e.g. "from:yudapearl causal -is:reply since:2021-01-01"
+//with "&mode=fuzzy" terms tolerate typos, with "&mode=regex" the query is a regex over the text
+//with "&group=conversation" each match is shown in its thread, grouped by conversation
+#[get("/search?<query>&<mode>&<group>")]
+async fn search(
+    archive: &'static Archive,
+    query: &str,
+    mode: Option<&str>,
+    group: Option<&str>,
+) -> Result<String, BadRequest<String>> {
+    let mode = match mode.map(app::search::Mode::from_name) {
+        Some(Some(mode)) => mode,
+        Some(None) => {
+            return Err(BadRequest(Some(
+                "Unknown search mode, try \"query\", \"fuzzy\" or \"regex\"".to_string(),
+            )))
+        }
+        None => app::search::Mode::default(),
+    };
+    let by_conversation = match group {
+        Some("conversation") => true,
+        Some(group) => {
+            return Err(BadRequest(Some(format!(
+                "Unknown grouping \"{group}\", try \"conversation\""
+            ))))
+        }
+        None => false,
+    };
+    //searching can take a while on a big archive, so it is kept off the async workers
+    let query = query.to_string();
+    let results = tokio::task::spawn_blocking(move || match by_conversation {
+        true => app::search::search_by_conversation(archive, &query, mode).map(|conversations| {
+            ron::ser::to_string_pretty(&conversations, ron::ser::PrettyConfig::new())
+        }),
+        false => app::search::search(archive, &query, mode)
+            .map(|tweets| ron::ser::to_string_pretty(&tweets, ron::ser::PrettyConfig::new())),
+    })
+    .await
+    .expect("Search task failed");
+    match results {
+        Ok(results) => Ok(results.expect("Failed to serve search results")),
+        Err(error) => Err(BadRequest(Some(error.to_string()))),
+    }
+}
+
+#[rocket::main]
+async fn main() {
+    dotenv().ok();
+    let args = cli::Args::from_env();
+    match args.command.as_deref() {
+        None | Some("serve") => rocket()
+            .launch()
+            .await
+            .expect("Failed to launch the server"),
+        Some(_) => cli::run(&args).await,
+    }
+}
+
+pub fn rocket() -> Rocket<Build> {
     dotenv().ok();
-    let figment = rocket::Config::figment();
+    rocket_from(cli::figment(&cli::Args::from_env()))
+}
+
+//the server for a configuration, archives and all, which the tests point at their own data
+pub fn rocket_from(figment: Figment) -> Rocket<Build> {
+    archive::configure(&cli::archive_config(&figment));
+    println!("Serving archives: {:?}", archive::names());
+    app::api::configure(
+        &figment
+            .extract::<ApiConfig>()
+            .expect("Failed to read the Twitter API configuration"),
+    );
+    let site: Site = figment
+        .extract()
+        .expect("Failed to read the site configuration");
+    let job_config: JobConfig = figment
+        .extract()
+        .expect("Failed to read the job configuration");
+    let sync_config: SyncConfig = figment
+        .extract()
+        .expect("Failed to read the sync configuration");
+    app::activitypub::configure(
+        figment
+            .extract::<ActivityPubConfig>()
+            .expect("Failed to read the activitypub configuration"),
+    );
+    app::webhooks::configure(
+        figment
+            .extract::<WebhookConfig>()
+            .expect("Failed to read the webhook configuration"),
+    );
     rocket::custom(figment)
+        .manage(site)
+        .attach(AdHoc::on_liftoff("Job workers", move |_| {
+            Box::pin(async move { app::jobs::start(&job_config) })
+        }))
+        .attach(AdHoc::on_liftoff("Watchlist scheduler", move |_| {
+            Box::pin(async move { app::watchlist::start(&sync_config) })
+        }))
+        .mount("/", routes![twitter_usage])
+        .mount("/", routes![ping_webhooks])
+        .mount("/", routes![webhook_delivery])
+        .mount("/", routes![webhook_deliveries])
+        .mount("/", routes![tombstones])
+        .mount("/", routes![unwatch_user])
+        .mount("/", routes![watch_user])
+        .mount("/", routes![watched_user])
+        .mount("/", routes![watchlist])
+        .mount("/", routes![progress_events])
+        .mount("/", routes![watch_job])
+        .mount("/", routes![job_by_id])
+        .mount("/", routes![enqueue_job])
+        .mount("/", routes![activitypub_inbox])
+        .mount("/", routes![activitypub_status])
+        .mount("/", routes![activitypub_followers])
+        .mount("/", routes![activitypub_outbox])
+        .mount("/", routes![activitypub_actor])
+        .mount("/", routes![webfinger])
+        .mount("/", routes![search_feed])
+        .mount("/", routes![rss_feed_by_twitter_handle])
+        .mount("/", routes![atom_feed_by_twitter_handle])
         .mount("/", routes![search])
+        .mount("/", routes![export_table])
+        .mount("/", routes![export_users_table])
+        .mount("/", routes![interaction_graph])
+        .mount("/", routes![stats_dashboard_by_twitter_handle])
+        .mount("/", routes![stats_by_twitter_handle])
         .mount("/", routes![conversations_by_twitter_handle])
         .mount("/", routes![tweets_by_user])
         .mount("/", routes![user_info_by_twitter_handle])
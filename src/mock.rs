@@ -0,0 +1,608 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rocket::http::{ContentType, Status};
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket::response::{self, Responder, Response};
+use rocket::{Build, Rocket, State};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use twitter_v2::id::NumericId;
+use twitter_v2::{Tweet, User};
+
+// A stand-in for the Twitter API v2, serving the users and tweets of a fixtures file so the
+// archiver can run without network access, from the tests or with `mock-twitter`. It answers
+// the requests app::api sends, in the API's shapes, under "/2/". Fixtures are JSON:
+//
+//     {
+//         "users": [{"id": "1", "name": "Alice", "username": "alice"}],
+//         "tweets": [{"id": "10", "text": "hi", "author_id": "1", "conversation_id": "10"}],
+//         "media": [{"media_key": "3_10", "type": "photo", "url": "/media/3_10.jpg"}],
+//         "deleted_tweets": ["11"],
+//         "rate_limited": {"/2/users/by/username/alice": 2},
+//         "failures": {"/2/tweets/12": 503},
+//         "exhausted_tokens": ["spent-token-0000"],
+//         "actors": {"zoe": "-----BEGIN PUBLIC KEY-----\n..."}
+//     }
+//
+// Like twitter, tweets only come with the fields asked for in `tweet.fields` besides their id and
+// text, and the media they attach come in "includes" when asked for with `expansions`. Media urls
+// starting with "/" are on the mock itself, which serves any file under "/media/". Deleted tweets
+// are left out of timelines and lookups as if they never existed. The first `rate_limited`
+// requests to a path are answered 429 with a rate limit reset a second away, and a path in
+// `failures` is always answered with its status. Every request needs a bearer token, and one in
+// `exhausted_tokens` has used up its rate limit for the next 15 minutes.
+//
+// Besides twitter it stands in for the servers the archiver posts to: the `actors` are served as
+// ActivityPub actors under "/actors/<name>", with the public key they sign with, and any POST is
+// kept and answered 200, or as `rate_limited` and `failures` say, so it can be a follower's inbox
+// ("/actors/<name>/inbox") or a webhook receiver alike.
+
+//what the x-rate-limit-limit header says, twitter's limit for a user's timeline
+const RATE_LIMIT: usize = 900;
+
+//seconds until the rate limit resets, unless a request is rate limited by the fixtures
+const RATE_LIMIT_WINDOW: u64 = 15 * 60;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Fixtures {
+    #[serde(default)]
+    pub users: Vec<User>,
+    #[serde(default)]
+    pub tweets: Vec<Tweet>,
+    //twitter's media objects, with every field
+    #[serde(default)]
+    pub media: Vec<Value>,
+    #[serde(default)]
+    pub deleted_tweets: Vec<NumericId>,
+    //path -> how many requests to it are rate limited before it answers
+    #[serde(default)]
+    pub rate_limited: HashMap<String, usize>,
+    //path -> the status it always answers with
+    #[serde(default)]
+    pub failures: HashMap<String, u16>,
+    //bearer tokens every request with is answered 429
+    #[serde(default)]
+    pub exhausted_tokens: Vec<String>,
+    //name -> the PEM of the actor's public key
+    #[serde(default)]
+    pub actors: HashMap<String, String>,
+}
+
+impl Fixtures {
+    pub fn from_file(path: &str) -> Fixtures {
+        let fixtures = std::fs::read_to_string(path)
+            .unwrap_or_else(|_| panic!("Failed to read fixtures from \"{path}\""));
+        serde_json::from_str(&fixtures)
+            .unwrap_or_else(|error| panic!("Failed to parse fixtures from \"{path}\": {error}"))
+    }
+
+    fn is_deleted(&self, id: u64) -> bool {
+        self.deleted_tweets
+            .iter()
+            .any(|deleted| deleted.as_u64() == id)
+    }
+
+    fn tweet(&self, id: u64) -> Option<&Tweet> {
+        self.tweets
+            .iter()
+            .find(|tweet| tweet.id.as_u64() == id && !self.is_deleted(id))
+    }
+}
+
+//a POST the mock was sent, with its headers by lowercase name
+#[derive(Debug, Clone)]
+pub struct Post {
+    pub path: String,
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+//the fixtures behind a running mock
+pub struct Mock {
+    fixtures: Mutex<Fixtures>,
+    //every path requested, in order
+    requests: Mutex<Vec<String>>,
+    //every POST, in order
+    posts: Mutex<Vec<Post>>,
+}
+
+impl Mock {
+    pub fn new(fixtures: Fixtures) -> Mock {
+        Mock {
+            fixtures: Mutex::new(fixtures),
+            requests: Mutex::new(Vec::new()),
+            posts: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn fixtures(&self) -> MutexGuard<'_, Fixtures> {
+        self.fixtures
+            .lock()
+            .expect("The fixtures lock was poisoned")
+    }
+
+    //how many requests there have been to the path, this one included
+    fn request(&self, path: &str) -> usize {
+        let mut requests = self
+            .requests
+            .lock()
+            .expect("The requests lock was poisoned");
+        requests.push(path.to_string());
+        requests
+            .iter()
+            .filter(|requested| *requested == path)
+            .count()
+    }
+
+    //the error a request to the path is answered with, if it's rate limited or set to fail
+    fn failure(fixtures: &Fixtures, path: &str, count: usize) -> Option<Answer> {
+        if fixtures
+            .rate_limited
+            .get(path)
+            .is_some_and(|&limited| count <= limited)
+        {
+            return Some(Answer {
+                reset_in: 1,
+                ..Answer::error(Status::TooManyRequests, count)
+            });
+        }
+        fixtures.failures.get(path).map(|&status| {
+            Answer::error(
+                Status::from_code(status).unwrap_or(Status::InternalServerError),
+                count,
+            )
+        })
+    }
+
+    // Answers a request to its path with what `respond` makes of the fixtures, unless the
+    // request isn't authorized, is rate limited or the path is set to fail.
+    fn answer(&self, call: &Call, respond: impl FnOnce(&Fixtures) -> Value) -> Answer {
+        let count = self.request(&call.path);
+        let fixtures = self.fixtures();
+        let token = match &call.token {
+            Some(token) => token,
+            None => return Answer::error(Status::Unauthorized, count),
+        };
+        if fixtures.exhausted_tokens.contains(token) {
+            return Answer::error(Status::TooManyRequests, count);
+        }
+        if let Some(failure) = Mock::failure(&fixtures, &call.path, count) {
+            return failure;
+        }
+        Answer {
+            status: Status::Ok,
+            body: respond(&fixtures),
+            count,
+            reset_in: RATE_LIMIT_WINDOW,
+        }
+    }
+}
+
+// For the tests to change what twitter says while the mock runs: a user can tweet, delete a
+// tweet or change their profile between two syncs.
+#[cfg(test)]
+impl Mock {
+    pub fn requests(&self) -> Vec<String> {
+        self.requests
+            .lock()
+            .expect("The requests lock was poisoned")
+            .clone()
+    }
+
+    //the POSTs to the path, in order
+    pub fn posts(&self, path: &str) -> Vec<Post> {
+        self.posts
+            .lock()
+            .expect("The posts lock was poisoned")
+            .iter()
+            .filter(|post| post.path == path)
+            .cloned()
+            .collect()
+    }
+
+    pub fn tweet(&self, tweet: Tweet) {
+        self.fixtures().tweets.push(tweet);
+    }
+
+    pub fn delete_tweet(&self, id: u64) {
+        self.fixtures().deleted_tweets.push(NumericId::new(id));
+    }
+
+    //replaces the user with the same id
+    pub fn update_user(&self, user: User) {
+        let mut fixtures = self.fixtures();
+        fixtures.users.retain(|archived| archived.id != user.id);
+        fixtures.users.push(user);
+    }
+}
+
+//the path of a request, the bearer token it came with and the fields and expansions it asked for
+pub struct Call {
+    path: String,
+    token: Option<String>,
+    tweet_fields: Vec<String>,
+    media_fields: Vec<String>,
+    expansions: Vec<String>,
+    //"localhost:<port>", for the media urls on the mock
+    host: String,
+}
+
+impl Call {
+    //the tweet as twitter sends it, with only the fields that were asked for
+    fn tweet(&self, tweet: &Tweet) -> Value {
+        let mut tweet = json!(tweet);
+        if let Value::Object(fields) = &mut tweet {
+            fields.retain(|name, _| {
+                name == "id" || name == "text" || self.tweet_fields.contains(name)
+            });
+        }
+        tweet
+    }
+
+    //the media as twitter sends it, with only the fields that were asked for
+    fn media(&self, media: &Value) -> Value {
+        let mut media = media.clone();
+        if let Value::Object(fields) = &mut media {
+            fields.retain(|name, _| {
+                name == "media_key" || name == "type" || self.media_fields.contains(name)
+            });
+        }
+        self.resolve_urls(&mut media);
+        media
+    }
+
+    fn resolve_urls(&self, value: &mut Value) {
+        match value {
+            Value::String(url) if url.starts_with('/') => {
+                *url = format!("http://{}{url}", self.host);
+            }
+            Value::Array(values) => values.iter_mut().for_each(|value| self.resolve_urls(value)),
+            Value::Object(fields) => fields
+                .iter_mut()
+                .filter(|(name, _)| name.ends_with("url") || *name == "variants")
+                .for_each(|(_, value)| self.resolve_urls(value)),
+            _ => {}
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Call {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let token = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|authorization| authorization.strip_prefix("Bearer "))
+            .filter(|token| !token.is_empty())
+            .map(String::from);
+        //read off the query as is, rocket would take "tweet.fields" for a nested field
+        let list = |name: &str| -> Vec<String> {
+            request
+                .uri()
+                .query()
+                .and_then(|query| query.segments().find(|(segment, _)| *segment == name))
+                .map(|(_, values)| values.split(',').map(String::from).collect())
+                .unwrap_or_default()
+        };
+        Outcome::Success(Call {
+            path: request.uri().path().to_string(),
+            token,
+            tweet_fields: list("tweet.fields"),
+            media_fields: list("media.fields"),
+            expansions: list("expansions"),
+            host: request
+                .headers()
+                .get_one("Host")
+                .unwrap_or("localhost")
+                .to_string(),
+        })
+    }
+}
+
+//a JSON body with twitter's rate limit headers
+pub struct Answer {
+    status: Status,
+    body: Value,
+    //how many requests there have been to the path, this one included
+    count: usize,
+    //seconds until the rate limit resets
+    reset_in: u64,
+}
+
+impl Answer {
+    //in the shape of twitter's problem responses
+    fn error(status: Status, count: usize) -> Answer {
+        Answer {
+            status,
+            body: json!({
+                "title": status.reason().unwrap_or("Error"),
+                "detail": status.reason().unwrap_or("Error"),
+                "type": "about:blank",
+                "status": status.code,
+            }),
+            count,
+            reset_in: RATE_LIMIT_WINDOW,
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for Answer {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("The clock is before 1970")
+            .as_secs();
+        let remaining = match self.status == Status::TooManyRequests {
+            true => 0,
+            false => RATE_LIMIT.saturating_sub(self.count),
+        };
+        let reset = now + self.reset_in;
+        let body = self.body.to_string();
+        Response::build()
+            .status(self.status)
+            .header(ContentType::JSON)
+            .raw_header("x-rate-limit-limit", RATE_LIMIT.to_string())
+            .raw_header("x-rate-limit-remaining", remaining.to_string())
+            .raw_header("x-rate-limit-reset", reset.to_string())
+            .sized_body(body.len(), Cursor::new(body))
+            .ok()
+    }
+}
+
+//what twitter answers for an id that doesn't exist, alongside whatever did
+fn not_found(resource_type: &str, parameter: &str, value: &str) -> Value {
+    json!({
+        "value": value,
+        "detail": format!("Could not find {resource_type} with {parameter}: [{value}]."),
+        "title": "Not Found Error",
+        "resource_type": resource_type,
+        "parameter": parameter,
+        "resource_id": value,
+        "type": "https://api.twitter.com/2/problems/resource-not-found",
+    })
+}
+
+fn data_or_not_found<T: serde::Serialize>(
+    data: Option<&T>,
+    resource_type: &str,
+    value: &str,
+) -> Value {
+    match data {
+        Some(data) => json!({ "data": data }),
+        None => json!({ "errors": [not_found(resource_type, "id", value)] }),
+    }
+}
+
+#[get("/users/by/username/<username>")]
+fn user_by_username(mock: &State<Arc<Mock>>, call: Call, username: &str) -> Answer {
+    mock.answer(&call, |fixtures| {
+        match fixtures
+            .users
+            .iter()
+            .find(|user| user.username.eq_ignore_ascii_case(username))
+        {
+            Some(user) => json!({ "data": user }),
+            None => json!({ "errors": [not_found("user", "username", username)] }),
+        }
+    })
+}
+
+#[get("/users/<id>")]
+fn user_by_id(mock: &State<Arc<Mock>>, call: Call, id: u64) -> Answer {
+    mock.answer(&call, |fixtures| {
+        let user = fixtures.users.iter().find(|user| user.id == id);
+        data_or_not_found(user, "user", &id.to_string())
+    })
+}
+
+// Newest first, like twitter: at most `max_results` tweets (10 by default) newer than
+// `since_id` and older than `until_id`, continued from `pagination_token` which is the id of the
+// oldest tweet of the page before.
+#[get("/users/<id>/tweets?<max_results>&<since_id>&<until_id>&<pagination_token>")]
+fn user_tweets(
+    mock: &State<Arc<Mock>>,
+    call: Call,
+    id: u64,
+    max_results: Option<usize>,
+    since_id: Option<u64>,
+    until_id: Option<u64>,
+    pagination_token: Option<u64>,
+) -> Answer {
+    mock.answer(&call, |fixtures| {
+        let until_id = match (until_id, pagination_token) {
+            (Some(until_id), Some(token)) => Some(until_id.min(token)),
+            (until_id, token) => until_id.or(token),
+        };
+        let mut tweets: Vec<&Tweet> = fixtures
+            .tweets
+            .iter()
+            .filter(|tweet| tweet.author_id.is_some_and(|author_id| author_id == id))
+            .filter(|tweet| !fixtures.is_deleted(tweet.id.as_u64()))
+            .filter(|tweet| since_id.is_none_or(|since_id| tweet.id.as_u64() > since_id))
+            .filter(|tweet| until_id.is_none_or(|until_id| tweet.id.as_u64() < until_id))
+            .collect();
+        tweets.sort_by_key(|tweet| std::cmp::Reverse(tweet.id.as_u64()));
+        let more = tweets.len() > max_results.unwrap_or(10).clamp(5, 100);
+        tweets.truncate(max_results.unwrap_or(10).clamp(5, 100));
+        let mut meta = json!({ "result_count": tweets.len() });
+        if let (Some(newest), Some(oldest)) = (tweets.first(), tweets.last()) {
+            meta["newest_id"] = json!(newest.id.to_string());
+            meta["oldest_id"] = json!(oldest.id.to_string());
+            if more {
+                meta["next_token"] = json!(oldest.id.to_string());
+            }
+        }
+        //twitter leaves "data" out of an empty page
+        match tweets.is_empty() {
+            true => json!({ "meta": meta }),
+            false => {
+                let tweets: Vec<Value> = tweets.iter().map(|tweet| call.tweet(tweet)).collect();
+                json!({ "data": tweets, "meta": meta })
+            }
+        }
+    })
+}
+
+#[get("/tweets/<id>")]
+fn tweet_by_id(mock: &State<Arc<Mock>>, call: Call, id: u64) -> Answer {
+    mock.answer(&call, |fixtures| {
+        let tweet = fixtures.tweet(id).map(|tweet| call.tweet(tweet));
+        data_or_not_found(tweet.as_ref(), "tweet", &id.to_string())
+    })
+}
+
+//a comma separated list of ids, the ones that don't exist are listed in "errors"
+#[get("/tweets?<ids>")]
+fn tweets_by_ids(mock: &State<Arc<Mock>>, call: Call, ids: &str) -> Answer {
+    mock.answer(&call, |fixtures| {
+        let (found, missing): (Vec<&str>, Vec<&str>) = ids
+            .split(',')
+            .partition(|id| id.parse().ok().and_then(|id| fixtures.tweet(id)).is_some());
+        let tweets: Vec<Value> = found
+            .iter()
+            .filter_map(|id| fixtures.tweet(id.parse().ok()?))
+            .map(|tweet| call.tweet(tweet))
+            .collect();
+        let media_keys: Vec<String> = found
+            .iter()
+            .filter_map(|id| fixtures.tweet(id.parse().ok()?))
+            .filter_map(|tweet| tweet.attachments.as_ref()?.media_keys.as_ref())
+            .flatten()
+            .map(|media_key| media_key.to_string())
+            .collect();
+        let media: Vec<Value> = fixtures
+            .media
+            .iter()
+            .filter(|media| {
+                media["media_key"]
+                    .as_str()
+                    .is_some_and(|media_key| media_keys.iter().any(|key| key == media_key))
+            })
+            .map(|media| call.media(media))
+            .collect();
+        let mut body = json!({});
+        if !tweets.is_empty() {
+            body["data"] = json!(tweets);
+        }
+        if call
+            .expansions
+            .iter()
+            .any(|expansion| expansion == "attachments.media_keys")
+            && !media.is_empty()
+        {
+            body["includes"] = json!({ "media": media });
+        }
+        if !missing.is_empty() {
+            body["errors"] = missing
+                .iter()
+                .map(|id| not_found("tweet", "ids", id))
+                .collect();
+        }
+        body
+    })
+}
+
+//any media file, its contents are its name
+#[get("/media/<file>")]
+fn media_file(file: &str) -> Vec<u8> {
+    format!("media {file}").into_bytes()
+}
+
+//an ActivityPub actor the way Mastodon serves one, with the key it signs its requests with
+#[get("/actors/<name>")]
+fn actor(mock: &State<Arc<Mock>>, call: Call, name: &str) -> Option<(ContentType, String)> {
+    mock.request(&call.path);
+    let public_key_pem = mock.fixtures().actors.get(name)?.clone();
+    let id = format!("http://{}/actors/{name}", call.host);
+    let actor = json!({
+        "@context": ["https://www.w3.org/ns/activitystreams", "https://w3id.org/security/v1"],
+        "id": id,
+        "type": "Person",
+        "preferredUsername": name,
+        "inbox": format!("{id}/inbox"),
+        "publicKey": {
+            "id": format!("{id}#main-key"),
+            "owner": id,
+            "publicKeyPem": public_key_pem,
+        },
+    });
+    Some((
+        ContentType::new("application", "activity+json"),
+        actor.to_string(),
+    ))
+}
+
+//the path and headers of a POST
+pub struct Posted {
+    path: String,
+    headers: HashMap<String, String>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Posted {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(Posted {
+            path: request.uri().path().to_string(),
+            headers: request
+                .headers()
+                .iter()
+                .map(|header| {
+                    (
+                        header.name().as_str().to_lowercase(),
+                        header.value().to_string(),
+                    )
+                })
+                .collect(),
+        })
+    }
+}
+
+//kept for the tests to look at, whatever the path
+#[post("/<_path..>", data = "<body>")]
+fn post(mock: &State<Arc<Mock>>, posted: Posted, _path: PathBuf, body: String) -> Answer {
+    let count = mock.request(&posted.path);
+    mock.posts
+        .lock()
+        .expect("The posts lock was poisoned")
+        .push(Post {
+            path: posted.path.clone(),
+            headers: posted.headers,
+            body,
+        });
+    Mock::failure(&mock.fixtures(), &posted.path, count).unwrap_or(Answer {
+        status: Status::Ok,
+        body: json!({}),
+        count,
+        reset_in: RATE_LIMIT_WINDOW,
+    })
+}
+
+//the mock API at "http://localhost:<port>/2/", quiet unless something goes wrong
+pub fn rocket(mock: Arc<Mock>, port: u16) -> Rocket<Build> {
+    let config = rocket::Config {
+        port,
+        log_level: rocket::config::LogLevel::Critical,
+        ..rocket::Config::default()
+    };
+    rocket::custom(config)
+        .manage(mock)
+        .mount(
+            "/2",
+            routes![
+                user_by_username,
+                user_by_id,
+                user_tweets,
+                tweet_by_id,
+                tweets_by_ids
+            ],
+        )
+        .mount("/", routes![media_file, actor, post])
+}
@@ -0,0 +1,2101 @@
+use std::collections::HashMap;
+use std::net::{TcpListener, TcpStream};
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use futures::FutureExt;
+use rocket::http::{ContentType, Header, Status};
+use rocket::local::asynchronous::Client;
+use serde_json::json;
+use tempfile::TempDir;
+use twitter_v2::{Tweet, User};
+
+use crate::app::api::cassette::{self, Cassette};
+use crate::app::api::tokens::{self, Pool};
+use crate::app::api::{self, ApiConfig};
+use crate::app::archive::Archive;
+use crate::app::io::layout::{Layout, StorageFormat};
+use crate::app::site::Site;
+use crate::app::{
+    self, activitypub, archive, convert, export, migrations, progress, search, tombstones, webhooks,
+};
+use crate::mock::{self, Fixtures, Mock};
+
+// The archiver against the mock Twitter API serving "tests/fixtures/twitter.json", loading into
+// an empty archive and reading back what was stored. Every test has its own users in the
+// fixtures, so they can run at the same time against the one mock.
+
+//what the mock is asked with, it takes any token
+const BEARER_TOKEN: &str = "test-bearer-token";
+
+//one the mock always says is rate limited
+const SPENT_BEARER_TOKEN: &str = "spent-token-0000";
+
+static MOCK_PORT: OnceLock<u16> = OnceLock::new();
+
+//started by the first test that needs it, on its own thread so it outlives each test's runtime
+fn mock() -> &'static Arc<Mock> {
+    static MOCK: OnceLock<Arc<Mock>> = OnceLock::new();
+    MOCK.get_or_init(|| {
+        let fixtures: Fixtures =
+            serde_json::from_str(include_str!("../tests/fixtures/twitter.json"))
+                .expect("Failed to parse the fixtures");
+        let mock = Arc::new(Mock::new(fixtures));
+        let port = TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .expect("Failed to find a free port")
+            .port();
+        let server = mock.clone();
+        std::thread::spawn(move || {
+            tokio::runtime::Runtime::new()
+                .expect("Failed to start a runtime for the mock")
+                .block_on(mock::rocket(server, port).launch())
+                .expect("Failed to launch the mock Twitter API")
+        });
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        MOCK_PORT.get_or_init(|| port);
+        std::env::set_var("TWITTER_DEV_BEARER_TOKEN", BEARER_TOKEN);
+        api::configure(&ApiConfig {
+            api_base_url: format!("http://127.0.0.1:{port}/2"),
+            ..ApiConfig::default()
+        });
+        mock
+    })
+}
+
+//a url on the mock, which has to be running
+fn mock_url(path: &str) -> String {
+    let port = MOCK_PORT.get().expect("The mock is running");
+    format!("http://127.0.0.1:{port}{path}")
+}
+
+// The followers' servers the activitypub inbox requests are on the mock, so the tests let it
+// request private addresses, the same in the server and outside it.
+fn allow_private_activitypub_addresses() {
+    activitypub::configure(activitypub::ActivityPubConfig {
+        activitypub_private_addresses: true,
+    });
+}
+
+//an empty archive, deleted with the directory
+fn archive() -> (TempDir, Archive) {
+    let directory = tempfile::tempdir().expect("Failed to create a directory for the archive");
+    let archive = Archive::open("test", directory.path().to_path_buf());
+    (directory, archive)
+}
+
+fn tweet(id: u64, author_id: u64, text: &str) -> Tweet {
+    serde_json::from_value(json!({
+        "id": id.to_string(),
+        "text": text,
+        "author_id": author_id.to_string(),
+        "conversation_id": id.to_string(),
+        "created_at": "2022-03-05T10:00:00.000Z",
+    }))
+    .expect("Failed to make a tweet")
+}
+
+//a reply in the conversation started by `conversation_id`
+fn reply(id: u64, author_id: u64, replied_to: u64, conversation_id: u64, text: &str) -> Tweet {
+    serde_json::from_value(json!({
+        "id": id.to_string(),
+        "text": text,
+        "author_id": author_id.to_string(),
+        "conversation_id": conversation_id.to_string(),
+        "created_at": "2022-03-05T10:00:00.000Z",
+        "referenced_tweets": [{"type": "replied_to", "id": replied_to.to_string()}],
+    }))
+    .expect("Failed to make a reply")
+}
+
+fn at(mut tweet: Tweet, created_at: &str) -> Tweet {
+    tweet.created_at = Some(
+        time::OffsetDateTime::parse(created_at, &time::format_description::well_known::Rfc3339)
+            .expect("Failed to parse a date"),
+    );
+    tweet
+}
+
+fn with_media(mut tweet: Tweet, media_key: &str) -> Tweet {
+    tweet.attachments = Some(
+        serde_json::from_value(json!({ "media_keys": [media_key] }))
+            .expect("Failed to attach media"),
+    );
+    tweet
+}
+
+fn user(id: u64, name: &str, username: &str) -> User {
+    serde_json::from_value(json!({
+        "id": id.to_string(),
+        "name": name,
+        "username": username,
+    }))
+    .expect("Failed to make a user")
+}
+
+fn ids(tweets: &[Tweet]) -> Vec<u64> {
+    tweets.iter().map(|tweet| tweet.id.as_u64()).collect()
+}
+
+fn requests_to(mock: &Mock, prefix: &str) -> usize {
+    mock.requests()
+        .iter()
+        .filter(|path| path.starts_with(prefix))
+        .count()
+}
+
+#[tokio::test]
+async fn loads_a_user_their_tweets_and_conversations() {
+    let mock = mock();
+    let (directory, archive) = archive();
+    let user = app::load_user_from_twitter_handle(&archive, "alice").await;
+    assert_eq!(user.id, 1);
+    let conversations = app::load_conversations_from_twitter_handle(&archive, "alice").await;
+    assert_eq!(
+        ids(&archive
+            .user_tweets("alice")
+            .expect("Alice's tweets are archived")),
+        [104, 103, 102, 101]
+    );
+    //the thread is followed up to bob's tweet, newest first
+    let thread = conversations
+        .iter()
+        .find(|conversation| conversation[0].id == 103)
+        .expect("The thread is archived");
+    assert_eq!(ids(thread), [103, 102, 200]);
+    assert!(archive.tweet(200).is_some());
+    //with the metrics, entities and language twitter was asked for
+    let rows = export::table::tweet_rows(&archive, "alice").expect("Alice is archived");
+    let row = rows
+        .iter()
+        .find(|row| row.id == "104")
+        .expect("The quote is exported");
+    assert_eq!(
+        (row.retweets, row.replies, row.likes, row.quotes),
+        (Some(2), Some(1), Some(7), Some(0))
+    );
+    assert_eq!((row.urls, row.lang.as_deref()), (1, Some("en")));
+    let stats = app::stats::user_stats(&archive, "alice").expect("Alice is archived");
+    assert_eq!(stats.top_domains.len(), 1);
+    assert_eq!(
+        (
+            stats.top_domains[0].value.as_str(),
+            stats.top_domains[0].count
+        ),
+        ("example.com", 1)
+    );
+
+    //everything was written to disk
+    let reopened = Archive::open("test", directory.path().to_path_buf());
+    assert_eq!(
+        reopened
+            .user_from_twitter_handle("alice")
+            .map(|user| user.id),
+        Some(user.id)
+    );
+    assert_eq!(
+        reopened.user_conversations("alice"),
+        Some(conversations.clone())
+    );
+
+    //and is loaded from the archive from then on
+    let requests = requests_to(mock, "/2/users/1/");
+    app::load_conversations_from_twitter_handle(&archive, "alice").await;
+    assert_eq!(requests_to(mock, "/2/users/1/"), requests);
+}
+
+#[tokio::test]
+async fn reports_each_stored_tweet_once() {
+    mock();
+    let (_directory, archive) = archive();
+    let mut updates = progress::subscribe();
+    //a job of its own, to tell its events from those of the tests running alongside
+    let job = u64::MAX;
+    progress::JOB
+        .scope(job, async {
+            app::load_conversations_from_twitter_handle(&archive, "alice").await;
+            app::load_conversation_from_tweet_id(&archive, 103).await;
+        })
+        .await;
+    let mut stored = 0;
+    while let Ok(update) = updates.try_recv() {
+        if let (Some(update_job), progress::Event::TweetsStored { count }) =
+            (update.job, update.event)
+        {
+            if update_job == job {
+                stored += count;
+            }
+        }
+    }
+    //alice's four tweets and bob's one she replied to
+    assert_eq!(archive.tweets().len(), 5);
+    assert_eq!(stored, 5);
+}
+
+#[tokio::test]
+async fn downloads_the_media_tweets_attach() {
+    mock();
+    let (directory, archive) = archive();
+    app::load_conversations_from_twitter_handle(&archive, "ivan").await;
+    //photos as they are and videos as their best mp4
+    let media = directory.path().join("media");
+    assert_eq!(
+        std::fs::read_to_string(media.join("3_901.jpg"))
+            .ok()
+            .as_deref(),
+        Some("media sunrise.jpg")
+    );
+    assert_eq!(
+        std::fs::read_to_string(media.join("7_902.mp4"))
+            .ok()
+            .as_deref(),
+        Some("media waves-large.mp4")
+    );
+    assert_eq!(std::fs::read_dir(&media).map(Iterator::count).ok(), Some(2));
+    let tweets = archive
+        .user_tweets("ivan")
+        .expect("Ivan's tweets are archived");
+    assert_eq!(app::media::download(&archive, &tweets).await, 0);
+
+    //which the check finds attached and the mastodon export brings along
+    assert!(app::check::check(&archive.layout).is_empty());
+    let exported = TempDir::new().expect("Failed to create a directory");
+    let site = Site {
+        public_url: "https://archive.example".to_string(),
+    };
+    let exported = export::mastodon::export(&archive, &site, "ivan", exported.path())
+        .await
+        .expect("Ivan is archived");
+    assert_eq!((exported.media, exported.missing_media), (2, 0));
+}
+
+#[tokio::test]
+async fn pages_through_a_long_timeline() {
+    let mock = mock();
+    for id in 600_001..=600_250 {
+        mock.tweet(tweet(id, 6, "Again"));
+    }
+    let carol = app::load_user_from_twitter_handle(&archive().1, "carol").await;
+    let tweets = api::get_all_tweets_from_user(&carol).await;
+    assert_eq!(tweets.len(), 250);
+    assert_eq!(tweets.first().map(|tweet| tweet.id), Some(600_250.into()));
+    assert_eq!(tweets.last().map(|tweet| tweet.id), Some(600_001.into()));
+    //two full pages and the one that wasn't
+    assert_eq!(requests_to(mock, "/2/users/6/tweets"), 3);
+}
+
+#[tokio::test]
+async fn syncs_new_tweets_deletions_and_profile_changes() {
+    let mock = mock();
+    let (_directory, archive) = archive();
+    //nothing archived yet, so everything is new
+    assert_eq!(app::sync_user(&archive, "dave", |_, _| {}).await, 3);
+
+    mock.tweet(tweet(304, 3, "Four"));
+    mock.delete_tweet(302);
+    mock.update_user(user(3, "Dave (on holiday)", "dave"));
+    assert_eq!(app::sync_user(&archive, "dave", |_, _| {}).await, 1);
+    //deleted tweets stay archived, with a tombstone
+    assert_eq!(
+        ids(&archive
+            .user_tweets("dave")
+            .expect("Dave's tweets are archived")),
+        [304, 303, 302, 301]
+    );
+    let tombstones = tombstones::list(&archive);
+    assert_eq!(tombstones.len(), 1);
+    assert_eq!(tombstones[0].tweet_id, 302);
+    assert_eq!(tombstones[0].twitter_handle, "dave");
+    assert_eq!(
+        archive
+            .user_from_twitter_handle("dave")
+            .map(|user| user.name),
+        Some("Dave (on holiday)".to_string())
+    );
+    assert!(archive.conversation(304).is_some());
+
+    //a sync without news finds nothing, and records no tombstone twice
+    assert_eq!(app::sync_user(&archive, "dave", |_, _| {}).await, 0);
+    assert_eq!(tombstones::list(&archive).len(), 1);
+}
+
+#[tokio::test]
+async fn finds_deletions_anywhere_in_a_long_timeline() {
+    let mock = mock();
+    let timeline: Vec<Tweet> = (700_001..=700_150)
+        .rev()
+        .map(|id| tweet(id, 10, "Still here"))
+        .collect();
+    for tweet in &timeline {
+        mock.tweet(tweet.clone());
+    }
+    //the newest and the oldest, which a lookup of the newest hundred wouldn't get to
+    mock.delete_tweet(700_150);
+    mock.delete_tweet(700_001);
+    let (_directory, archive) = archive();
+    let recorded = |tombstones: Vec<tombstones::Tombstone>| -> Vec<u64> {
+        tombstones
+            .iter()
+            .map(|tombstone| tombstone.tweet_id)
+            .collect()
+    };
+    assert_eq!(
+        recorded(tombstones::check(&archive, "judy", &timeline).await),
+        [700_150, 700_001]
+    );
+    assert!(tombstones::check(&archive, "judy", &timeline)
+        .await
+        .is_empty());
+    assert_eq!(recorded(tombstones::list(&archive)), [700_150, 700_001]);
+}
+
+#[tokio::test]
+async fn waits_out_rate_limits() {
+    let mock = mock();
+    let (_directory, archive) = archive();
+    let started = Instant::now();
+    let erin = app::load_user_from_twitter_handle(&archive, "erin").await;
+    assert_eq!(erin.username, "erin");
+    assert!(started.elapsed() >= Duration::from_secs(1));
+    assert_eq!(requests_to(mock, "/2/users/by/username/erin"), 2);
+}
+
+#[tokio::test]
+async fn twitter_errors_fail_the_load() {
+    mock();
+    let (_directory, archive) = archive();
+    let panic = AssertUnwindSafe(app::load_tweet_from_id(&archive, 502))
+        .catch_unwind()
+        .await
+        .expect_err("A failing request fails the load");
+    let message = panic.downcast::<String>().expect("The panic has a message");
+    assert!(message.contains("503"), "{message}");
+    assert!(archive.tweet(502).is_none());
+}
+
+#[tokio::test]
+async fn deleted_tweets_are_not_found() {
+    mock();
+    let (_directory, archive) = archive();
+    assert_eq!(api::get_existing_tweet_ids(&[200, 199]).await, [200]);
+    let loaded = AssertUnwindSafe(app::load_tweet_from_id(&archive, 199))
+        .catch_unwind()
+        .await;
+    assert!(loaded.is_err());
+    assert!(archive.tweet(199).is_none());
+}
+
+#[tokio::test]
+async fn records_and_replays_a_cassette() {
+    let mock = mock();
+    let directory = tempfile::tempdir().expect("Failed to create a directory for the cassette");
+    let path = directory.path().join("bob.jsonl");
+    let (_recorded_directory, recorded) = archive();
+    let conversations = cassette::with(
+        Cassette::record(&path),
+        app::load_conversations_from_twitter_handle(&recorded, "bob"),
+    )
+    .await;
+    let contents = std::fs::read_to_string(&path).expect("The cassette was written");
+    assert_eq!(contents.lines().count(), 2);
+    assert!(contents.contains("users/by/username/bob"));
+    assert!(!contents.contains(BEARER_TOKEN));
+
+    //played back into another archive without asking the mock
+    let bob_requests =
+        || requests_to(mock, "/2/users/by/username/bob") + requests_to(mock, "/2/users/2/");
+    let requests = bob_requests();
+    let (_replayed_directory, replayed) = archive();
+    let replayed_conversations = cassette::with(
+        Cassette::replay(&path),
+        app::load_conversations_from_twitter_handle(&replayed, "bob"),
+    )
+    .await;
+    assert_eq!(replayed_conversations, conversations);
+    assert_eq!(replayed.user_tweets("bob"), recorded.user_tweets("bob"));
+    assert_eq!(bob_requests(), requests);
+}
+
+#[tokio::test]
+async fn replaying_fails_on_requests_that_were_not_recorded() {
+    mock();
+    let directory = tempfile::tempdir().expect("Failed to create a directory for the cassette");
+    let path = directory.path().join("empty.jsonl");
+    std::fs::write(&path, "").expect("Failed to write the cassette");
+    let (_directory, archive) = archive();
+    let panic = AssertUnwindSafe(cassette::with(
+        Cassette::replay(&path),
+        app::load_tweet_from_id(&archive, 101),
+    ))
+    .catch_unwind()
+    .await
+    .expect_err("Replaying an unrecorded request fails");
+    let message = panic.downcast::<String>().expect("The panic has a message");
+    assert!(message.contains("is not in the cassette"), "{message}");
+}
+
+#[tokio::test]
+async fn rotates_bearer_tokens_on_rate_limits() {
+    mock();
+    let (_directory, archive) = archive();
+    let pool = Arc::new(Pool::new(vec![
+        SPENT_BEARER_TOKEN.to_string(),
+        BEARER_TOKEN.to_string(),
+    ]));
+    let conversations = tokens::with(
+        pool.clone(),
+        app::load_conversations_from_twitter_handle(&archive, "heidi"),
+    )
+    .await;
+    assert_eq!(conversations.len(), 2);
+    let usage = pool.usage();
+    assert_eq!(
+        usage
+            .iter()
+            .map(|usage| usage.token.as_str())
+            .collect::<Vec<_>>(),
+        ["…0000", "…oken"]
+    );
+    //the spent token was tried once per endpoint, every request after went to the other one
+    let (spent, other) = (&usage[0], &usage[1]);
+    assert!(spent.requests > 0);
+    assert_eq!(spent.rate_limited, spent.requests);
+    for (endpoint, budget) in &spent.endpoints {
+        assert_eq!(budget.requests, 1, "{endpoint}");
+        assert_eq!(budget.remaining, Some(0), "{endpoint}");
+        assert!(other.endpoints.contains_key(endpoint), "{endpoint}");
+    }
+    assert!(spent.endpoints.contains_key("users/by/username/:username"));
+    assert!(spent.endpoints.contains_key("users/:id/tweets"));
+    assert_eq!(other.rate_limited, 0);
+    assert!(other.requests >= spent.requests);
+    assert!(other
+        .endpoints
+        .values()
+        .all(|budget| budget.limit == Some(900)));
+}
+
+#[tokio::test]
+async fn reads_bearer_tokens_from_the_config_and_a_secrets_file() {
+    //sets TWITTER_DEV_BEARER_TOKEN
+    mock();
+    let directory = tempfile::tempdir().expect("Failed to create a directory for the secrets");
+    let path = directory.path().join("bearer_tokens.txt");
+    std::fs::write(
+        &path,
+        "# the spare ones\nspare-bearer-token-2222\n\n  spare-bearer-token-3333\nspare-bearer-token-1111\n",
+    )
+    .expect("Failed to write the secrets");
+    let pool = Pool::from_config(&ApiConfig {
+        bearer_tokens: vec!["spare-bearer-token-1111".to_string()],
+        bearer_tokens_file: Some(path),
+        ..ApiConfig::default()
+    });
+    assert_eq!(
+        pool.usage()
+            .iter()
+            .map(|usage| usage.token.as_str())
+            .collect::<Vec<_>>(),
+        ["…oken", "…1111", "…2222", "…3333"]
+    );
+    assert_eq!(tokens::endpoint("users/12/tweets"), "users/:id/tweets");
+    assert_eq!(
+        tokens::endpoint("users/by/username/12"),
+        "users/by/username/:username"
+    );
+    assert_eq!(tokens::endpoint("tweets"), "tweets");
+}
+
+// The server's routes, booted with `rocket_from` against a copy of the archives in
+// "tests/fixtures/archive": alice and her conversations in the default archive, bob in the one
+// called "other". Grace is left out of both for the routes to load her from the mock.
+
+fn copy_directory(from: &Path, to: &Path) {
+    std::fs::create_dir_all(to).expect("Failed to create a directory");
+    for entry in std::fs::read_dir(from).expect("Failed to read a fixture directory") {
+        let entry = entry.expect("Failed to read a fixture");
+        let path = entry.path();
+        match path.is_dir() {
+            true => copy_directory(&path, &to.join(entry.file_name())),
+            false => {
+                std::fs::copy(&path, to.join(entry.file_name())).expect("Failed to copy a fixture");
+            }
+        }
+    }
+}
+
+const WEBHOOK_SECRET: &str = "webhook-secret";
+
+// Receivers on the mock for nothing but pings, so the other tests' events don't reach them: one
+// that checks signatures, one the fixtures fail twice before it answers and one that's down.
+fn test_webhooks() -> serde_json::Value {
+    json!([
+        {"url": mock_url("/hooks/signed"), "secret": WEBHOOK_SECRET, "events": ["ping"]},
+        {"url": mock_url("/hooks/flaky"), "events": ["ping"]},
+        {"url": mock_url("/hooks/down"), "events": ["ping"]},
+    ])
+}
+
+// Archives are configured once per process, so every client serves the same copy of the
+// fixtures, kept until the tests are done.
+async fn client() -> Client {
+    static DATA: OnceLock<TempDir> = OnceLock::new();
+    mock();
+    let data = DATA.get_or_init(|| {
+        let directory = tempfile::tempdir().expect("Failed to create a directory for the archives");
+        copy_directory(Path::new("tests/fixtures/archive"), directory.path());
+        directory
+    });
+    let figment = rocket::Config::figment()
+        .merge(("data_dir", data.path()))
+        .merge(("archives", HashMap::from([("other", "other")])))
+        .merge(("activitypub_private_addresses", true))
+        .merge(("webhooks", test_webhooks()))
+        .merge(("webhook_attempts", 3))
+        .merge(("webhook_backoff", 0))
+        .merge(("log_level", "off"));
+    Client::tracked(crate::rocket_from(figment))
+        .await
+        .expect("Failed to start the server")
+}
+
+async fn get(client: &Client, uri: &str) -> (Status, Option<ContentType>, String) {
+    let response = client.get(uri).dispatch().await;
+    let status = response.status();
+    let content_type = response.content_type();
+    (
+        status,
+        content_type,
+        response.into_string().await.unwrap_or_default(),
+    )
+}
+
+async fn post(client: &Client, uri: &str, body: &str) -> (Status, Option<ContentType>, String) {
+    let response = client.post(uri).body(body).dispatch().await;
+    let status = response.status();
+    let content_type = response.content_type();
+    (
+        status,
+        content_type,
+        response.into_string().await.unwrap_or_default(),
+    )
+}
+
+//the ids of the tweets in a RON body, in the order they appear, leaving out referenced tweets
+fn ids_in(body: &str) -> Vec<u64> {
+    body.split("id: \"")
+        .skip(1)
+        .filter(|rest| {
+            rest.split_once('\n')
+                .is_some_and(|(_, next)| next.trim_start().starts_with("text:"))
+        })
+        .filter_map(|rest| rest.split('"').next()?.parse().ok())
+        .collect()
+}
+
+#[tokio::test]
+async fn the_index_lists_every_route() {
+    let client = client().await;
+    let (status, content_type, index) = get(&client, "/").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(content_type, Some(ContentType::Plain));
+    for route in client.rocket().routes() {
+        let path = route.uri.path();
+        if path == "/" {
+            continue;
+        }
+        let method = route.method.as_str().to_lowercase();
+        assert!(
+            index.contains(&format!("#[{method}(\"{path}\")]"))
+                || index.contains(&format!("#[{method}(\"{path}?")),
+            "{method} {path} is missing from the index"
+        );
+    }
+}
+
+#[tokio::test]
+async fn serves_archived_tweets_users_and_conversations() {
+    let client = client().await;
+    let (status, content_type, tweet) = get(&client, "/tweet/101").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(content_type, Some(ContentType::Plain));
+    assert!(tweet.contains("text: \"Hello\""), "{tweet}");
+
+    let (status, _, conversation) = get(&client, "/conversation/103").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(ids_in(&conversation), [103, 102, 200]);
+    let (status, _, tweet) = get(&client, "/conversation/103/102").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(ids_in(&tweet), [102]);
+    let (status, _, _) = get(&client, "/conversation/103/101").await;
+    assert_eq!(status, Status::NotFound);
+
+    let (status, content_type, user) = get(&client, "/user/alice").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(content_type, Some(ContentType::Plain));
+    assert!(user.contains("username: \"alice\""), "{user}");
+    assert_eq!(get(&client, "/user/alice/info").await.2, user);
+    assert_eq!(get(&client, "/userid/1").await.2, user);
+
+    let (status, _, tweets) = get(&client, "/user/alice/tweets").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(ids_in(&tweets), [104, 103, 102, 101]);
+    let (status, _, conversations) = get(&client, "/user/alice/conversations").await;
+    assert_eq!(status, Status::Ok);
+    assert!(ids_in(&conversations).starts_with(&[104]));
+    assert!(conversations.contains("Or a collider?"));
+}
+
+#[tokio::test]
+async fn loads_what_the_archive_misses_from_twitter() {
+    let mock = mock();
+    let client = client().await;
+    let (status, _, user) = get(&client, "/user/grace").await;
+    assert_eq!(status, Status::Ok);
+    assert!(user.contains("username: \"grace\""), "{user}");
+    assert_eq!(requests_to(mock, "/2/users/by/username/grace"), 1);
+    //stored, so asking again or by id doesn't go to twitter
+    assert_eq!(get(&client, "/user/grace/info").await.2, user);
+    assert_eq!(get(&client, "/userid/7").await.2, user);
+    assert_eq!(requests_to(mock, "/2/users/by/username/grace"), 1);
+    assert_eq!(requests_to(mock, "/2/users/7"), 0);
+
+    let (status, _, tweet) = get(&client, "/tweet/701").await;
+    assert_eq!(status, Status::Ok);
+    assert!(tweet.contains("Good morning"), "{tweet}");
+    assert_eq!(requests_to(mock, "/2/tweets/701"), 1);
+    let (status, _, tweets) = get(&client, "/user/grace/tweets").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(ids_in(&tweets), [702, 701]);
+    assert_eq!(requests_to(mock, "/2/users/7/tweets"), 1);
+    let (status, _, tweet) = get(&client, "/conversation/702/701").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(ids_in(&tweet), [701]);
+    get(&client, "/tweet/701").await;
+    assert_eq!(requests_to(mock, "/2/tweets/701"), 1);
+}
+
+#[tokio::test]
+async fn serves_stats_feeds_exports_and_graphs() {
+    let client = client().await;
+    let (status, content_type, stats) = get(&client, "/user/alice/stats").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(content_type, Some(ContentType::JSON));
+    let stats: serde_json::Value = serde_json::from_str(&stats).expect("The stats are JSON");
+    assert_eq!(stats["twitter_handle"], "alice");
+    assert_eq!(stats["tweets"], 4);
+    let (status, content_type, dashboard) = get(&client, "/user/alice/stats/dashboard").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(content_type, Some(ContentType::HTML));
+    assert!(dashboard.contains("alice"));
+    assert_eq!(get(&client, "/user/nobody/stats").await.0, Status::NotFound);
+    assert_eq!(
+        get(&client, "/user/nobody/stats/dashboard").await.0,
+        Status::NotFound
+    );
+
+    let (status, content_type, feed) = get(&client, "/user/alice/feed.atom").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(
+        content_type,
+        Some(ContentType::new("application", "atom+xml"))
+    );
+    assert!(
+        feed.contains("<feed") && feed.contains("Worth reading"),
+        "{feed}"
+    );
+    let (status, content_type, feed) = get(&client, "/user/alice/feed.rss").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(
+        content_type,
+        Some(ContentType::new("application", "rss+xml"))
+    );
+    assert!(
+        feed.contains("<rss") && feed.contains("Worth reading"),
+        "{feed}"
+    );
+    assert_eq!(
+        get(&client, "/user/nobody/feed.atom").await.0,
+        Status::NotFound
+    );
+    assert_eq!(
+        get(&client, "/user/nobody/feed.rss").await.0,
+        Status::NotFound
+    );
+    let (status, content_type, feed) = get(&client, "/search/feed.atom?query=collider").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(
+        content_type,
+        Some(ContentType::new("application", "atom+xml"))
+    );
+    assert!(feed.contains("Or a collider?"), "{feed}");
+    assert_eq!(
+        get(&client, "/search/feed.atom?query=(collider").await.0,
+        Status::BadRequest
+    );
+
+    let (status, content_type, csv) = get(&client, "/export/alice.csv").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(content_type, Some(ContentType::CSV));
+    //a header and a line per tweet
+    assert_eq!(csv.lines().count(), 5);
+    let (status, content_type, users) = get(&client, "/export/users/all.jsonl").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(
+        content_type,
+        Some(ContentType::new("application", "x-ndjson"))
+    );
+    //grace may be archived by now too
+    assert!(
+        users.lines().any(|user| user.contains("\"alice\"")),
+        "{users}"
+    );
+    assert_eq!(get(&client, "/export/nobody.csv").await.0, Status::NotFound);
+    assert_eq!(get(&client, "/export/alice.pdf").await.0, Status::NotFound);
+    assert_eq!(
+        get(&client, "/export/users/alice.csv").await.0,
+        Status::NotFound
+    );
+    //an account called "users" has its tweets exported like any other
+    let other = archive::get("other").expect("The other archive is configured");
+    other.insert_user(&user(11, "Users", "users"));
+    other.insert_tweets(&[tweet(1101, 11, "Not a table")]);
+    let (status, _, csv) = get(&client, "/export/users.csv?archive=other").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(csv.lines().count(), 2, "{csv}");
+    assert!(csv.contains("Not a table"), "{csv}");
+
+    let (status, content_type, graph) = get(&client, "/graph").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(content_type, Some(ContentType::Plain));
+    //alice replied to and quoted bob, who isn't an archived user
+    assert!(graph.contains("label: \"alice\""), "{graph}");
+    assert!(
+        graph.contains("kind: reply") && graph.contains("kind: quote"),
+        "{graph}"
+    );
+    let (status, content_type, graph) = get(&client, "/graph?format=gexf&kinds=reply").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(content_type, Some(ContentType::XML));
+    assert!(graph.contains("<gexf"), "{graph}");
+    assert_eq!(
+        get(&client, "/graph?format=png").await.0,
+        Status::BadRequest
+    );
+    assert_eq!(
+        get(&client, "/graph?kinds=like").await.0,
+        Status::BadRequest
+    );
+}
+
+#[tokio::test]
+async fn searches_the_archive() {
+    let client = client().await;
+    let (status, content_type, results) = get(&client, "/search?query=collider").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(content_type, Some(ContentType::Plain));
+    assert_eq!(ids_in(&results), [103]);
+    let (_, _, results) = get(&client, "/search?query=colider&mode=fuzzy").await;
+    assert_eq!(ids_in(&results), [103]);
+    let (_, _, results) = get(&client, "/search?query=con.ounder&mode=regex").await;
+    assert_eq!(ids_in(&results), [102]);
+    let (status, _, results) = get(&client, "/search?query=collider&group=conversation").await;
+    assert_eq!(status, Status::Ok);
+    assert!(ids_in(&results).contains(&200), "{results}");
+    let (_, _, results) = get(&client, "/search?query=from:alice%20-is:reply").await;
+    assert_eq!(ids_in(&results), [104, 101]);
+
+    for uri in [
+        "/search?query=collider&mode=telepathy",
+        "/search?query=collider&group=author",
+        "/search?query=(collider",
+        "/search?query=(&mode=regex",
+    ] {
+        assert_eq!(get(&client, uri).await.0, Status::BadRequest, "{uri}");
+    }
+}
+
+#[tokio::test]
+async fn selects_archives_by_name() {
+    let client = client().await;
+    assert_eq!(
+        get(&client, "/user/bob/stats?archive=other").await.0,
+        Status::Ok
+    );
+    assert_eq!(get(&client, "/user/bob/stats").await.0, Status::NotFound);
+    //the dashboard's links stay in the archive it shows
+    let (_, _, dashboard) = get(&client, "/user/bob/stats/dashboard?archive=other").await;
+    assert!(
+        dashboard.contains("/user/bob/stats?archive=other\">JSON</a>"),
+        "{dashboard}"
+    );
+    assert!(
+        dashboard.contains("/conversation/200?archive=other\">200</a>"),
+        "{dashboard}"
+    );
+    assert_eq!(
+        get(&client, "/user/alice/stats?archive=other").await.0,
+        Status::NotFound
+    );
+    let (status, _, tweet) = get(&client, "/tweet/200?archive=other").await;
+    assert_eq!(status, Status::Ok);
+    assert!(tweet.contains("Does smoking cause cancer?"), "{tweet}");
+    assert_eq!(
+        get(&client, "/tweet/200?archive=missing").await.0,
+        Status::NotFound
+    );
+}
+
+// Job workers start with every client and run the jobs of any test, so only what's queued is
+// looked at here.
+#[tokio::test]
+async fn queues_jobs() {
+    let client = client().await;
+    let (status, content_type, job) =
+        post(&client, "/jobs?archive=other", r#"{"tweet": 999}"#).await;
+    assert_eq!(status, Status::Accepted);
+    assert_eq!(content_type, Some(ContentType::JSON));
+    let job: serde_json::Value = serde_json::from_str(&job).expect("The job is JSON");
+    assert_eq!(job["archive"], "other");
+    assert_eq!(job["target"], json!({"tweet": 999}));
+    let id = job["id"].as_u64().expect("The job has an id");
+
+    let (status, content_type, queued) = get(&client, &format!("/jobs/{id}")).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(content_type, Some(ContentType::JSON));
+    let queued: serde_json::Value = serde_json::from_str(&queued).expect("The job is JSON");
+    assert_eq!(queued["id"], id);
+    let (status, content_type, page) = get(&client, &format!("/jobs/{id}/watch")).await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(content_type, Some(ContentType::HTML));
+    assert!(page.contains(&format!("/progress?job={id}")), "{page}");
+
+    assert_eq!(
+        post(&client, "/jobs", "{\"video\": 1}").await.0,
+        Status::BadRequest
+    );
+    assert_eq!(
+        post(&client, "/jobs", "not json").await.0,
+        Status::BadRequest
+    );
+    assert_eq!(get(&client, "/jobs/0").await.0, Status::NotFound);
+    assert_eq!(get(&client, "/jobs/0/watch").await.0, Status::NotFound);
+    assert_eq!(get(&client, "/progress?job=0").await.0, Status::NotFound);
+}
+
+#[tokio::test]
+async fn watches_and_unwatches_users() {
+    let client = client().await;
+    let (status, content_type, watched) = post(
+        &client,
+        "/watchlist?archive=other",
+        r#"{"twitter_handle": "zed", "interval": "6h"}"#,
+    )
+    .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(content_type, Some(ContentType::JSON));
+    let watched: serde_json::Value = serde_json::from_str(&watched).expect("The user is JSON");
+    assert_eq!(watched["twitter_handle"], "zed");
+    assert_eq!(watched["interval"], "6h");
+
+    let (status, _, watched) = get(&client, "/watchlist/zed?archive=other").await;
+    assert_eq!(status, Status::Ok);
+    assert!(watched.contains("\"6h\""), "{watched}");
+    let (status, content_type, watchlist) = get(&client, "/watchlist?archive=other").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(content_type, Some(ContentType::JSON));
+    assert!(watchlist.contains("\"zed\""), "{watchlist}");
+    assert_eq!(get(&client, "/watchlist/zed").await.0, Status::NotFound);
+
+    for watch in [
+        r#"{"twitter_handle": "zed", "interval": "1s"}"#,
+        r#"{"twitter_handle": "not a handle"}"#,
+        r#"{"interval": "6h"}"#,
+    ] {
+        assert_eq!(
+            post(&client, "/watchlist?archive=other", watch).await.0,
+            Status::BadRequest,
+            "{watch}"
+        );
+    }
+
+    let unwatch = || client.delete("/watchlist/zed?archive=other").dispatch();
+    assert_eq!(unwatch().await.status(), Status::NoContent);
+    assert_eq!(unwatch().await.status(), Status::NotFound);
+    assert_eq!(
+        get(&client, "/watchlist/zed?archive=other").await.0,
+        Status::NotFound
+    );
+}
+
+#[tokio::test]
+async fn serves_tombstones_and_webhook_deliveries() {
+    let client = client().await;
+    let (status, content_type, tombstones) = get(&client, "/tombstones?archive=other").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(content_type, Some(ContentType::JSON));
+    assert_eq!(tombstones, "[]");
+    let (status, content_type, deliveries) =
+        get(&client, "/webhooks/deliveries?archive=other").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(content_type, Some(ContentType::JSON));
+    assert_eq!(deliveries, "[]");
+    assert_eq!(
+        get(&client, "/webhooks/deliveries/1?archive=other").await.0,
+        Status::NotFound
+    );
+}
+
+#[tokio::test]
+async fn delivers_signed_webhooks_and_retries_failed_deliveries() {
+    let client = client().await;
+    let (status, content_type, pinged) = post(&client, "/webhooks/ping", "").await;
+    assert_eq!(status, Status::Accepted);
+    assert_eq!(content_type, Some(ContentType::JSON));
+    let pinged: Vec<serde_json::Value> =
+        serde_json::from_str(&pinged).expect("Deliveries are JSON");
+    assert_eq!(pinged.len(), 3);
+    assert!(pinged
+        .iter()
+        .all(|delivery| delivery["status"] == "pending"));
+
+    //with no backoff, the retries are over as soon as the receivers have answered them
+    let started = Instant::now();
+    let deliveries = loop {
+        let deliveries: Vec<serde_json::Value> =
+            serde_json::from_str(&get(&client, "/webhooks/deliveries").await.2)
+                .expect("Deliveries are JSON");
+        if deliveries
+            .iter()
+            .all(|delivery| delivery["status"] != "pending")
+        {
+            break deliveries;
+        }
+        assert!(
+            started.elapsed() < Duration::from_secs(10),
+            "{deliveries:?}"
+        );
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    };
+    let delivery = |path: &str| {
+        deliveries
+            .iter()
+            .find(|delivery| delivery["url"] == mock_url(path))
+            .unwrap_or_else(|| panic!("A delivery to {path}"))
+    };
+    let attempts = |path: &str| -> Vec<serde_json::Value> {
+        delivery(path)["attempts"]
+            .as_array()
+            .map(|attempts| {
+                attempts
+                    .iter()
+                    .map(|attempt| attempt["status"].clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    assert_eq!(delivery("/hooks/signed")["status"], "delivered");
+    assert_eq!(attempts("/hooks/signed"), vec![json!(200)]);
+    assert_eq!(delivery("/hooks/flaky")["status"], "delivered");
+    assert_eq!(
+        attempts("/hooks/flaky"),
+        vec![json!(429), json!(429), json!(200)]
+    );
+    assert_eq!(delivery("/hooks/down")["status"], "failed");
+    assert_eq!(attempts("/hooks/down"), vec![json!(503); 3]);
+    let id = &delivery("/hooks/down")["id"];
+    let (status, _, failed) = get(&client, &format!("/webhooks/deliveries/{id}")).await;
+    assert_eq!(status, Status::Ok);
+    assert!(failed.contains("\"failed\""), "{failed}");
+
+    //only the receiver with a secret gets a signature, of the body it got
+    let mock = mock();
+    let [signed]: [mock::Post; 1] = mock.posts("/hooks/signed").try_into().expect("One ping");
+    assert_eq!(
+        signed.headers.get("x-archiver-signature"),
+        Some(&webhooks::signature(WEBHOOK_SECRET, &signed.body))
+    );
+    assert_eq!(
+        signed.headers.get("x-archiver-event").map(String::as_str),
+        Some("ping")
+    );
+    let ping: serde_json::Value = serde_json::from_str(&signed.body).expect("The ping is JSON");
+    assert_eq!(ping["event"], "ping");
+    assert_eq!(ping["archive"], "default");
+    assert_eq!(ping["delivery"], delivery("/hooks/signed")["id"]);
+    let flaky = mock.posts("/hooks/flaky");
+    assert_eq!(flaky.len(), 3);
+    assert!(flaky
+        .iter()
+        .all(|post| !post.headers.contains_key("x-archiver-signature")));
+}
+
+#[test]
+fn backs_off_exponentially_without_overflowing() {
+    use crate::app::webhooks::backoff;
+    assert_eq!(backoff(10, 1), Duration::from_secs(10));
+    assert_eq!(backoff(10, 4), Duration::from_secs(80));
+    assert_eq!(backoff(0, 100), Duration::ZERO);
+    //a large backoff or many attempts wait a day at most, instead of overflowing
+    assert_eq!(backoff(10, 65), Duration::from_secs(24 * 60 * 60));
+    assert_eq!(backoff(u64::MAX, 2), Duration::from_secs(24 * 60 * 60));
+}
+
+#[tokio::test]
+async fn reports_bearer_token_usage() {
+    let client = client().await;
+    let (status, content_type, usage) = get(&client, "/twitter/usage").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(content_type, Some(ContentType::JSON));
+    assert!(!usage.contains(BEARER_TOKEN), "{usage}");
+    let usage: serde_json::Value = serde_json::from_str(&usage).expect("The usage is JSON");
+    assert_eq!(usage.as_array().map(Vec::len), Some(1));
+    assert_eq!(usage[0]["token"], "…oken");
+}
+
+#[tokio::test]
+async fn serves_archived_users_as_activitypub_actors() {
+    let client = client().await;
+    let activity_json = Some(ContentType::new("application", "activity+json"));
+    let (status, content_type, webfinger) = get(
+        &client,
+        "/.well-known/webfinger?resource=acct:alice@localhost:8000",
+    )
+    .await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(
+        content_type,
+        Some(ContentType::new("application", "jrd+json"))
+    );
+    assert!(
+        webfinger.contains("\"acct:alice@localhost:8000\""),
+        "{webfinger}"
+    );
+    assert_eq!(
+        get(
+            &client,
+            "/.well-known/webfinger?resource=acct:alice@elsewhere.social"
+        )
+        .await
+        .0,
+        Status::NotFound
+    );
+
+    let (status, content_type, actor) = get(&client, "/users/alice").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(content_type, activity_json);
+    let actor: serde_json::Value = serde_json::from_str(&actor).expect("The actor is JSON");
+    assert_eq!(actor["type"], "Person");
+    assert_eq!(actor["preferredUsername"], "alice");
+    let (status, content_type, outbox) = get(&client, "/users/alice/outbox").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(content_type, activity_json);
+    let outbox: serde_json::Value = serde_json::from_str(&outbox).expect("The outbox is JSON");
+    assert_eq!(outbox["totalItems"], 4);
+    assert_eq!(
+        get(&client, "/users/alice/outbox?page=99").await.0,
+        Status::NotFound
+    );
+    let (status, content_type, _) = get(&client, "/users/alice/followers").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(content_type, activity_json);
+    let (status, content_type, note) = get(&client, "/users/alice/statuses/104").await;
+    assert_eq!(status, Status::Ok);
+    assert_eq!(content_type, activity_json);
+    assert!(note.contains("Worth reading"), "{note}");
+    //bob's tweet, archived in alice's conversation
+    assert_eq!(
+        get(&client, "/users/alice/statuses/200").await.0,
+        Status::NotFound
+    );
+    for uri in [
+        "/users/nobody",
+        "/users/nobody/outbox",
+        "/users/nobody/followers",
+    ] {
+        assert_eq!(get(&client, uri).await.0, Status::NotFound, "{uri}");
+    }
+
+    assert_eq!(
+        post(&client, "/users/nobody/inbox", "{}").await.0,
+        Status::NotFound
+    );
+    assert_eq!(
+        post(&client, "/users/alice/inbox", "{}").await.0,
+        Status::Unauthorized
+    );
+    let signed = client
+        .post("/users/alice/inbox")
+        .header(Header::new(
+            "Signature",
+            "keyId=\"https://social.example/users/zoe\"",
+        ))
+        .body("not json")
+        .dispatch()
+        .await;
+    assert_eq!(signed.status(), Status::BadRequest);
+}
+
+// Pure logic over archived tweets, without the mock.
+
+#[test]
+fn migrates_version_1_archives_once() {
+    use crate::app::io::layout::Layout;
+    use crate::app::records::{self, TweetRecord, UserRecord};
+    let fixture = Path::new("tests/fixtures/archive-v1");
+    let directory = tempfile::tempdir().expect("Failed to create a directory for the archive");
+    copy_directory(fixture, directory.path());
+    let layout = Layout::new(directory.path());
+    let files = [
+        "tweets.ron",
+        "users.ron",
+        "conversations.ron",
+        "user-info_alice.ron",
+        "user-tweets_alice.ron",
+        "user-conversations_alice.ron",
+    ];
+    let read = |path: &Path| std::fs::read_to_string(path).expect("Failed to read a file");
+    let read_all = |root: &Path| files.map(|file| read(&root.join(file)));
+    let backups = || -> Vec<std::path::PathBuf> {
+        std::fs::read_dir(directory.path().join("backups"))
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| Some(entry.ok()?.path()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    assert_eq!(migrations::archive_version(&layout), 1);
+
+    let migrated = migrations::try_migrate(&layout).expect("A version 1 archive migrates");
+    assert_eq!(
+        migrations::manifest(&migrated).map(|manifest| manifest.version),
+        Some(migrations::CURRENT_VERSION)
+    );
+    //the backup has every file as it was
+    let [backup]: [std::path::PathBuf; 1] = backups().try_into().expect("One backup");
+    assert!(backup
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with("v1-")));
+    assert_eq!(read_all(&backup), read_all(fixture));
+    //and every file was rewritten as records of the same tweets and users
+    let v1_tweets = |file: &str| -> Vec<Tweet> {
+        ron::from_str(&read(&fixture.join(file))).expect("A version 1 file")
+    };
+    let v1_conversations = |file: &str| -> Vec<Vec<Tweet>> {
+        ron::from_str(&read(&fixture.join(file))).expect("A version 1 file")
+    };
+    let tweets = |file: &str| {
+        records::tweets_from_records(
+            ron::from_str::<Vec<TweetRecord>>(&read(&layout.root().join(file)))
+                .expect("A file of tweet records"),
+        )
+    };
+    let conversations = |file: &str| {
+        records::conversations_from_records(
+            ron::from_str::<Vec<Vec<TweetRecord>>>(&read(&layout.root().join(file)))
+                .expect("A file of conversation records"),
+        )
+    };
+    assert_eq!(tweets("tweets.ron"), v1_tweets("tweets.ron"));
+    assert_eq!(
+        tweets("user-tweets_alice.ron"),
+        v1_tweets("user-tweets_alice.ron")
+    );
+    assert_eq!(
+        conversations("conversations.ron"),
+        v1_conversations("conversations.ron")
+    );
+    assert_eq!(
+        conversations("user-conversations_alice.ron"),
+        v1_conversations("user-conversations_alice.ron")
+    );
+    let users: Vec<User> =
+        ron::from_str(&read(&fixture.join("users.ron"))).expect("A version 1 file");
+    assert_eq!(
+        records::users_from_records(
+            ron::from_str(&read(&layout.users())).expect("A file of user records")
+        ),
+        users
+    );
+    let alice: UserRecord =
+        ron::from_str(&read(&layout.user_info("alice"))).expect("A user record");
+    assert_eq!(User::from(alice), users[0]);
+    let archive = Archive::open("test", directory.path().to_path_buf());
+    assert_eq!(
+        archive.user_tweets("alice").map(|tweets| ids(&tweets)),
+        Some(vec![102])
+    );
+    assert_eq!(ids(&archive.tweets_in_conversation(101)), [102, 101]);
+    drop(archive);
+
+    //a second run finds the archive current and leaves it alone
+    let rewritten = read_all(layout.root());
+    let manifest = read(&layout.manifest());
+    migrations::try_migrate(&layout).expect("A current archive migrates");
+    assert_eq!(backups().len(), 1);
+    assert_eq!(read_all(layout.root()), rewritten);
+    assert_eq!(read(&layout.manifest()), manifest);
+}
+
+#[tokio::test]
+async fn checks_archives_too_broken_to_migrate() {
+    use crate::app::check::{self, Problem, RepairOptions};
+    use crate::app::io::layout::Layout;
+    //a version 1 archive, from before the manifest, with users.ron cut off
+    let directory = tempfile::tempdir().expect("Failed to create a directory for the archive");
+    let tweets = ron::to_string(&vec![tweet(101, 1, "Hello")]).expect("Failed to write tweets");
+    std::fs::write(directory.path().join("tweets.ron"), &tweets).expect("Failed to write tweets");
+    std::fs::write(directory.path().join("users.ron"), "[(id: \"1\", na")
+        .expect("Failed to write users");
+    let layout = Layout::new(directory.path().to_path_buf());
+
+    let problems = check::check_archive(&layout);
+    assert!(
+        matches!(
+            problems.as_slice(),
+            [Problem::UnparsableFile { file, .. }] if *file == layout.users()
+        ),
+        "{problems:?}"
+    );
+    //and nothing was migrated
+    assert_eq!(
+        std::fs::read_to_string(layout.tweets()).ok().as_ref(),
+        Some(&tweets)
+    );
+    assert!(!layout.manifest().exists());
+
+    //repairing moves it aside and migrates the rest
+    let problems = check::repair_archive(&layout, RepairOptions::default()).await;
+    assert_eq!(
+        problems,
+        [Problem::AuthorMissing {
+            tweet_id: 101,
+            author_id: 1
+        }]
+    );
+    assert!(directory.path().join("users.ron.corrupt").exists());
+    let archive = Archive::open("test", directory.path().to_path_buf());
+    assert_eq!(
+        archive.tweet(101).map(|tweet| tweet.text),
+        Some("Hello".to_string())
+    );
+}
+
+//writes the media files, named after their media keys
+fn media_files(layout: &app::io::layout::Layout, media_keys: &[&str]) {
+    for media_key in media_keys {
+        app::io::write::media_to_file(layout, &format!("{media_key}.jpg"), b"jpeg");
+    }
+}
+
+fn media_on_disk(layout: &app::io::layout::Layout) -> Vec<String> {
+    let mut media: Vec<String> = app::io::read::media_files(layout)
+        .iter()
+        .filter_map(|file| file.file_stem()?.to_str().map(str::to_string))
+        .collect();
+    media.sort();
+    media
+}
+
+#[tokio::test]
+async fn repairs_duplicates_and_strays_keeping_the_media_strays_use() {
+    use crate::app::check::{self, Problem, RepairOptions};
+    let (directory, archive) = archive();
+    let layout = archive.layout.clone();
+    drop(archive);
+    let kept = with_media(tweet(101, 1, "Sunrise"), "3_101");
+    let stray = with_media(reply(300, 1, 101, 101, "And sunset"), "3_300");
+    app::io::write::tweets_to_ron(&layout, &[kept.clone(), kept.clone()]);
+    app::io::write::users_to_ron(
+        &layout,
+        &[user(1, "Alice", "alice"), user(1, "Alice", "alice")],
+    );
+    app::io::write::conversations_to_ron(&layout, &[vec![stray.clone(), kept.clone()], vec![]]);
+    media_files(&layout, &["3_101", "3_300", "3_999"]);
+
+    let problems = check::check(&layout);
+    assert_eq!(
+        problems,
+        [
+            Problem::DuplicateTweet {
+                file: layout.tweets(),
+                tweet_id: 101
+            },
+            Problem::DuplicateUser {
+                file: layout.users(),
+                user_id: 1
+            },
+            Problem::ConversationTweetMissing {
+                last_tweet_id: 101,
+                tweet_id: 300
+            },
+            Problem::EmptyConversation {
+                file: layout.conversations(),
+                index: 1
+            },
+            Problem::OrphanedMedia {
+                file: layout.media().join("3_999.jpg")
+            },
+        ]
+    );
+
+    assert!(check::repair(&layout, RepairOptions::default())
+        .await
+        .is_empty());
+    assert_eq!(media_on_disk(&layout), ["3_101", "3_300"]);
+    let archive = Archive::open("test", directory.path().to_path_buf());
+    assert_eq!(ids(&archive.tweets()), [101, 300]);
+    assert_eq!(ids(&archive.tweets_in_conversation(101)), [101, 300]);
+}
+
+#[tokio::test]
+async fn repairs_unparsable_tweets_without_pruning_media() {
+    use crate::app::check::{self, Problem, RepairOptions};
+    let (directory, archive) = archive();
+    let layout = archive.layout.clone();
+    drop(archive);
+    //3_555 belonged to a tweet only tweets.ron had
+    app::io::write::users_to_ron(&layout, &[user(1, "Alice", "alice")]);
+    app::io::write::user_tweets_to_ron(
+        &layout,
+        &[with_media(tweet(101, 1, "Sunrise"), "3_101")],
+        "alice",
+    );
+    app::io::write::string_to_file("[(id: \"10", &layout.tweets());
+    media_files(&layout, &["3_101", "3_555"]);
+
+    let problems = check::check(&layout);
+    assert!(
+        matches!(
+            problems.as_slice(),
+            [Problem::UnparsableFile { file, .. }, Problem::UserTweetMissing { tweet_id: 101, .. }]
+                if *file == layout.tweets()
+        ),
+        "{problems:?}"
+    );
+
+    //the user's tweets are all that's left, and nothing is pruned while tweets.ron is aside
+    assert!(check::repair(&layout, RepairOptions::default())
+        .await
+        .is_empty());
+    assert!(directory.path().join("tweets.ron.corrupt").exists());
+    assert_eq!(media_on_disk(&layout), ["3_101", "3_555"]);
+    assert!(check::repair(&layout, RepairOptions::default())
+        .await
+        .is_empty());
+    assert_eq!(media_on_disk(&layout), ["3_101", "3_555"]);
+    let archive = Archive::open("test", directory.path().to_path_buf());
+    assert_eq!(ids(&archive.tweets()), [101]);
+}
+
+#[test]
+fn forgets_the_handle_a_user_renamed_away_from() {
+    let (directory, archive) = archive();
+    archive.insert_user_info(&user(1, "Alice", "alice"), "alice");
+    archive.update_user_info(&user(1, "Alice", "alice_2"), "alice_2");
+    let id = |archive: &Archive, twitter_handle: &str| {
+        archive
+            .user_from_twitter_handle(twitter_handle)
+            .map(|user| user.id.as_u64())
+    };
+    assert_eq!(id(&archive, "alice"), None);
+    assert_eq!(id(&archive, "Alice_2"), Some(1));
+    let reopened = Archive::open("test", directory.path().to_path_buf());
+    assert_eq!(id(&reopened, "alice"), None);
+    assert_eq!(id(&reopened, "alice_2"), Some(1));
+}
+
+#[test]
+fn titles_feed_entries_with_the_first_line_cut_at_80_characters() {
+    use crate::app::feed::{self, FeedFormat};
+    let (_directory, archive) = archive();
+    let long_line = "é".repeat(90);
+    archive.insert_tweets(&[
+        tweet(1001, 1, "Is smoking a confounder?\nOr a collider?"),
+        tweet(1002, 1, &long_line),
+        tweet(1003, 1, &"é".repeat(60)),
+    ]);
+    archive.insert_user(&user(1, "Alice", "alice"));
+    let site = Site {
+        public_url: "https://archive.example".to_string(),
+    };
+    let feed = feed::user_feed(&archive, "alice", FeedFormat::Atom, &site).expect("A feed");
+    assert!(
+        feed.contains("<title>@alice: Is smoking a confounder?</title>"),
+        "{feed}"
+    );
+    let cut = format!("<title>@alice: {}…</title>", "é".repeat(80));
+    assert!(feed.contains(&cut), "{feed}");
+    let whole = format!("<title>@alice: {}</title>", "é".repeat(60));
+    assert!(feed.contains(&whole), "{feed}");
+}
+
+#[test]
+fn signs_webhooks_with_hmac_sha256() {
+    use crate::app::webhooks::signature;
+    //RFC 4231's second test case
+    assert_eq!(
+        signature("Jefe", "what do ya want for nothing?"),
+        "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+    //a key longer than a block is hashed first
+    assert_eq!(
+        signature(&"webhook-secret-".repeat(6), r#"{"type":"ping"}"#),
+        "sha256=f457c865d342204d0536fa6a97a6859982774d84f2d780f9b04ebd3bc164f4e5"
+    );
+    assert_eq!(
+        signature("", ""),
+        "sha256=b613679a0814d9ec772f95d778c35fc5ff1697c493715653c6c712144292c5ad"
+    );
+}
+
+//bob's question with alice's two replies, stored the way a loaded conversation is: newest first
+fn thread() -> (TempDir, Archive) {
+    let (directory, archive) = archive();
+    let conversation = [
+        reply(103, 1, 102, 200, "Or a collider?"),
+        reply(102, 1, 200, 200, "@bob Is it a confounder?"),
+        tweet(200, 2, "Does smoking cause cancer?"),
+    ];
+    archive.insert_tweets(&conversation);
+    archive.insert_conversation(&conversation);
+    (directory, archive)
+}
+
+#[test]
+fn parses_twitter_search_operators() {
+    use crate::app::search::index::tokenize;
+    use crate::app::search::query::{parse, Kind, Query};
+    let term = |word: &str| Query::Term {
+        text: word.to_string(),
+        tokens: tokenize(word),
+    };
+    assert_eq!(
+        parse(
+            r#"from:@yudapearl (causal OR causality) -"Machine Learning" since:2021-01-31 is:reply"#
+        ),
+        Ok(Query::And(vec![
+            Query::From("yudapearl".to_string()),
+            Query::Or(vec![term("causal"), term("causality")]),
+            Query::Not(Box::new(Query::Phrase("machine learning".to_string()))),
+            Query::Since(
+                time::Date::from_calendar_date(2021, time::Month::January, 31)
+                    .expect("A valid date")
+            ),
+            Query::Is(Kind::Reply),
+        ]))
+    );
+    //OR binds looser than AND, and NOT the same as a leading '-'
+    assert_eq!(
+        parse("a OR b AND c"),
+        Ok(Query::Or(vec![
+            term("a"),
+            Query::And(vec![term("b"), term("c")])
+        ]))
+    );
+    assert_eq!(parse("NOT a"), parse("-a"));
+    //a colon that isn't an operator is part of the term, and so is a lone '-'
+    assert_eq!(
+        parse("https://example.com"),
+        Ok(term("https://example.com"))
+    );
+    assert_eq!(
+        parse("a - b"),
+        Ok(Query::And(vec![term("a"), term("-"), term("b")]))
+    );
+
+    let error = |query: &str| {
+        let error = parse(query).expect_err("An invalid query");
+        (error.message, error.position)
+    };
+    assert_eq!(error(r#"a "open"#), ("Unclosed quote".to_string(), 2));
+    assert_eq!(error("(a"), ("Expected ')'".to_string(), 2));
+    assert_eq!(error("a)"), ("Unexpected ')'".to_string(), 1));
+    assert_eq!(
+        error("OR a"),
+        ("Expected a term before AND/OR".to_string(), 0)
+    );
+    assert_eq!(error("a AND"), ("Expected a term".to_string(), 5));
+    assert_eq!(
+        error("since:2021-13-01"),
+        (
+            "\"2021-13-01\" is not a date like 2021-01-31".to_string(),
+            6
+        )
+    );
+    assert_eq!(
+        error("is:poll"),
+        ("Unknown is: filter \"poll\"".to_string(), 3)
+    );
+}
+
+#[test]
+fn runs_search_operators_over_the_archive() {
+    let (_directory, archive) = thread();
+    archive.insert_user(&user(1, "Alice", "alice"));
+    archive.insert_user(&user(2, "Bob", "bob"));
+    let found = |query: &str| {
+        search::search(&archive, query, search::Mode::Query)
+            .map(|tweets| ids(&tweets))
+            .expect("A valid query")
+    };
+    assert_eq!(found("from:alice is:reply"), [103, 102]);
+    assert_eq!(found("to:bob"), [102]);
+    assert_eq!(found("conversation:200 -collider"), [200, 102]);
+    assert_eq!(found(r#""smoking cause""#), [200]);
+    assert_eq!(found("confounder OR collider"), [103, 102]);
+    assert_eq!(found("since:2022-03-05"), [200, 103, 102]);
+    assert!(found("until:2022-03-05").is_empty());
+}
+
+#[test]
+fn searches_with_typos_and_bounded_regexes() {
+    let (_directory, archive) = thread();
+    let found = |query: &str, mode| {
+        search::search(&archive, query, mode)
+            .map(|tweets| ids(&tweets))
+            .ok()
+    };
+    assert_eq!(found("confunder", search::Mode::Query), Some(vec![]));
+    assert_eq!(found("confunder", search::Mode::Fuzzy), Some(vec![102]));
+    //short words have to be spelled right even when fuzzy
+    assert_eq!(found("cas", search::Mode::Fuzzy), Some(vec![]));
+    assert_eq!(found("(?i)^or a", search::Mode::Regex), Some(vec![103]));
+    for pattern in ["(", &"a".repeat(1001), r"\w{1000}{1000}"] {
+        assert!(matches!(
+            search::search(&archive, pattern, search::Mode::Regex),
+            Err(search::SearchError::Regex(_))
+        ));
+    }
+}
+
+#[test]
+fn converts_archives_to_zstd_and_back() {
+    let (directory, archive) = thread();
+    let tweets = ids(&archive.tweets());
+    drop(archive);
+    let layout = migrations::migrate(&Layout::new(directory.path()));
+
+    let sizes = convert::convert(&layout, StorageFormat::Zstd);
+    assert!(sizes.after < sizes.before);
+    let compressed = layout.with_format(StorageFormat::Zstd);
+    assert!(compressed.tweets().is_file());
+    assert!(!layout.tweets().is_file());
+    let archive = Archive::open("test", directory.path().to_path_buf());
+    assert_eq!(ids(&archive.tweets()), tweets);
+    assert_eq!(ids(&archive.tweets_in_conversation(200)), [103, 102, 200]);
+    drop(archive);
+
+    //converting is a no-op for an archive already in that format
+    let sizes = convert::convert(&migrations::migrate(&layout), StorageFormat::Zstd);
+    assert_eq!(sizes.before, sizes.after);
+    convert::convert(&compressed, StorageFormat::Ron);
+    assert!(layout.tweets().is_file());
+    assert!(!compressed.tweets().is_file());
+    let archive = Archive::open("test", directory.path().to_path_buf());
+    assert_eq!(ids(&archive.tweets()), tweets);
+}
+
+#[test]
+fn exports_typed_parquet_partitions_and_replaces_earlier_exports() {
+    use ::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use app::export::parquet::{self, Partition};
+    use arrow_schema::{DataType, TimeUnit};
+    let (_directory, archive) = archive();
+    let undated: Tweet = serde_json::from_value(json!({"id": "1003", "text": "Who wrote this?"}))
+        .expect("Failed to make a tweet");
+    archive.insert_tweets(&[
+        at(
+            tweet(1001, 1, "Is smoking a confounder?"),
+            "2022-03-05T10:00:00Z",
+        ),
+        with_media(
+            at(
+                reply(1002, 2, 1001, 1001, "@alice It is a cause"),
+                "2022-04-01T10:00:00Z",
+            ),
+            "3_1002",
+        ),
+        undated,
+    ]);
+    archive.insert_user(&user(1, "Alice", "alice"));
+    archive.insert_user(&user(2, "Bob", "bob"));
+    let exported = tempfile::tempdir().expect("Failed to create a directory for the export");
+    let read = |file: &str| {
+        let file_path = exported.path().join(file);
+        let file = std::fs::File::open(&file_path)
+            .unwrap_or_else(|_| panic!("Failed to open \"{}\"", file_path.display()));
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+            .expect("A parquet file")
+            .build()
+            .expect("A parquet reader");
+        let batches: Vec<_> = reader.map(|batch| batch.expect("A record batch")).collect();
+        let schema = batches.first().expect("At least one batch").schema();
+        let rows: usize = batches.iter().map(|batch| batch.num_rows()).sum();
+        (schema, rows)
+    };
+    let partitions = || -> Vec<String> {
+        let mut partitions: Vec<String> = std::fs::read_dir(exported.path().join("tweets"))
+            .expect("A tweets directory")
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .collect();
+        partitions.sort();
+        partitions
+    };
+
+    let months = parquet::export(&archive, exported.path(), Partition::Month);
+    assert_eq!((months.tweets, months.partitions), (3, 3));
+    assert_eq!(
+        partitions(),
+        [
+            "month=2022-03",
+            "month=2022-04",
+            "month=__HIVE_DEFAULT_PARTITION__"
+        ]
+    );
+
+    //exporting again by author leaves none of the month partitions behind
+    let authors = parquet::export(&archive, exported.path(), Partition::Author);
+    assert_eq!(
+        (
+            authors.tweets,
+            authors.users,
+            authors.references,
+            authors.media
+        ),
+        (3, 2, 1, 1)
+    );
+    assert_eq!(
+        partitions(),
+        ["author=1", "author=2", "author=__HIVE_DEFAULT_PARTITION__"]
+    );
+    let type_of = |schema: &arrow_schema::Schema, name: &str| {
+        schema
+            .field_with_name(name)
+            .ok()
+            .map(|field| field.data_type().clone())
+    };
+    let timestamp = DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into()));
+    let mut tweet_rows = 0;
+    for partition in partitions() {
+        let (schema, rows) = read(&format!("tweets/{partition}/tweets.parquet"));
+        assert_eq!(type_of(&schema, "id"), Some(DataType::UInt64));
+        assert_eq!(type_of(&schema, "created_at"), Some(timestamp.clone()));
+        assert!(matches!(
+            type_of(&schema, "hashtags"),
+            Some(DataType::List(_))
+        ));
+        assert_eq!(schema.fields().len(), 14);
+        tweet_rows += rows;
+    }
+    assert_eq!(tweet_rows, 3);
+    let (users, rows) = read("users.parquet");
+    assert_eq!(rows, 2);
+    assert_eq!(type_of(&users, "handle"), Some(DataType::Utf8));
+    assert_eq!(type_of(&users, "verified"), Some(DataType::Boolean));
+    let (references, rows) = read("references.parquet");
+    assert_eq!(rows, 1);
+    let names: Vec<&str> = references
+        .fields()
+        .iter()
+        .map(|field| field.name().as_str())
+        .collect();
+    assert_eq!(
+        names,
+        ["tweet_id", "author_id", "kind", "referenced_tweet_id"]
+    );
+    let (media, rows) = read("media.parquet");
+    assert_eq!(rows, 1);
+    let names: Vec<&str> = media
+        .fields()
+        .iter()
+        .map(|field| field.name().as_str())
+        .collect();
+    assert_eq!(
+        names,
+        ["tweet_id", "author_id", "created_at", "media_key", "file"]
+    );
+}
+
+#[test]
+fn tokenizes_and_stems_words_but_not_hashtags_mentions_or_emoji() {
+    use crate::app::search::index::tokenize;
+    assert_eq!(
+        tokenize("Running #RustLang tests with @Bob 🎉, it loves them!"),
+        [
+            "run",
+            "#rustlang",
+            "test",
+            "with",
+            "@bob",
+            "🎉",
+            "it",
+            "love",
+            "them"
+        ]
+    );
+    //a ZWJ sequence stays one token
+    assert_eq!(tokenize("👩‍🔬"), ["👩‍🔬"]);
+}
+
+#[test]
+fn ranks_tweets_by_bm25_from_the_postings_lists() {
+    use crate::app::search::index::{tokenize, Index};
+    let tweets = [
+        tweet(1, 1, "Smoking causes cancer, smoking kills"),
+        tweet(2, 1, "Smoking is bad"),
+        tweet(3, 1, "Cancer research is good"),
+        tweet(4, 1, "Nothing to see here"),
+    ];
+    let index = Index::build(&tweets);
+    assert_eq!(index.len(), 4);
+    let score = |id: u64, query: &str| index.score(id, &tokenize(query));
+    //more often in a tweet, and in a shorter one, scores higher
+    assert!(score(1, "smoking") > 0.0);
+    assert!(score(2, "smoke") > 0.0);
+    assert_eq!(score(3, "smoking"), 0.0);
+    //so does a term fewer tweets have
+    assert!(score(3, "research") > score(3, "cancer"));
+
+    //candidates come from the postings, terms narrow them down and phrases can't
+    let candidates = |query: &str| {
+        let mut candidates: Vec<u64> = search::query::parse(query)
+            .expect("A valid query")
+            .candidates(&index)?
+            .into_iter()
+            .collect();
+        candidates.sort();
+        Some(candidates)
+    };
+    assert_eq!(candidates("smoke"), Some(vec![1, 2]));
+    assert_eq!(candidates("smoking cancer"), Some(vec![1]));
+    assert_eq!(candidates("smoking OR research"), Some(vec![1, 2, 3]));
+    assert_eq!(candidates("cancer -smoking"), Some(vec![1, 3]));
+    assert_eq!(candidates("unheard"), Some(vec![]));
+    assert_eq!(candidates("\"is bad\""), None);
+    assert_eq!(candidates("smoking OR \"is bad\""), None);
+}
+
+#[test]
+fn writes_the_search_index_once_it_has_grown_enough() {
+    let (directory, archive) = archive();
+    let index_file = archive.layout.search_index();
+    let written = std::fs::read_to_string(&index_file).expect("The empty index was written");
+    for id in 1..=3 {
+        archive.insert_tweets(&[tweet(id, 1, "Does smoking cause cancer?")]);
+    }
+    assert_eq!(
+        std::fs::read_to_string(&index_file).ok().as_ref(),
+        Some(&written)
+    );
+    let found = |archive: &Archive| {
+        search::search(archive, "smoking", search::Mode::Query)
+            .map(|tweets| ids(&tweets))
+            .expect("A valid query")
+    };
+    assert_eq!(found(&archive), [3, 2, 1]);
+    //the tweets it's missing are added and written when the archive is opened again
+    let reopened = Archive::open("test", directory.path().to_path_buf());
+    assert_eq!(found(&reopened), [3, 2, 1]);
+    assert_ne!(
+        std::fs::read_to_string(&index_file).ok().as_ref(),
+        Some(&written)
+    );
+}
+
+#[test]
+fn threads_follow_replies_back_to_the_root() {
+    let (_directory, archive) = thread();
+    let root = archive.tweet(200).expect("The root is archived");
+    let leaf = archive.tweet(103).expect("The leaf is archived");
+    let groups = search::threads::group_by_conversation(&[&root, &leaf], &archive.read());
+    assert_eq!(groups.len(), 1);
+    assert_eq!(groups[0].conversation_id, 200);
+    let matches = &groups[0].matches;
+    assert!(matches[0].ancestors.is_empty());
+    //the root first, then the tweet replied to
+    assert_eq!(ids(&matches[1].ancestors), [200, 102]);
+    assert!(matches[1].collapsed_siblings.is_empty());
+}
+
+#[tokio::test]
+async fn exports_each_branch_of_a_thread_as_a_note() {
+    let (_directory, archive) = archive();
+    let root = at(
+        tweet(200, 2, "Does smoking cause cancer?"),
+        "2022-02-28T10:00:00Z",
+    );
+    let question = at(
+        reply(102, 1, 200, 200, "@bob Is it a confounder?"),
+        "2022-03-01T11:00:00Z",
+    );
+    let follow_up = at(
+        reply(103, 1, 102, 200, "Or a collider?"),
+        "2022-03-01T11:05:00Z",
+    );
+    let conversations = vec![
+        vec![follow_up.clone(), question.clone(), root.clone()],
+        vec![question.clone(), root.clone()],
+    ];
+    archive.insert_tweets(&[follow_up, question, root]);
+    archive.insert_user(&user(2, "Bob", "bob"));
+    archive.insert_user_info(&user(1, "Alice", "alice"), "alice");
+    archive.insert_user_conversations(&conversations, "alice");
+
+    let vault = tempfile::tempdir().expect("Failed to create a directory for the vault");
+    let exported = app::export::markdown::export(&archive, "alice", vault.path()).await;
+    assert_eq!(exported.conversations, 2);
+    assert_eq!(exported.users, 2);
+    let note = |name: &str| {
+        std::fs::read_to_string(vault.path().join(name)).expect("The note was written")
+    };
+    let thread = note("conversations/conversation-103.md");
+    //oldest first, from the question to the follow up
+    let position = |text: &str| thread.find(text).expect("The tweet is in the note");
+    assert!(position("Does smoking") < position("Is it a confounder"));
+    assert!(position("Is it a confounder") < position("Or a collider"));
+    assert!(
+        thread.contains("tweet_ids: [\"200\",\"102\",\"103\"]"),
+        "{thread}"
+    );
+    //named after its newest tweet, while the conversation is the root's
+    assert!(thread.contains("newest_tweet_id: \"103\""), "{thread}");
+    assert!(thread.contains("conversation_id: \"200\""), "{thread}");
+    assert!(thread.contains("started: 2022-02-28T10:00:00Z"), "{thread}");
+    assert!(thread.contains("ended: 2022-03-01T11:05:00Z"), "{thread}");
+    //the two branches of bob's thread link to each other
+    assert!(thread.contains("[[conversation-102]]"), "{thread}");
+    assert!(note("conversations/conversation-102.md").contains("[[conversation-103]]"));
+    assert!(note("users/bob.md").contains("[[conversation-103]]"));
+}
+
+//an inbox request signed the way Mastodon signs them
+fn signed_inbox_request(
+    key: &rsa::RsaPrivateKey,
+    key_id: &str,
+    body: &str,
+) -> activitypub::InboxRequest {
+    use base64::Engine;
+    use rsa::signature::{SignatureEncoding, Signer};
+    use sha2::Digest;
+    let base64 = base64::engine::general_purpose::STANDARD;
+    let target = "post /users/alice/inbox".to_string();
+    let mut headers = std::collections::BTreeMap::from([
+        ("host".to_string(), "archive.example".to_string()),
+        (
+            "date".to_string(),
+            httpdate::fmt_http_date(std::time::SystemTime::now()),
+        ),
+        (
+            "digest".to_string(),
+            format!("SHA-256={}", base64.encode(sha2::Sha256::digest(body))),
+        ),
+    ]);
+    let signed = format!(
+        "(request-target): {target}\nhost: {}\ndate: {}\ndigest: {}",
+        headers["host"], headers["date"], headers["digest"]
+    );
+    let signature =
+        rsa::pkcs1v15::SigningKey::<sha2::Sha256>::new(key.clone()).sign(signed.as_bytes());
+    headers.insert(
+        "signature".to_string(),
+        format!(
+            "keyId=\"{key_id}\",algorithm=\"rsa-sha256\",headers=\"(request-target) host date digest\",signature=\"{}\"",
+            base64.encode(signature.to_bytes())
+        ),
+    );
+    activitypub::InboxRequest { target, headers }
+}
+
+#[test]
+fn inbox_requests_have_to_be_signed_by_the_key() {
+    use rsa::pkcs8::{EncodePublicKey, LineEnding};
+    let public_key_pem = |key: &rsa::RsaPrivateKey| {
+        key.to_public_key()
+            .to_public_key_pem(LineEnding::LF)
+            .expect("Failed to encode a public key")
+    };
+    let mut rng = rand::thread_rng();
+    let key = rsa::RsaPrivateKey::new(&mut rng, 1024).expect("Failed to generate a key");
+    let other_key = rsa::RsaPrivateKey::new(&mut rng, 1024).expect("Failed to generate a key");
+    let body = r#"{"type": "Follow", "actor": "https://social.example/users/zoe"}"#;
+    let request = signed_inbox_request(&key, "https://social.example/users/zoe#main-key", body);
+    assert_eq!(
+        activitypub::verify_signature(&request, body, &public_key_pem(&key)),
+        Ok("https://social.example/users/zoe#main-key".to_string())
+    );
+
+    //someone else's key, another body, another target or no date at all
+    assert!(activitypub::verify_signature(&request, body, &public_key_pem(&other_key)).is_err());
+    assert!(activitypub::verify_signature(&request, "{}", &public_key_pem(&key)).is_err());
+    let mut retargeted = request.clone();
+    retargeted.target = "post /users/bob/inbox".to_string();
+    assert!(activitypub::verify_signature(&retargeted, body, &public_key_pem(&key)).is_err());
+    let mut undated = request.clone();
+    undated.headers.remove("date");
+    assert!(activitypub::verify_signature(&undated, body, &public_key_pem(&key)).is_err());
+    let mut unsigned = request;
+    unsigned.headers.remove("signature");
+    assert!(activitypub::verify_signature(&unsigned, body, &public_key_pem(&key)).is_err());
+}
+
+#[tokio::test]
+async fn accepts_follows_from_a_local_inbox_and_forgets_them_on_undo() {
+    use rsa::pkcs8::{EncodePublicKey, LineEnding};
+    let mock = mock();
+    allow_private_activitypub_addresses();
+    let mut rng = rand::thread_rng();
+    let key = rsa::RsaPrivateKey::new(&mut rng, 1024).expect("Failed to generate a key");
+    let other_key = rsa::RsaPrivateKey::new(&mut rng, 1024).expect("Failed to generate a key");
+    let public_key_pem = key
+        .to_public_key()
+        .to_public_key_pem(LineEnding::LF)
+        .expect("Failed to encode a public key");
+    mock.fixtures()
+        .actors
+        .insert("zoe".to_string(), public_key_pem);
+    let (_directory, archive) = archive();
+    archive.insert_user_info(&user(1, "Alice", "alice"), "alice");
+    let site = Site {
+        public_url: "https://archive.example".to_string(),
+    };
+    let followers = |archive: &Archive| {
+        activitypub::followers(archive, &site, "alice")
+            .map(|followers| followers["totalItems"].clone())
+    };
+    let zoe = mock_url("/actors/zoe");
+    let key_id = format!("{zoe}#main-key");
+    let follow = json!({
+        "id": format!("{zoe}#follows/1"),
+        "type": "Follow",
+        "actor": zoe,
+        "object": "https://archive.example/users/alice",
+    });
+    let body = follow.to_string();
+
+    //signed by a key that isn't zoe's
+    let forged = signed_inbox_request(&other_key, &key_id, &body);
+    assert!(
+        activitypub::receive(&archive, &site, "alice", &forged, &body)
+            .await
+            .is_err()
+    );
+    assert_eq!(followers(&archive), Some(json!(0)));
+    assert!(mock.posts("/actors/zoe/inbox").is_empty());
+
+    let request = signed_inbox_request(&key, &key_id, &body);
+    assert_eq!(
+        activitypub::receive(&archive, &site, "alice", &request, &body).await,
+        Ok(())
+    );
+    assert_eq!(followers(&archive), Some(json!(1)));
+    //zoe's inbox was sent an Accept, signed with the key alice's actor publishes
+    let accepts = mock.posts("/actors/zoe/inbox");
+    assert_eq!(accepts.len(), 1);
+    let accept: serde_json::Value =
+        serde_json::from_str(&accepts[0].body).expect("The Accept is JSON");
+    assert_eq!(accept["type"], "Accept");
+    //the archive's name is in every url of its actors
+    let alice = "https://archive.example/users/alice?archive=test";
+    assert_eq!(accept["actor"], alice);
+    assert_eq!(accept["object"], follow);
+    let actor = activitypub::actor(&archive, &site, "alice")
+        .await
+        .expect("Alice's key is there")
+        .expect("Alice is archived");
+    let accept_request = activitypub::InboxRequest {
+        target: "post /actors/zoe/inbox".to_string(),
+        headers: accepts[0].headers.clone().into_iter().collect(),
+    };
+    assert_eq!(
+        activitypub::verify_signature(
+            &accept_request,
+            &accepts[0].body,
+            actor["publicKey"]["publicKeyPem"]
+                .as_str()
+                .expect("Alice has a public key")
+        ),
+        Ok(format!("{alice}#main-key"))
+    );
+
+    let undo = json!({
+        "id": format!("{zoe}#follows/1/undo"),
+        "type": "Undo",
+        "actor": zoe,
+        "object": follow,
+    })
+    .to_string();
+    let request = signed_inbox_request(&key, &key_id, &undo);
+    assert_eq!(
+        activitypub::receive(&archive, &site, "alice", &request, &undo).await,
+        Ok(())
+    );
+    assert_eq!(followers(&archive), Some(json!(0)));
+    assert_eq!(mock.posts("/actors/zoe/inbox").len(), 1);
+}
+
+#[test]
+fn only_public_addresses_are_requested_for_activities() {
+    for address in ["93.184.216.34", "2606:2800:220:1::1"] {
+        let address = address.parse().expect("A valid address");
+        assert!(activitypub::is_public_address(address), "{address}");
+    }
+    for address in [
+        "127.0.0.1",
+        "10.1.2.3",
+        "172.16.0.1",
+        "192.168.1.1",
+        "169.254.169.254",
+        "100.64.0.1",
+        "0.0.0.0",
+        "::1",
+        "fd00::1",
+        "fe80::1",
+        "::ffff:127.0.0.1",
+    ] {
+        let address = address.parse().expect("A valid address");
+        assert!(!activitypub::is_public_address(address), "{address}");
+    }
+}
+
+#[test]
+fn parses_sync_intervals() {
+    use crate::app::watchlist::parse_interval;
+    let hours = |hours: u64| Ok(Duration::from_secs(hours * 60 * 60));
+    assert_eq!(parse_interval("30m"), Ok(Duration::from_secs(30 * 60)));
+    assert_eq!(parse_interval("6h"), hours(6));
+    assert_eq!(parse_interval("2d"), hours(48));
+    assert_eq!(parse_interval("1w"), hours(7 * 24));
+    assert_eq!(parse_interval("@hourly"), hours(1));
+    assert_eq!(parse_interval("@daily"), hours(24));
+    assert_eq!(parse_interval("@weekly"), hours(7 * 24));
+    assert_eq!(parse_interval("52w"), hours(52 * 7 * 24));
+    for invalid in ["", "6", "h", "6 h", "-6h", "6y", "@monthly", "1.5h"] {
+        assert!(parse_interval(invalid).is_err(), "{invalid}");
+    }
+    //too often, too rarely and more seconds than there are
+    for out_of_range in ["14m", "0h", "53w", "1000000w", "18446744073709551615w"] {
+        assert!(parse_interval(out_of_range).is_err(), "{out_of_range}");
+    }
+}
+
+#[tokio::test]
+async fn refuses_to_watch_users_on_intervals_too_long_to_schedule() {
+    let client = client().await;
+    let (status, _, error) = post(
+        &client,
+        "/watchlist?archive=other",
+        r#"{"twitter_handle": "yan", "interval": "1000000w"}"#,
+    )
+    .await;
+    assert_eq!(status, Status::BadRequest);
+    assert!(error.contains("too long"), "{error}");
+    //and the watchlist still works
+    assert_eq!(get(&client, "/watchlist?archive=other").await.0, Status::Ok);
+}